@@ -1,13 +1,45 @@
+use chrono::{DateTime, Duration, Utc};
 use std::{future::Future, time::Instant};
 
-use poise::CreateReply;
-use serenity::all::{ChannelId, Color, CreateEmbed, CreateEmbedFooter, UserId};
+use poise::{ChoiceParameter, CreateReply};
+use serenity::all::{
+    Attachment, ButtonStyle, CacheHttp, ChannelId, Color, CreateAttachment, CreateEmbed,
+    CreateEmbedFooter, Member, UserId,
+};
 
 use crate::{
-    constants::{CONGRATULATE_NEW_ROLE_CHANNEL_IDS, LEADERBOARD_PAGE_SIZE, LOG_ENTRY_PAGE_SIZE},
-    repository::{CharacterStatisticsRepository, SQLiteCharacterStatisticsRepository},
-    roles::{Roles, UserRoles},
-    utils::format_with_commas,
+    constants::{
+        ADMIN_LIST_SIZE, AUDIT_PAGE_SIZE, CLI_ACTOR_ID, CONGRATULATE_NEW_ROLE_CHANNEL_IDS,
+        EMBED_FIELD_LIMIT, IMPORT_PREVIEW_SIZE, LATE_QUIZ_ATTEMPT_WINDOW, LATE_QUIZ_COOLDOWN,
+        LATE_QUIZ_MAX_ATTEMPTS, LEADERBOARD_PAGE_SIZE, MAX_ATTACHMENT_SIZE, MODERATION_RECENT_DAYS,
+        NAME_HISTORY_PAGE_SIZE, QUIZ_PASS_PAGE_SIZE, QUIZ_REQUIREMENTS,
+    },
+    counting::{breakdown, count_characters, CharacterClass, COUNTING_RULES, DEFAULT_RULES},
+    extract::{extract_text, FileKind},
+    import::{
+        export_history, parse_history, parse_mokuro_volumes, parse_texthooker_session,
+        parse_ttu_statistics, HistoryFormat, ImportEntry, ImportPlan, FILE_SOURCE, MOKURO_SOURCE,
+        TEXTHOOKER_SOURCE, TTU_SOURCE,
+    },
+    integrity::{format_mismatch, recompute_totals},
+    model::{
+        AuditAction, AuditEntry, CharacterStatistics, Data, DefaultMediaType, MediaType,
+        PendingLogEntry, QuizStatistics, UserSettings,
+    },
+    moderation::{moderation_channel, moderation_reason, send_to_moderation},
+    reminders::{Reminder, ReminderFrequency},
+    repository::{
+        AuditRepository, CharacterStatisticsRepository, ImportRepository, ModerationRepository,
+        NameHistoryRepository, QuizAttemptRepository, ReminderRepository, SQLiteAuditRepository,
+        SQLiteCharacterStatisticsRepository, SQLiteImportRepository, SQLiteModerationRepository,
+        SQLiteNameHistoryRepository, SQLiteQuizAttemptRepository, SQLiteReminderRepository,
+        SQLiteUserSettingsRepository, UserSettingsRepository,
+    },
+    roles::{QuizRoles, Roles, UserRoles},
+    scheduler::{parse_time, Job, JobContext},
+    service::{self, is_promotion, leaderboard_page_of_rank, LogResult, RoleProgress},
+    timezone::{timezone_names, Timezone},
+    utils::{check_entry_characters, format_duration, format_with_commas, truncate_chars},
     Context, Error,
 };
 
@@ -31,182 +63,1602 @@ pub async fn help(
     Ok(())
 }
 
-/// Logs immersion characters.
-///
-/// Optionally, add a note to keep track of read materials, i.e: `/log_characters characters:4000 notes:Episode 1 of Love Live season 1`
+/// Logs immersion characters.
+///
+/// Optionally, add a note to keep track of read materials, i.e: `/log_characters characters:4000 notes:Episode 1 of Love Live season 1`
+/// Instead of the amount of characters, you can attach a .txt, .srt, .ass, .vtt or .epub file to count its characters.
+#[poise::command(slash_command)]
+pub async fn log_characters(
+    ctx: Context<'_>,
+    #[description = "The amount of characters read"] characters: Option<i64>,
+    #[description = "Extra information such as the title of the book or VN"] notes: Option<String>,
+    #[description = "A text, subtitle or epub file to count the characters of instead"]
+    attachment: Option<Attachment>,
+    #[description = "What you read, your default from /settings me otherwise"] media_type: Option<
+        MediaType,
+    >,
+) -> Result<(), Error> {
+    let characters = match (characters, attachment) {
+        (Some(characters), None) => characters,
+        (None, Some(attachment)) => {
+            // downloading and reading the file can take longer than discord waits for a reply
+            ctx.defer().await?;
+            match read_attachment_text(ctx, &attachment).await {
+                Ok(text) => count_characters(&text),
+                Err(error) => {
+                    let embed = create_base_embed().description(error.to_string());
+                    ctx.send(CreateReply::default().embed(embed).ephemeral(true))
+                        .await?;
+                    return Ok(());
+                }
+            }
+        }
+        _ => {
+            let embed = create_base_embed()
+                .description("Provide either the amount of characters or an attachment.");
+            ctx.send(CreateReply::default().embed(embed).ephemeral(true))
+                .await?;
+            return Ok(());
+        }
+    };
+
+    log_characters_for_author(ctx, characters, notes, media_type).await
+}
+
+/// Logs characters for the user who invoked the command and replies with their new total, rank and role progress
+async fn log_characters_for_author(
+    ctx: Context<'_>,
+    characters: i64,
+    notes: Option<String>,
+    media_type: Option<MediaType>,
+) -> Result<(), Error> {
+    if let Err(error) = check_entry_characters(characters) {
+        let embed = create_base_embed().description(error.to_string());
+        ctx.send(CreateReply::default().embed(embed).ephemeral(true))
+            .await?;
+        return Ok(());
+    }
+
+    let user_id = ctx.author().id.get();
+    let name = ctx.author().display_name().to_owned();
+    let outcome = log_characters_for_user(
+        ctx,
+        ctx.data(),
+        user_id,
+        name,
+        characters,
+        notes,
+        media_type,
+    )
+    .await?;
+    let result = match outcome {
+        LogOutcome::Pending => {
+            let embed = create_base_embed().description(format!(
+                "Your log of {} characters is waiting for a moderator's approval. It will count towards your total once approved.",
+                format_with_commas(characters)
+            ));
+            ctx.send(CreateReply::default().embed(embed).ephemeral(true))
+                .await?;
+            return Ok(());
+        }
+        LogOutcome::Logged(result) => result,
+    };
+
+    let member = ctx.author_member().await.unwrap().into_owned();
+    reply_with_log_result(ctx, &member, characters, &result).await
+}
+
+/// What happened to a log
+pub enum LogOutcome {
+    /// it waits for a moderator's approval
+    Pending,
+    Logged(LogResult),
+}
+
+/// What the transaction of log_characters_for_user did, the moderation message is sent after it
+enum LogWrite {
+    Pending(ChannelId, PendingLogEntry, String),
+    Logged(LogResult),
+}
+
+/// Logs characters for a user, large logs are sent to the moderation channel instead.
+/// Shared by /log_characters and the Log now button of reminders.
+/// Without a media type, the user's default from their settings is used.
+pub async fn log_characters_for_user(
+    cache_http: impl CacheHttp,
+    data: &Data,
+    user_id: u64,
+    name: String,
+    characters: i64,
+    notes: Option<String>,
+    media_type: Option<MediaType>,
+) -> Result<LogOutcome, Error> {
+    // large logs wait for a moderator's approval and don't count until then.
+    // the check and the log share one transaction, so no other log can slip in between them
+    let moderation_channel = moderation_channel();
+    let time = data.clock.timestamp();
+    let logged = data
+        .database
+        .write(move |connection| {
+            let tx = connection.transaction()?;
+            let media_type = match media_type {
+                Some(media_type) => Some(media_type),
+                None => {
+                    SQLiteUserSettingsRepository::new(&tx)
+                        .get_settings(user_id)?
+                        .default_media_type
+                }
+            };
+            let mut repository = SQLiteCharacterStatisticsRepository::new(&tx);
+
+            if let Some(channel_id) = moderation_channel {
+                let since = *time - Duration::days(MODERATION_RECENT_DAYS);
+                let (recent_entries, recent_average) =
+                    repository.get_log_average_since(user_id, &since)?;
+                if let Some(reason) = moderation_reason(characters, recent_entries, recent_average)
+                {
+                    let mut moderation_repository = SQLiteModerationRepository::new(&tx);
+                    let entry = moderation_repository.add_pending_log_entry(
+                        user_id, &name, characters, &time, notes, media_type,
+                    )?;
+                    tx.commit()?;
+                    return Ok(LogWrite::Pending(channel_id, entry, reason));
+                }
+            }
+
+            let result =
+                service::log_characters(&mut repository, user_id, &name, characters, &time, notes)?;
+            if let (Some(log_entry_id), Some(media_type)) = (result.log_entry_id, media_type) {
+                repository.set_media_type(log_entry_id, media_type)?;
+            }
+            tx.commit()?;
+            Ok(LogWrite::Logged(result))
+        })
+        .await?;
+
+    match logged {
+        LogWrite::Logged(result) => Ok(LogOutcome::Logged(result)),
+        LogWrite::Pending(channel_id, entry, reason) => {
+            send_to_moderation(cache_http, channel_id, &entry, &reason).await?;
+            Ok(LogOutcome::Pending)
+        }
+    }
+}
+
+/// Returns the settings of a user, the defaults if they never changed them
+pub async fn get_user_settings(data: &Data, user_id: u64) -> Result<UserSettings, Error> {
+    data.database
+        .read(move |connection| {
+            let tx = connection.transaction()?;
+            let settings = SQLiteUserSettingsRepository::new(&tx).get_settings(user_id)?;
+            tx.commit()?;
+            Ok(settings)
+        })
+        .await
+}
+
+/// The timezone of the author for commands that store dates, tells them and returns None if it can't be loaded
+async fn author_timezone(ctx: Context<'_>) -> Result<Option<Timezone>, Error> {
+    let settings = get_user_settings(ctx.data(), ctx.author().id.get()).await?;
+    match settings.timezone() {
+        Ok(timezone) => Ok(Some(timezone)),
+        Err(error) => {
+            let embed = create_base_embed().description(format!(
+                "Nothing was imported. {} Pick it again with /settings me.",
+                error
+            ));
+            ctx.send(CreateReply::default().embed(embed).ephemeral(true))
+                .await?;
+            Ok(None)
+        }
+    }
+}
+
+/// The timezone to show dates in, UTC with a notice to show along if it can't be loaded
+fn timezone_or_utc(settings: &UserSettings) -> (Timezone, Option<String>) {
+    match settings.timezone() {
+        Ok(timezone) => (timezone, None),
+        Err(error) => {
+            println!("Using UTC for {}: {}", settings.user_id, error);
+            let notice = format!("{} The dates are shown in UTC.", error);
+            (Timezone::utc(), Some(notice))
+        }
+    }
+}
+
+/// Downloads an attachment, the errors are meant to be shown to the user
+async fn download_attachment(ctx: Context<'_>, attachment: &Attachment) -> Result<Vec<u8>, Error> {
+    if attachment.size > MAX_ATTACHMENT_SIZE {
+        return Err("The file is too large.".into());
+    }
+
+    let response = ctx.data().http_client.get(&attachment.url).send().await?;
+    if !response.status().is_success() {
+        return Err("Failed to download the file, try again later.".into());
+    }
+    Ok(response.bytes().await?.to_vec())
+}
+
+/// Downloads an attachment and returns its readable text
+async fn read_attachment_text(ctx: Context<'_>, attachment: &Attachment) -> Result<String, Error> {
+    let kind = FileKind::from_file_name(&attachment.filename)
+        .ok_or("Only .txt, .srt, .ass, .vtt and .epub files are supported.")?;
+    let bytes = download_attachment(ctx, attachment).await?;
+    extract_text(kind, &bytes)
+}
+
+/// Updates the roles of the command author after their total changed, and congratulates them on a higher role
+async fn sync_author_roles(ctx: Context<'_>, data: &CharacterStatistics) -> Result<(), Error> {
+    let user = ctx.author_member().await.unwrap().into_owned();
+    update_roles_and_congratulate(ctx, &user, data).await?;
+    Ok(())
+}
+
+/// Updates the roles of a member after their total changed and congratulates them on a higher role, returns the roles they had before
+async fn update_roles_and_congratulate(
+    ctx: Context<'_>,
+    member: &Member,
+    data: &CharacterStatistics,
+) -> Result<UserRoles, Error> {
+    let guild = ctx.guild().unwrap().to_owned();
+    let roles = UserRoles::new(&member.roles, &guild.roles);
+    let new_role = roles.update_role(ctx, &guild, member, data).await?;
+    if let Some(new_role) = new_role {
+        if is_promotion(new_role, &roles.roles) {
+            let congrats_msg = format!(
+                "Congratulations {} for obtaining role: {}",
+                member.user.display_name(),
+                new_role
+            );
+            ctx.say(&congrats_msg).await?;
+            for channel_id in CONGRATULATE_NEW_ROLE_CHANNEL_IDS {
+                ChannelId::new(channel_id).say(ctx, &congrats_msg).await?;
+            }
+        }
+    }
+    Ok(roles)
+}
+
+pub fn format_role_progress(progress: &RoleProgress) -> String {
+    let current_role_message = match progress.current_role {
+        Some(role) => format!("Current role is {}", role),
+        None => "You currently don't have a role".to_owned(),
+    };
+
+    let next_role_message = match (&progress.next_requirement, progress.missing_characters()) {
+        (None, _) => "You already have the highest role.".to_owned(),
+        (Some(requirement), Some(missing)) => format!(
+            "For {} you need {} more characters.",
+            requirement.role,
+            format_with_commas(missing)
+        ),
+        (Some(requirement), None) => format!(
+            "For {} you need to pass {}.",
+            requirement.role,
+            progress.missing_quiz().unwrap()
+        ),
+    };
+
+    format!("{}. {}", current_role_message, next_role_message)
+}
+
+/// Updates the member's roles and replies with their new total, rank and role progress
+async fn reply_with_log_result(
+    ctx: Context<'_>,
+    member: &Member,
+    characters: i64,
+    result: &LogResult,
+) -> Result<(), Error> {
+    let roles = update_roles_and_congratulate(ctx, member, &result.statistics).await?;
+    let progress = RoleProgress::new(&roles.quizzes, result.statistics.total_characters);
+    let rank_message = match result.rank {
+        Some(rank) => format!("You are currently rank {} on the leaderboard", rank),
+        None => "You are currently not on the leaderboard".to_owned(),
+    };
+
+    let embed = create_base_embed()
+        .title(format!(
+            "{} logged {} characters!",
+            member.user.display_name(),
+            format_with_commas(characters),
+        ))
+        .description(format!(
+            "Total characters logged: {}",
+            format_with_commas(result.statistics.total_characters)
+        ))
+        .field(rank_message, format_role_progress(&progress), false);
+
+    ctx.send(CreateReply::default().embed(embed)).await?;
+    Ok(())
+}
+
+/// Gives a member the role matching their statistics, without congratulating them. Members who left are skipped.
+async fn sync_member_roles(
+    ctx: Context<'_>,
+    user_id: UserId,
+    data: &CharacterStatistics,
+) -> Result<(), Error> {
+    let guild = ctx.guild().unwrap().to_owned();
+    let Ok(member) = guild.member(ctx, user_id).await else {
+        return Ok(());
+    };
+    let roles = UserRoles::new(&member.roles, &guild.roles);
+    roles.update_role(ctx, &guild, &member, data).await?;
+    Ok(())
+}
+
+/// Imports reading statistics from other tools.
+#[poise::command(
+    slash_command,
+    subcommands("ttu", "texthooker", "mokuro", "file"),
+    subcommand_required
+)]
+pub async fn import(_: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Imports the statistics exported from ttu reader.
+///
+/// Export them from the statistics page of ttu reader. Days that were already imported are skipped.
+#[poise::command(slash_command)]
+pub async fn ttu(
+    ctx: Context<'_>,
+    #[description = "The statistics .json file exported from ttu reader"] attachment: Attachment,
+) -> Result<(), Error> {
+    ctx.defer().await?;
+    let Some(timezone) = author_timezone(ctx).await? else {
+        return Ok(());
+    };
+    let entries = match download_attachment(ctx, &attachment).await {
+        Ok(bytes) => parse_ttu_statistics(&bytes, &timezone),
+        Err(error) => Err(error),
+    };
+
+    match entries {
+        Ok(entries) => confirm_import(ctx, TTU_SOURCE, entries).await,
+        Err(error) => {
+            let embed = create_base_embed().description(error.to_string());
+            ctx.send(CreateReply::default().embed(embed).ephemeral(true))
+                .await?;
+            Ok(())
+        }
+    }
+}
+
+/// Imports a visual novel session saved from texthooker-ui.
+///
+/// The characters are counted with the same rules as /count. Lines that were already imported are skipped, so a session can be saved and imported again as you keep reading.
+#[poise::command(slash_command)]
+pub async fn texthooker(
+    ctx: Context<'_>,
+    #[description = "The .txt or .json session saved from texthooker-ui"] attachment: Attachment,
+    #[description = "The title of the game"] title: String,
+) -> Result<(), Error> {
+    ctx.defer().await?;
+    let entry = match download_attachment(ctx, &attachment).await {
+        Ok(bytes) => {
+            parse_texthooker_session(&attachment.filename, &bytes, &title, ctx.data().clock.now())
+        }
+        Err(error) => Err(error),
+    };
+
+    match entry {
+        Ok(entry) => confirm_import(ctx, TEXTHOOKER_SOURCE, vec![entry]).await,
+        Err(error) => {
+            let embed = create_base_embed().description(error.to_string());
+            ctx.send(CreateReply::default().embed(embed).ephemeral(true))
+                .await?;
+            Ok(())
+        }
+    }
+}
+
+/// Imports manga progress from mokuro reader.
+///
+/// Only the characters read since your last mokuro import are logged, per volume.
+#[poise::command(slash_command)]
+pub async fn mokuro(
+    ctx: Context<'_>,
+    #[description = "The volume data .json file exported from mokuro reader"]
+    attachment: Attachment,
+) -> Result<(), Error> {
+    ctx.defer().await?;
+    let entries = match download_attachment(ctx, &attachment).await {
+        Ok(bytes) => parse_mokuro_volumes(&bytes, ctx.data().clock.now()),
+        Err(error) => Err(error),
+    };
+
+    match entries {
+        Ok(entries) => confirm_import(ctx, MOKURO_SOURCE, entries).await,
+        Err(error) => {
+            let embed = create_base_embed().description(error.to_string());
+            ctx.send(CreateReply::default().embed(embed).ephemeral(true))
+                .await?;
+            Ok(())
+        }
+    }
+}
+
+/// Imports logs from a .csv or .json file, i.e. from /export, a spreadsheet or another bot.
+///
+/// The file needs time, characters and notes columns (or fields). Nothing is imported if any row is invalid.
+/// Rows that are already in your log history are skipped, so importing your own export changes nothing.
+#[poise::command(slash_command)]
+pub async fn file(
+    ctx: Context<'_>,
+    #[description = "The .csv or .json file with your logs"] attachment: Attachment,
+) -> Result<(), Error> {
+    ctx.defer().await?;
+    let Some(format) = HistoryFormat::from_file_name(&attachment.filename) else {
+        let embed = create_base_embed().description("Only .csv and .json files are supported.");
+        ctx.send(CreateReply::default().embed(embed).ephemeral(true))
+            .await?;
+        return Ok(());
+    };
+
+    let Some(timezone) = author_timezone(ctx).await? else {
+        return Ok(());
+    };
+    let entries = match download_attachment(ctx, &attachment).await {
+        Ok(bytes) => parse_history(&bytes, format, &timezone),
+        Err(error) => Err(error),
+    };
+
+    match entries {
+        Ok(Ok(entries)) => confirm_import(ctx, FILE_SOURCE, entries).await,
+        Ok(Err(row_errors)) => {
+            let mut lines = row_errors
+                .iter()
+                .take(IMPORT_PREVIEW_SIZE)
+                .cloned()
+                .collect::<Vec<String>>()
+                .join("\n");
+            if row_errors.len() > IMPORT_PREVIEW_SIZE {
+                lines += &format!("\n...and {} more", row_errors.len() - IMPORT_PREVIEW_SIZE);
+            }
+            let embed = create_base_embed()
+                .title(format!(
+                    "{} invalid rows, nothing was imported",
+                    row_errors.len()
+                ))
+                .description(lines);
+            ctx.send(CreateReply::default().embed(embed).ephemeral(true))
+                .await?;
+            Ok(())
+        }
+        Err(error) => {
+            let embed = create_base_embed().description(error.to_string());
+            ctx.send(CreateReply::default().embed(embed).ephemeral(true))
+                .await?;
+            Ok(())
+        }
+    }
+}
+
+/// Sends your full log history as a file.
+///
+/// The file can be imported back with /import file.
+#[poise::command(slash_command)]
+pub async fn export(
+    ctx: Context<'_>,
+    #[description = "The file format, defaults to csv"] format: Option<HistoryFormat>,
+) -> Result<(), Error> {
+    let format = format.unwrap_or(HistoryFormat::Csv);
+    let user_id = ctx.author().id.get();
+    let entries = ctx
+        .data()
+        .database
+        .read(move |connection| {
+            let tx = connection.transaction()?;
+            let mut repository = SQLiteCharacterStatisticsRepository::new(&tx);
+            repository.get_all_log_entries(user_id)
+        })
+        .await?;
+
+    if entries.is_empty() {
+        let embed = create_base_embed().description("You haven't made any logs.");
+        ctx.send(CreateReply::default().embed(embed).ephemeral(true))
+            .await?;
+        return Ok(());
+    }
+
+    let bytes = export_history(&entries, format)?;
+    let attachment = CreateAttachment::bytes(bytes, format!("history.{}", format.extension()));
+    let embed = create_base_embed().description(format!(
+        "Your log history, {} entries.",
+        format_with_commas(entries.len() as i64)
+    ));
+    ctx.send(
+        CreateReply::default()
+            .embed(embed)
+            .attachment(attachment)
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Compares the entries with what was imported before, history files also skip the rows that are already logged
+fn plan_import(
+    tx: &rusqlite::Transaction,
+    user_id: u64,
+    source: &str,
+    entries: Vec<ImportEntry>,
+) -> Result<ImportPlan, Error> {
+    let mut plan = ImportPlan::new(&SQLiteImportRepository::new(tx), user_id, source, entries)?;
+    if source == FILE_SOURCE {
+        let logged = SQLiteCharacterStatisticsRepository::new(tx).get_all_log_entries(user_id)?;
+        plan.skip_logged_entries(&logged);
+    }
+    Ok(plan)
+}
+
+/// Shows a preview of what an import would log, and logs it once the author confirms
+async fn confirm_import(
+    ctx: Context<'_>,
+    source: &'static str,
+    entries: Vec<ImportEntry>,
+) -> Result<(), Error> {
+    let Some(timezone) = author_timezone(ctx).await? else {
+        return Ok(());
+    };
+    let user_id = ctx.author().id.get();
+    let plan = ctx
+        .data()
+        .database
+        .read(move |connection| {
+            let tx = connection.transaction()?;
+            plan_import(&tx, user_id, source, entries)
+        })
+        .await?;
+
+    for (entry, characters) in plan.entries.iter() {
+        if let Err(error) = check_entry_characters(*characters) {
+            let embed = create_base_embed().description(format!(
+                "Nothing was imported, the entry of {} is too large. {}",
+                timezone.to_local(entry.time).format("%Y年%m月%d日"),
+                error
+            ));
+            ctx.send(CreateReply::default().embed(embed).ephemeral(true))
+                .await?;
+            return Ok(());
+        }
+    }
+
+    if plan.entries.is_empty() {
+        let embed = create_base_embed().description(format!(
+            "There is nothing new to import, {} entries were already imported before.",
+            plan.skipped
+        ));
+        ctx.send(CreateReply::default().embed(embed).ephemeral(true))
+            .await?;
+        return Ok(());
+    }
+
+    let mut lines = "".to_owned();
+    for (entry, characters) in plan.entries.iter().take(IMPORT_PREVIEW_SIZE) {
+        lines += &format!(
+            "{}: {} characters | {}\n",
+            timezone.to_local(entry.time).format("%Y年%m月%d日"),
+            format_with_commas(*characters),
+            entry.notes
+        );
+    }
+    if plan.entries.len() > IMPORT_PREVIEW_SIZE {
+        lines += &format!("...and {} more\n", plan.entries.len() - IMPORT_PREVIEW_SIZE);
+    }
+
+    let embed = create_base_embed()
+        .title(format!(
+            "Import {} characters from {}?",
+            format_with_commas(plan.total_characters()),
+            source
+        ))
+        .description(format!(
+            "{} new entries will be logged, {} entries were already imported and will be skipped.",
+            plan.entries.len(),
+            plan.skipped
+        ))
+        // the notes come from the file, so the preview can be longer than discord allows
+        .field("Preview", truncate_chars(&lines, EMBED_FIELD_LIMIT), false);
+
+    let ctx_id = ctx.id();
+    let confirm_button_id = format!("{}confirm", ctx_id);
+    let cancel_button_id = format!("{}cancel", ctx_id);
+    let components = serenity::builder::CreateActionRow::Buttons(vec![
+        serenity::builder::CreateButton::new(&confirm_button_id)
+            .label("Import")
+            .style(ButtonStyle::Success),
+        serenity::builder::CreateButton::new(&cancel_button_id)
+            .label("Cancel")
+            .style(ButtonStyle::Secondary),
+    ]);
+    ctx.send(
+        CreateReply::default()
+            .embed(embed)
+            .components(vec![components]),
+    )
+    .await?;
+
+    let author_id = ctx.author().id;
+    let press = serenity::collector::ComponentInteractionCollector::new(ctx)
+        .filter(move |press| press.data.custom_id.starts_with(&ctx_id.to_string()))
+        .author_id(author_id)
+        .timeout(std::time::Duration::from_secs(600))
+        .await;
+
+    let Some(press) = press else {
+        return Ok(());
+    };
+
+    if press.data.custom_id != confirm_button_id {
+        press
+            .create_response(
+                ctx.serenity_context(),
+                serenity::builder::CreateInteractionResponse::UpdateMessage(
+                    serenity::builder::CreateInteractionResponseMessage::new()
+                        .embed(create_base_embed().description("Import cancelled."))
+                        .components(vec![]),
+                ),
+            )
+            .await?;
+        return Ok(());
+    }
+
+    let name = ctx.author().display_name().to_owned();
+    let time = ctx.data().clock.timestamp();
+    let channel_id = moderation_channel();
+    let (data, logged, pending, unmoderated) = ctx
+        .data()
+        .database
+        .write(move |connection| {
+            let tx = connection.transaction()?;
+            // the plan is checked again in case the same export was imported in the meantime
+            let entries = plan.entries.into_iter().map(|(entry, _)| entry).collect();
+            let plan = plan_import(&tx, user_id, source, entries)?;
+
+            let mut import_repository = SQLiteImportRepository::new(&tx);
+            let mut repository = SQLiteCharacterStatisticsRepository::new(&tx);
+            let mut moderation_repository = SQLiteModerationRepository::new(&tx);
+            let since = *time - Duration::days(MODERATION_RECENT_DAYS);
+            let (recent_entries, recent_average) =
+                repository.get_log_average_since(user_id, &since)?;
+
+            let mut data = repository.get_or_initialize_statistics(user_id, &name)?;
+            let mut logged = 0;
+            let mut pending = vec![];
+            let mut unmoderated = 0;
+            for (entry, characters) in plan.entries.iter() {
+                let notes = Some(entry.notes.to_owned()).filter(|notes| !notes.is_empty());
+                // large entries wait for a moderator's approval like the ones of /log
+                match (
+                    moderation_reason(*characters, recent_entries, recent_average),
+                    channel_id,
+                ) {
+                    (Some(reason), Some(_)) => {
+                        let pending_entry = moderation_repository.add_pending_log_entry(
+                            user_id,
+                            &name,
+                            *characters,
+                            &entry.time,
+                            notes,
+                            None,
+                        )?;
+                        pending.push((pending_entry, reason));
+                    }
+                    (reason, _) => {
+                        if reason.is_some() {
+                            unmoderated += 1;
+                        }
+                        data = repository.add_log_entry(
+                            user_id,
+                            &name,
+                            *characters,
+                            &entry.time,
+                            notes,
+                        )?;
+                        logged += characters;
+                    }
+                }
+                // pending entries count as imported too, so importing again doesn't submit them twice
+                for (key, characters) in entry.parts.iter() {
+                    import_repository.set_imported_characters(
+                        user_id,
+                        source,
+                        key,
+                        *characters,
+                        &time,
+                    )?;
+                }
+            }
+            tx.commit()?;
+
+            Ok((data, logged, pending, unmoderated))
+        })
+        .await?;
+
+    if let Some(channel_id) = channel_id {
+        for (entry, reason) in pending.iter() {
+            send_to_moderation(ctx, channel_id, entry, reason).await?;
+        }
+    }
+
+    let mut description = format!(
+        "Total characters logged: {}",
+        format_with_commas(data.total_characters)
+    );
+    if !pending.is_empty() {
+        description += &format!(
+            "\n{} large entries of {} characters wait for a moderator's approval.",
+            pending.len(),
+            format_with_commas(pending.iter().map(|(entry, _)| entry.characters).sum())
+        );
+    }
+    if unmoderated > 0 {
+        description += &format!(
+            "\nModeration is disabled, so {} large entries were logged without approval.",
+            unmoderated
+        );
+    }
+
+    press
+        .create_response(
+            ctx.serenity_context(),
+            serenity::builder::CreateInteractionResponse::UpdateMessage(
+                serenity::builder::CreateInteractionResponseMessage::new()
+                    .embed(
+                        create_base_embed()
+                            .title(format!(
+                                "Imported {} characters from {}!",
+                                format_with_commas(logged),
+                                source
+                            ))
+                            .description(description),
+                    )
+                    .components(vec![]),
+            ),
+        )
+        .await?;
+
+    sync_author_roles(ctx, &data).await
+}
+
+/// Counts the characters in a text, subtitle or epub file.
+///
+/// Punctuation, markup, timestamps and ruby readings aren't counted. You can log the result right away with the button.
+#[poise::command(slash_command)]
+pub async fn count(
+    ctx: Context<'_>,
+    #[description = "A .txt, .srt, .ass, .vtt or .epub file"] attachment: Attachment,
+    #[description = "Notes to log with the result, defaults to the file name"] notes: Option<
+        String,
+    >,
+) -> Result<(), Error> {
+    ctx.defer().await?;
+    let text = match read_attachment_text(ctx, &attachment).await {
+        Ok(text) => text,
+        Err(error) => {
+            let embed = create_base_embed().description(error.to_string());
+            ctx.send(CreateReply::default().embed(embed).ephemeral(true))
+                .await?;
+            return Ok(());
+        }
+    };
+
+    let notes = notes.unwrap_or_else(|| match attachment.filename.rsplit_once('.') {
+        Some((stem, _)) => stem.to_owned(),
+        None => attachment.filename.to_owned(),
+    });
+
+    let characters = count_characters(&text);
+    let mut other_rules = "".to_owned();
+    for rules in COUNTING_RULES.iter() {
+        if rules.name == DEFAULT_RULES.name {
+            continue;
+        }
+        other_rules += &format!(
+            "{}: {}\n",
+            rules.name,
+            format_with_commas(rules.count(&text))
+        );
+    }
+
+    let counts = breakdown(&text);
+    let mut breakdown_lines = "".to_owned();
+    for class in [
+        CharacterClass::Kanji,
+        CharacterClass::Hiragana,
+        CharacterClass::Katakana,
+        CharacterClass::FullWidthAlphanumeric,
+        CharacterClass::Latin,
+        CharacterClass::Punctuation,
+        CharacterClass::Symbol,
+    ] {
+        let count = counts.get(&class).copied().unwrap_or(0);
+        breakdown_lines += &format!("{}: {}\n", class, format_with_commas(count as i64));
+    }
+
+    let embed = create_base_embed()
+        .title(format!(
+            "{} contains {} characters",
+            attachment.filename,
+            format_with_commas(characters)
+        ))
+        .description(format!(
+            "Counted with the server rules ({}). Press the button below if you want to log them.",
+            DEFAULT_RULES.name
+        ))
+        .field("Breakdown", breakdown_lines, true)
+        .field("With other counting rules", other_rules, true);
+
+    let ctx_id = ctx.id();
+    let log_button_id = format!("{}log", ctx_id);
+    let components =
+        serenity::builder::CreateActionRow::Buttons(vec![serenity::builder::CreateButton::new(
+            &log_button_id,
+        )
+        .label(format!("Log {} characters", format_with_commas(characters)))]);
+    ctx.send(
+        CreateReply::default()
+            .embed(embed)
+            .components(vec![components]),
+    )
+    .await?;
+
+    let author_id = ctx.author().id;
+    let press = serenity::collector::ComponentInteractionCollector::new(ctx)
+        .filter(move |press| press.data.custom_id == log_button_id)
+        .author_id(author_id)
+        .timeout(std::time::Duration::from_secs(600))
+        .await;
+
+    if let Some(press) = press {
+        press
+            .create_response(
+                ctx.serenity_context(),
+                serenity::builder::CreateInteractionResponse::UpdateMessage(
+                    serenity::builder::CreateInteractionResponseMessage::new().components(vec![]),
+                ),
+            )
+            .await?;
+        log_characters_for_author(ctx, characters, Some(notes), None).await?;
+    }
+
+    Ok(())
+}
+
+/// Admin-only command to change any member's logs
+#[poise::command(slash_command, default_member_permissions = "ADMINISTRATOR")]
+pub async fn edit_characters(
+    ctx: Context<'_>,
+    #[description = "The targeted member"] user_id: UserId,
+    #[description = "The amount of characters read"] characters: i64,
+    #[description = "Why the logs are changed, it's kept in the audit log"] reason: String,
+    #[description = "Extra information such as the title of the book or VN"] notes: Option<String>,
+) -> Result<(), Error> {
+    if let Err(error) = check_entry_characters(characters) {
+        let embed = create_base_embed().description(error.to_string());
+        ctx.send(CreateReply::default().embed(embed).ephemeral(true))
+            .await?;
+        return Ok(());
+    }
+
+    let name = user_id.to_user(ctx).await?.display_name().to_owned();
+    let actor_id = ctx.author().id.get();
+    let time = ctx.data().clock.timestamp();
+    let result = ctx
+        .data()
+        .database
+        .write(move |connection| {
+            let tx = connection.transaction()?;
+            let mut repository = SQLiteCharacterStatisticsRepository::new(&tx);
+
+            let result = service::log_characters(
+                &mut repository,
+                user_id.get(),
+                &name,
+                characters,
+                &time,
+                notes,
+            )?;
+
+            let mut audit_repository = SQLiteAuditRepository::new(&tx);
+            audit_repository.add_audit_entry(&AuditEntry {
+                actor_id,
+                target_id: user_id.get(),
+                action: AuditAction::EditCharacters,
+                delta: result.delta(),
+                previous_total: result.previous_total,
+                reason,
+                log_entry_id: result.log_entry_id,
+                time,
+            })?;
+            tx.commit()?;
+            Ok(result)
+        })
+        .await?;
+
+    let guild = ctx.guild().unwrap().to_owned();
+    let member = guild.member(ctx, user_id).await?.into_owned();
+    reply_with_log_result(ctx, &member, characters, &result).await
+}
+
+/// Sends you a DM to remind you to log.
+#[poise::command(slash_command, subcommands("set", "show", "off"), subcommand_required)]
+pub async fn remind(_: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Reminds you to log at a time of day, with a Log now button.
+///
+/// The time is in the timezone from /settings me, i.e. `/remind set time:21:00 frequency:weekdays only_if_not_logged:True`
+#[poise::command(slash_command)]
+pub async fn set(
+    ctx: Context<'_>,
+    #[description = "The time of day in your timezone, i.e. 21:00"] time: String,
+    #[description = "On which days to remind you, daily by default"] frequency: Option<
+        ReminderFrequency,
+    >,
+    #[description = "Skip the reminder on days you already logged something"]
+    only_if_not_logged: Option<bool>,
+) -> Result<(), Error> {
+    let Some(time) = parse_time(&time) else {
+        let embed = create_base_embed().description("Provide the time as HH:MM, i.e. 21:00.");
+        ctx.send(CreateReply::default().embed(embed).ephemeral(true))
+            .await?;
+        return Ok(());
+    };
+
+    let now = ctx.data().clock.now();
+    let reminder = Reminder {
+        user_id: ctx.author().id.get(),
+        time,
+        frequency: frequency.unwrap_or(ReminderFrequency::Daily),
+        only_if_not_logged: only_if_not_logged.unwrap_or(false),
+        // a time earlier today shouldn't send it right away
+        last_sent: Some(now),
+    };
+    let stored = reminder.clone();
+    ctx.data()
+        .database
+        .write(move |connection| {
+            let tx = connection.transaction()?;
+            SQLiteReminderRepository::new(&tx).set_reminder(&stored)?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await?;
+
+    let settings = get_user_settings(ctx.data(), reminder.user_id).await?;
+    let mut description = format!(
+        "{} Make sure your DMs are open to members of this server.",
+        format_reminder(&reminder, now, &settings)
+    );
+    if !settings.reminder_dms {
+        description
+            .push_str(" Reminder DMs are turned off in /settings me, turn them on to get it.");
+    }
+    let embed = create_base_embed().description(description);
+    ctx.send(CreateReply::default().embed(embed).ephemeral(true))
+        .await?;
+    Ok(())
+}
+
+/// Shows when you get reminded to log.
+#[poise::command(slash_command)]
+pub async fn show(ctx: Context<'_>) -> Result<(), Error> {
+    let user_id = ctx.author().id.get();
+    let reminder = ctx
+        .data()
+        .database
+        .read(move |connection| {
+            let tx = connection.transaction()?;
+            let reminder = SQLiteReminderRepository::new(&tx).get_reminder(user_id)?;
+            tx.commit()?;
+            Ok(reminder)
+        })
+        .await?;
+
+    let description = match reminder {
+        Some(reminder) => {
+            let settings = get_user_settings(ctx.data(), user_id).await?;
+            format_reminder(&reminder, ctx.data().clock.now(), &settings)
+        }
+        None => "You don't have a reminder, set one with /remind set.".to_owned(),
+    };
+    let embed = create_base_embed().description(description);
+    ctx.send(CreateReply::default().embed(embed).ephemeral(true))
+        .await?;
+    Ok(())
+}
+
+fn format_reminder(reminder: &Reminder, now: DateTime<Utc>, settings: &UserSettings) -> String {
+    let Ok(timezone) = settings.timezone() else {
+        return format!(
+            "Your timezone `{}` can't be loaded, so no reminders are sent until you pick it again with /settings me.",
+            settings.timezone
+        );
+    };
+    let mut description = format!(
+        "I'll DM you {} at {} ({}, change it with /settings me).",
+        reminder.frequency.name(),
+        reminder.time.format("%H:%M"),
+        timezone.name()
+    );
+    if reminder.only_if_not_logged {
+        description.push_str(" Days you already logged on are skipped.");
+    }
+    if let Some(next) = reminder.next_due(now, &timezone) {
+        description.push_str(&format!(
+            " The next reminder is <t:{}:f>.",
+            next.timestamp()
+        ));
+    }
+    description
+}
+
+/// Stops the reminders to log.
+#[poise::command(slash_command)]
+pub async fn off(ctx: Context<'_>) -> Result<(), Error> {
+    let user_id = ctx.author().id.get();
+    let deleted = ctx
+        .data()
+        .database
+        .write(move |connection| {
+            let tx = connection.transaction()?;
+            let deleted = SQLiteReminderRepository::new(&tx).delete_reminder(user_id)?;
+            tx.commit()?;
+            Ok(deleted)
+        })
+        .await?;
+
+    let message = if deleted {
+        "You won't get reminders anymore."
+    } else {
+        "You don't have a reminder."
+    };
+    let embed = create_base_embed().description(message);
+    ctx.send(CreateReply::default().embed(embed).ephemeral(true))
+        .await?;
+    Ok(())
+}
+
+/// Changes how the bot treats you.
+#[poise::command(slash_command, subcommands("me"), subcommand_required)]
+pub async fn settings(_: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+async fn autocomplete_timezone<'a>(
+    _ctx: Context<'_>,
+    partial: &'a str,
+) -> impl Iterator<Item = String> + 'a {
+    let partial = partial.to_lowercase();
+    timezone_names()
+        .into_iter()
+        .filter(move |name| name.to_lowercase().contains(&partial))
+        .take(25)
+}
+
+/// Shows or changes your settings, only the options you fill in are changed.
+///
+/// i.e. `/settings me timezone:America/New_York default_media_type:visual_novel`
+#[poise::command(slash_command)]
+pub async fn me(
+    ctx: Context<'_>,
+    #[description = "Your timezone, i.e. America/New_York, dates are shown and grouped in it"]
+    #[autocomplete = "autocomplete_timezone"]
+    timezone: Option<String>,
+    #[description = "What your logs are when they don't say, none to clear it"]
+    default_media_type: Option<DefaultMediaType>,
+    #[description = "Whether others can see your /history"] history_visible: Option<bool>,
+    #[description = "Whether you appear on the leaderboard"] show_on_leaderboard: Option<bool>,
+    #[description = "Whether /remind sends you DMs"] reminder_dms: Option<bool>,
+    #[description = "Whether you get a DM when a moderator reviews your log"] review_dms: Option<
+        bool,
+    >,
+) -> Result<(), Error> {
+    let timezone = match timezone {
+        Some(name) => match Timezone::load(&name) {
+            Some(timezone) => Some(timezone.name().to_owned()),
+            None => {
+                let embed = create_base_embed().description(format!(
+                    "Unknown timezone `{}`, pick one of the suggestions, i.e. America/New_York.",
+                    name
+                ));
+                ctx.send(CreateReply::default().embed(embed).ephemeral(true))
+                    .await?;
+                return Ok(());
+            }
+        },
+        None => None,
+    };
+
+    let user_id = ctx.author().id.get();
+    let settings = ctx
+        .data()
+        .database
+        .write(move |connection| {
+            let tx = connection.transaction()?;
+            let mut repository = SQLiteUserSettingsRepository::new(&tx);
+            let mut settings = repository.get_settings(user_id)?;
+            let changed = timezone.is_some()
+                || default_media_type.is_some()
+                || history_visible.is_some()
+                || show_on_leaderboard.is_some()
+                || reminder_dms.is_some()
+                || review_dms.is_some();
+            if let Some(timezone) = timezone {
+                settings.timezone = timezone;
+            }
+            if let Some(media_type) = default_media_type {
+                settings.default_media_type = media_type.media_type();
+            }
+            if let Some(history_visible) = history_visible {
+                settings.history_visible = history_visible;
+            }
+            if let Some(show_on_leaderboard) = show_on_leaderboard {
+                settings.show_on_leaderboard = show_on_leaderboard;
+            }
+            if let Some(reminder_dms) = reminder_dms {
+                settings.reminder_dms = reminder_dms;
+            }
+            if let Some(review_dms) = review_dms {
+                settings.review_dms = review_dms;
+            }
+            if changed {
+                repository.set_settings(&settings)?;
+            }
+            tx.commit()?;
+            Ok(settings)
+        })
+        .await?;
+
+    let yes_no = |value: bool| if value { "Yes" } else { "No" };
+    let embed = create_base_embed()
+        .title("Your settings")
+        .field(
+            "Timezone",
+            match settings.timezone() {
+                Ok(_) => settings.timezone.to_owned(),
+                Err(_) => format!("{} (can't be loaded, pick it again)", settings.timezone),
+            },
+            true,
+        )
+        .field(
+            "Default media type",
+            settings
+                .default_media_type
+                .map_or("-", |media_type| media_type.name()),
+            true,
+        )
+        .field("History visible", yes_no(settings.history_visible), true)
+        .field(
+            "On the leaderboard",
+            yes_no(settings.show_on_leaderboard),
+            true,
+        )
+        .field("Reminder DMs", yes_no(settings.reminder_dms), true)
+        .field("Review DMs", yes_no(settings.review_dms), true);
+    ctx.send(CreateReply::default().embed(embed).ephemeral(true))
+        .await?;
+    Ok(())
+}
+
+/// Admin-only commands to maintain the database.
+#[poise::command(
+    slash_command,
+    subcommands("verify_totals", "set_total", "merge_users", "wipe", "names", "jobs"),
+    subcommand_required,
+    default_member_permissions = "ADMINISTRATOR"
+)]
+pub async fn admin(_: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Recomputes every member's total from their logs and reports the ones that don't match.
+#[poise::command(slash_command)]
+pub async fn verify_totals(
+    ctx: Context<'_>,
+    #[description = "Overwrite the mismatched totals with the recomputed ones"] repair: Option<
+        bool,
+    >,
+) -> Result<(), Error> {
+    let repair = repair.unwrap_or(false);
+    let repaired_by = repair.then_some(ctx.author().id.get());
+    let clock = ctx.data().clock.clone();
+    let mismatches = ctx
+        .data()
+        .database
+        .write(move |connection| recompute_totals(connection, repaired_by, clock.as_ref()))
+        .await?;
+
+    let title = match (mismatches.len(), repair) {
+        (0, _) => "All totals match their logs".to_owned(),
+        (count, true) => format!("Repaired {} totals", count),
+        (count, false) => format!("{} totals don't match their logs", count),
+    };
+    let mut description = mismatches
+        .iter()
+        .take(ADMIN_LIST_SIZE)
+        .map(format_mismatch)
+        .collect::<Vec<String>>()
+        .join("\n");
+    if mismatches.len() > ADMIN_LIST_SIZE {
+        description += &format!("\n...and {} more", mismatches.len() - ADMIN_LIST_SIZE);
+    }
+    if !repair && !mismatches.is_empty() {
+        description += "\n\nRun the command again with repair to fix them.";
+    }
+
+    let embed = create_base_embed().title(title).description(description);
+    ctx.send(CreateReply::default().embed(embed).ephemeral(true))
+        .await?;
+    Ok(())
+}
+
+/// Sets a member's total to an exact number by logging the difference.
 #[poise::command(slash_command)]
-pub async fn log_characters(
+pub async fn set_total(
     ctx: Context<'_>,
-    #[description = "The amount of characters read"] characters: i32,
-    #[description = "Extra information such as the title of the book or VN"] notes: Option<String>,
+    #[description = "The targeted member"] user: UserId,
+    #[description = "The new total characters"]
+    #[min = 0]
+    total: i64,
+    #[description = "Why the total is set, it's kept in the audit log"] reason: String,
 ) -> Result<(), Error> {
-    let (data, rank) = {
-        let mut connection = ctx.data().connection.lock().unwrap();
-        let tx = connection.transaction().map_err(|e| e.to_string())?;
-        let mut repository = SQLiteCharacterStatisticsRepository::new(&tx);
+    let name = user.to_user(ctx).await?.display_name().to_owned();
+    let actor_id = ctx.author().id.get();
+    let time = ctx.data().clock.timestamp();
+    let log_name = name.clone();
+    let (previous_total, statistics) = ctx
+        .data()
+        .database
+        .write(move |connection| {
+            let tx = connection.transaction()?;
+            let mut repository = SQLiteCharacterStatisticsRepository::new(&tx);
+
+            let previous_total = repository
+                .get_or_initialize_statistics(user.get(), &log_name)?
+                .total_characters;
+            let (statistics, log_entry_id) = repository.add_log_entry_with_id(
+                user.get(),
+                &log_name,
+                total - previous_total,
+                &time,
+                Some(format!(
+                    "Total set to {} by an admin",
+                    format_with_commas(total)
+                )),
+            )?;
+
+            let mut audit_repository = SQLiteAuditRepository::new(&tx);
+            audit_repository.add_audit_entry(&AuditEntry {
+                actor_id,
+                target_id: user.get(),
+                action: AuditAction::SetTotal,
+                delta: statistics.total_characters - previous_total,
+                previous_total,
+                reason,
+                log_entry_id,
+                time,
+            })?;
+            tx.commit()?;
+
+            Ok((previous_total, statistics))
+        })
+        .await?;
+
+    sync_member_roles(ctx, user, &statistics).await?;
+
+    let embed = create_base_embed().description(format!(
+        "Set the total of {} from {} to {} characters.",
+        name,
+        format_with_commas(previous_total),
+        format_with_commas(statistics.total_characters)
+    ));
+    ctx.send(CreateReply::default().embed(embed).ephemeral(true))
+        .await?;
+    Ok(())
+}
 
-        let user_id = ctx.author().id;
-        let name = ctx.author().display_name();
+/// Moves every log of one member to another, i.e. from an alt account to the main one.
+#[poise::command(slash_command)]
+pub async fn merge_users(
+    ctx: Context<'_>,
+    #[description = "The member whose logs are moved"] from: UserId,
+    #[description = "The member who receives the logs"] to: UserId,
+    #[description = "Why the logs are moved, it's kept in the audit log"] reason: String,
+) -> Result<(), Error> {
+    if from == to {
+        let embed = create_base_embed().description("Pick two different members to merge.");
+        ctx.send(CreateReply::default().embed(embed).ephemeral(true))
+            .await?;
+        return Ok(());
+    }
 
-        let time = &ctx.created_at();
-        let data = repository.add_log_entry(user_id.get(), name, characters, time, notes)?;
-        let rank = repository.get_rank(&data)?;
-        tx.commit()?;
+    let to_name = to.to_user(ctx).await?.display_name().to_owned();
+    let actor_id = ctx.author().id.get();
+    let time = ctx.data().clock.timestamp();
+    let merged = ctx
+        .data()
+        .database
+        .write(move |connection| {
+            let tx = connection.transaction()?;
+            let mut repository = SQLiteCharacterStatisticsRepository::new(&tx);
+
+            match repository.get_statistics(from.get())? {
+                None => Ok(None),
+                Some(from_statistics) => {
+                    let to_statistics =
+                        repository.get_or_initialize_statistics(to.get(), &to_name)?;
+                    let new_total = to_statistics
+                        .total_characters
+                        .checked_add(from_statistics.total_characters)
+                        .ok_or("The total characters would overflow.")?;
+                    let moved = repository.move_log_entries(from.get(), to.get())?;
+                    repository.set_total_characters(from.get(), 0)?;
+                    repository.set_total_characters(to.get(), new_total)?;
+                    SQLiteImportRepository::new(&tx).move_imported_entries(from.get(), to.get())?;
+                    // pending logs are approved for the member who receives the logs
+                    let moved_pending = SQLiteModerationRepository::new(&tx)
+                        .move_pending_log_entries(from.get(), to.get(), &to_name)?;
+
+                    let reason = format!("Merged <@{}> into <@{}>: {}", from, to, reason);
+                    let mut audit_repository = SQLiteAuditRepository::new(&tx);
+                    audit_repository.add_audit_entry(&AuditEntry {
+                        actor_id,
+                        target_id: from.get(),
+                        action: AuditAction::MergeUsers,
+                        delta: -from_statistics.total_characters,
+                        previous_total: from_statistics.total_characters,
+                        reason: reason.clone(),
+                        log_entry_id: None,
+                        time,
+                    })?;
+                    audit_repository.add_audit_entry(&AuditEntry {
+                        actor_id,
+                        target_id: to.get(),
+                        action: AuditAction::MergeUsers,
+                        delta: from_statistics.total_characters,
+                        previous_total: to_statistics.total_characters,
+                        reason,
+                        log_entry_id: None,
+                        time,
+                    })?;
+                    tx.commit()?;
+
+                    Ok(Some((
+                        moved,
+                        moved_pending,
+                        CharacterStatistics::new(from.get(), 0, from_statistics.name),
+                        CharacterStatistics::new(to.get(), new_total, to_name),
+                    )))
+                }
+            }
+        })
+        .await?;
 
-        (data, rank)
+    let Some((moved, moved_pending, from_statistics, to_statistics)) = merged else {
+        let embed = create_base_embed().description("That member hasn't made any logs.");
+        ctx.send(CreateReply::default().embed(embed).ephemeral(true))
+            .await?;
+        return Ok(());
     };
 
-    let user = ctx.author_member().await.unwrap().into_owned();
-    let guild = ctx.guild().unwrap().to_owned();
-    let user_roles = &user.roles;
-    let guild_roles = &guild.roles.clone();
-    let roles = UserRoles::new(user_roles, guild_roles);
-    let new_role = roles.update_role(ctx, &guild, &user, &data).await?;
-    if let Some(new_role) = new_role {
-        // role changed, if it's higher give a congratulations message
-        if roles.roles.iter().all(|r| &new_role > r) {
-            let congrats_msg = format!(
-                "Congratulations {} for obtaining role: {}",
-                user.user.display_name(),
-                new_role.to_string()
-            );
-            ctx.say(&congrats_msg).await?;
-            for channel_id in CONGRATULATE_NEW_ROLE_CHANNEL_IDS {
-                ChannelId::new(channel_id).say(ctx, &congrats_msg).await?;
-            }
-        }
-    }
+    sync_member_roles(ctx, from, &from_statistics).await?;
+    sync_member_roles(ctx, to, &to_statistics).await?;
 
-    let current_role = Roles::from_characters_and_quiz_roles(&roles.quizzes, data.total_characters);
-    let current_role_message = match current_role {
-        Some(role) => format!("Current role is {}", role.to_string()),
-        None => "You currently don't have a role".to_owned(),
-    };
+    let embed = create_base_embed().description(format!(
+        "Moved {} logs and {} pending logs from {} to {}, who now has {} characters.",
+        moved,
+        moved_pending,
+        from_statistics.name,
+        to_statistics.name,
+        format_with_commas(to_statistics.total_characters)
+    ));
+    ctx.send(CreateReply::default().embed(embed).ephemeral(true))
+        .await?;
+    Ok(())
+}
 
-    let next_role_message = if let Some(requirement) =
-        Roles::next_role_requirement(&roles.quizzes, data.total_characters)
-    {
-        let condition = data.total_characters < requirement.characters;
-        let message = if condition {
-            format!(
-                "{} more characters",
-                format_with_commas(requirement.characters - data.total_characters)
-            )
-        } else {
-            format!("to pass {}", requirement.quiz_role.unwrap().to_string())
-        };
-        format!("For {} you need {}.", requirement.role.to_string(), message)
-    } else {
-        "You already have the highest role.".to_owned()
+/// Deletes every log of a member and resets their total to 0.
+#[poise::command(slash_command)]
+pub async fn wipe(
+    ctx: Context<'_>,
+    #[description = "The targeted member"] user: UserId,
+    #[description = "Why the logs are deleted, it's kept in the audit log"] reason: String,
+) -> Result<(), Error> {
+    let actor_id = ctx.author().id.get();
+    let time = ctx.data().clock.timestamp();
+    let wiped = ctx
+        .data()
+        .database
+        .write(move |connection| {
+            let tx = connection.transaction()?;
+            let mut repository = SQLiteCharacterStatisticsRepository::new(&tx);
+
+            match repository.get_statistics(user.get())? {
+                None => Ok(None),
+                Some(statistics) => {
+                    let deleted = repository.delete_log_entries(user.get())?;
+                    repository.set_total_characters(user.get(), 0)?;
+                    SQLiteImportRepository::new(&tx).delete_imported_entries(user.get())?;
+                    // the moderation messages of the deleted pending logs answer that they were already reviewed
+                    let deleted_pending = SQLiteModerationRepository::new(&tx)
+                        .delete_pending_log_entries(user.get())?;
+
+                    let mut audit_repository = SQLiteAuditRepository::new(&tx);
+                    audit_repository.add_audit_entry(&AuditEntry {
+                        actor_id,
+                        target_id: user.get(),
+                        action: AuditAction::Wipe,
+                        delta: -statistics.total_characters,
+                        previous_total: statistics.total_characters,
+                        reason,
+                        log_entry_id: None,
+                        time,
+                    })?;
+                    tx.commit()?;
+
+                    Ok(Some((deleted, deleted_pending, statistics)))
+                }
+            }
+        })
+        .await?;
+
+    let Some((deleted, deleted_pending, statistics)) = wiped else {
+        let embed = create_base_embed().description("That member hasn't made any logs.");
+        ctx.send(CreateReply::default().embed(embed).ephemeral(true))
+            .await?;
+        return Ok(());
     };
 
-    let embed = create_base_embed()
-        .title(format!(
-            "{} logged {} characters!",
-            user.user.display_name(),
-            format_with_commas(characters),
-        ))
-        .description(format!(
-            "Total characters logged: {}",
-            format_with_commas(data.total_characters)
-        ))
-        .field(
-            format!("You are currently rank {} on the leaderboard", rank),
-            format!("{}. {}", current_role_message, next_role_message),
-            false,
-        );
+    let wiped_statistics = CharacterStatistics::new(user.get(), 0, statistics.name.clone());
+    sync_member_roles(ctx, user, &wiped_statistics).await?;
 
-    ctx.send(CreateReply::default().embed(embed)).await?;
+    let embed = create_base_embed().description(format!(
+        "Deleted {} logs and {} pending logs of {}, {} characters were removed.",
+        deleted,
+        deleted_pending,
+        statistics.name,
+        format_with_commas(statistics.total_characters)
+    ));
+    ctx.send(CreateReply::default().embed(embed).ephemeral(true))
+        .await?;
     Ok(())
 }
 
-/// Admin-only command to change any member's logs
-#[poise::command(slash_command, default_member_permissions = "ADMINISTRATOR")]
-pub async fn edit_characters(
+async fn make_name_history_embed_by_page(
     ctx: Context<'_>,
-    #[description = "The targeted member"] user_id: UserId,
-    #[description = "The amount of characters read"] characters: i32,
-    #[description = "Extra information such as the title of the book or VN"] notes: Option<String>,
-) -> Result<(), Error> {
-    let name = user_id.to_user(ctx).await?.display_name().to_owned();
-    let (data, rank) = {
-        let mut connection = ctx.data().connection.lock().unwrap();
-        let tx = connection.transaction().map_err(|e| e.to_string())?;
-        let mut repository = SQLiteCharacterStatisticsRepository::new(&tx);
+    page: u64,
+    filter: (Option<u64>, Option<String>),
+) -> Result<CreateEmbed, Error> {
+    let (name_changes, total_count) = ctx
+        .data()
+        .database
+        .read(move |connection| {
+            let (user_id, name) = filter;
+            let tx = connection.transaction()?;
+            let repository = SQLiteNameHistoryRepository::new(&tx);
+
+            let changes = repository.get_paginated_name_changes(user_id, name.as_deref(), page)?;
+            let total_count = repository.get_total_name_changes(user_id, name.as_deref())?;
+            tx.commit()?;
+
+            Ok((changes, total_count))
+        })
+        .await?;
 
-        let time = &ctx.created_at();
-        let data = repository.add_log_entry(user_id.get(), &name, characters, time, notes)?;
-        let rank = repository.get_rank(&data)?;
-        tx.commit()?;
+    let embed_builder = create_base_embed().title(format!(
+        "Name history (Page {} of {})",
+        page + 1,
+        total_count.div_ceil(NAME_HISTORY_PAGE_SIZE).max(1)
+    ));
 
-        (data, rank)
-    };
+    let mut lines = "".to_owned();
+    for change in name_changes {
+        let nickname = match &change.names.nickname {
+            Some(nickname) => format!(", nickname {}", nickname),
+            None => "".to_owned(),
+        };
+        lines += &format!(
+            "<t:{}:d> <@{}>: {}{}\n",
+            change.time.unix_timestamp(),
+            change.user_id,
+            change.names.global_name,
+            nickname
+        );
+    }
 
-    let guild = ctx.guild().unwrap().to_owned();
-    let member = guild.member(ctx, user_id).await?.into_owned();
-    let user_roles = &member.roles;
-    let guild_roles = &guild.roles.clone();
-    let roles = UserRoles::new(user_roles, guild_roles);
-    let new_role = roles.update_role(ctx, &guild, &member, &data).await?;
-    if let Some(new_role) = new_role {
-        // role changed, if it's higher give a congratulations message
-        if roles.roles.iter().all(|r| &new_role > r) {
-            let congrats_msg = format!(
-                "Congratulations {} for obtaining role: {}",
-                member.user.display_name(),
-                new_role.to_string()
-            );
-            ctx.say(&congrats_msg).await?;
-            for channel_id in CONGRATULATE_NEW_ROLE_CHANNEL_IDS {
-                ChannelId::new(channel_id).say(ctx, &congrats_msg).await?;
-            }
-        }
+    if lines.is_empty() {
+        lines = "No names were recorded.".to_owned();
     }
 
-    let current_role = Roles::from_characters_and_quiz_roles(&roles.quizzes, data.total_characters);
-    let current_role_message = match current_role {
-        Some(role) => format!("Current role is {}", role.to_string()),
-        None => "You currently don't have a role".to_owned(),
-    };
+    Ok(embed_builder.description(lines))
+}
 
-    let next_role_message = if let Some(requirement) =
-        Roles::next_role_requirement(&roles.quizzes, data.total_characters)
-    {
-        let condition = data.total_characters < requirement.characters;
-        let message = if condition {
-            format!(
-                "{} more characters",
-                format_with_commas(requirement.characters - data.total_characters)
-            )
-        } else {
-            format!("to pass {}", requirement.quiz_role.unwrap().to_string())
+/// Shows the names members had, to find out who someone was before they renamed.
+#[poise::command(slash_command)]
+pub async fn names(
+    ctx: Context<'_>,
+    #[description = "Only show the names of this member"] user: Option<UserId>,
+    #[description = "Only show the names containing this"] name: Option<String>,
+) -> Result<(), Error> {
+    let filter = (user.map(|user| user.get()), name);
+    let query = filter.clone();
+    let length = ctx
+        .data()
+        .database
+        .read(move |connection| {
+            let (user_id, name) = query;
+            let tx = connection.transaction()?;
+            let repository = SQLiteNameHistoryRepository::new(&tx);
+            let total_count = repository.get_total_name_changes(user_id, name.as_deref())?;
+            tx.commit()?;
+
+            Ok(total_count.div_ceil(NAME_HISTORY_PAGE_SIZE).max(1))
+        })
+        .await?;
+
+    paginate(ctx, None, filter, make_name_history_embed_by_page, length).await?;
+
+    Ok(())
+}
+
+/// Lists the scheduled jobs with their last and next run, or runs one right away.
+#[poise::command(slash_command)]
+pub async fn jobs(
+    ctx: Context<'_>,
+    #[description = "Run this job now instead of waiting for its schedule"] run: Option<Job>,
+) -> Result<(), Error> {
+    let data = ctx.data();
+    let mut description = "".to_owned();
+    if let Some(job) = run {
+        ctx.defer_ephemeral().await?;
+        let context = JobContext {
+            serenity: ctx.serenity_context().clone(),
+            database: data.database.clone(),
+            clock: data.clock.clone(),
         };
-        format!("For {} you need {}.", requirement.role.to_string(), message)
-    } else {
-        "You already have the highest role.".to_owned()
-    };
+        description = match data.scheduler.run(job, &context).await {
+            Ok(()) => format!("Ran {}.\n\n", job.name()),
+            Err(error) => format!("{} failed: {}\n\n", job.name(), error),
+        };
+    }
 
-    let embed = create_base_embed()
-        .title(format!(
-            "{} logged {} characters!",
-            member.user.display_name(),
-            format_with_commas(characters),
-        ))
-        .description(format!(
-            "Total characters logged: {}",
-            format_with_commas(data.total_characters)
-        ))
-        .field(
-            format!("You are currently rank {} on the leaderboard", rank),
-            format!("{}. {}", current_role_message, next_role_message),
-            false,
+    for status in data.scheduler.statuses(&data.database).await? {
+        let last_run = match &status.last_run {
+            Some(run) => format!("<t:{}:R>", run.time.timestamp()),
+            None => "never".to_owned(),
+        };
+        let next_run = match (status.running, status.next_run) {
+            (true, _) => "running now".to_owned(),
+            (false, Some(time)) => format!("<t:{}:R>", time.timestamp()),
+            (false, None) => "only when run from here".to_owned(),
+        };
+        description += &format!(
+            "**{}** ({}): {}\nLast run {}, next run {}\n",
+            status.job.name(),
+            status.schedule,
+            status.job.description(),
+            last_run,
+            next_run
         );
+        if let Some(error) = status.last_run.and_then(|run| run.error) {
+            description += &format!("Last error: {}\n", error);
+        }
+    }
 
-    ctx.send(CreateReply::default().embed(embed)).await?;
+    let embed = create_base_embed()
+        .title("Scheduled jobs")
+        .description(description);
+    ctx.send(CreateReply::default().embed(embed).ephemeral(true))
+        .await?;
     Ok(())
 }
 
-fn create_base_embed() -> CreateEmbed {
+pub fn create_base_embed() -> CreateEmbed {
     CreateEmbed::default()
         .footer(CreateEmbedFooter::new(
             "See /help for a list of commands and /usage for an explanation on what I can do.",
@@ -241,20 +1693,22 @@ pub async fn how_to_track(ctx: Context<'_>) -> Result<(), Error> {
     let embed = create_base_embed().title("How to track characters read")
         .description("Track **only** characters read (whether that be novels, VNs, games, subtitles, scripts, etc.), **not** raw listening. **Do not** guess how much immersion you've done, only log exact numbers that you're sure of. Whenever possible, your character count should exclude special characters (like punctuation, etc). These rules don't exist to be unnecessarily rigid, they exist to keep everyone on a (measureably) even playing field. Don't spoil things for others.
 
+If you have the subtitles, script or ebook as a .txt, .srt, .ass, .vtt or .epub file, you can count its characters with /count (or attach it to /log_characters) instead of using the sites below.
+
 **Anime**  
 It's recommended to create a Japanese-only account on [myanimelist](https://myanimelist.net/) or a similar tracking site, which will show exactly how many episodes you've watched with JP subs. You can download Japanese subtitles from [jimaku](https://jimaku.cc/) and watch/mine with [animebook](https://cademcniven.com/posts/20210703/). You can find the exact number of characters in the show with [subtitle character counter](https://cademcniven.com/subtitleCharacterCounter.html).
 
 **Novels**  
-You can track characters read for novels by reading on [ttu reader](https://ttu-ebook.web.app/). You can convert your ebooks to epub using [calibre](https://calibre-ebook.com/). You can download webnovels to epub with [WebToEpub](https://github.com/dteviot/WebToEpub). 
+You can track characters read for novels by reading on [ttu reader](https://ttu-ebook.web.app/). You can convert your ebooks to epub using [calibre](https://calibre-ebook.com/). You can download webnovels to epub with [WebToEpub](https://github.com/dteviot/WebToEpub). Instead of logging every day by hand, you can export your statistics from ttu reader and import them with /import ttu. 
 
 **Web Novels**  
 You can use [this userscript](https://greasyfork.org/en/scripts/512137-japanese-reading-tracker) to track characters read in popular Japanese novel websites like [Syosetu](https://syosetu.com) and [Kakuyomu](https://kakuyomu.jp).
 
 **Visual Novels**  
-You can track characters read from visual novels using a [texthooker](https://renji-xd.github.io/texthooker-ui/). Follow the [TMW Guide](https://learnjapanese.moe/vn/) to learn how to set it up.
+You can track characters read from visual novels using a [texthooker](https://renji-xd.github.io/texthooker-ui/). Follow the [TMW Guide](https://learnjapanese.moe/vn/) to learn how to set it up. Once you're done reading, you can save the lines from the texthooker and log them with /import texthooker.
 
 **Manga**  
-You can track characters read from manga by using [mokuro reader](https://reader.mokuro.app/), or which is a reader for [mokuro](https://github.com/kha-white/mokuro) files.");
+You can track characters read from manga by using [mokuro reader](https://reader.mokuro.app/), or which is a reader for [mokuro](https://github.com/kha-white/mokuro) files. You can export your volume data from mokuro reader and log the new characters with /import mokuro.");
 
     ctx.send(CreateReply::default().embed(embed)).await?;
     Ok(())
@@ -265,36 +1719,55 @@ async fn make_history_embed_by_page(
     page: u64,
     user_id: u64,
 ) -> Result<CreateEmbed, Error> {
-    let (log_entries, total_count) = {
-        let mut connection = ctx.data().connection.lock().unwrap();
-        let tx = connection.transaction().map_err(|e| e.to_string())?;
-        let mut repository = SQLiteCharacterStatisticsRepository::new(&tx);
-
-        let entries = repository.get_paginated_log_entries_by_time(user_id, page)?;
-        let total_entry_count = repository.get_total_log_entries(user_id)?;
-        tx.commit()?;
-
-        (entries, total_entry_count)
-    };
-
-    let embed_builder = create_base_embed().title(format!(
+    let (history_page, settings) = ctx
+        .data()
+        .database
+        .read(move |connection| {
+            let tx = connection.transaction()?;
+            let mut repository = SQLiteCharacterStatisticsRepository::new(&tx);
+            let history_page = service::history_page(&mut repository, user_id, page)?;
+            let settings = SQLiteUserSettingsRepository::new(&tx).get_settings(user_id)?;
+            tx.commit()?;
+            Ok((history_page, settings))
+        })
+        .await?;
+    // the dates are the days of the user whose history it is
+    let (timezone, notice) = timezone_or_utc(&settings);
+
+    let mut embed_builder = create_base_embed().title(format!(
         "Log history (Page {} of {})",
-        page + 1,
-        (total_count / LOG_ENTRY_PAGE_SIZE) + 1
+        history_page.page + 1,
+        history_page.total_pages
     ));
+    if let Some(notice) = notice {
+        embed_builder = embed_builder.field("Timezone", notice, false);
+    }
 
-    let mut lines = "".to_string();
-    for history in log_entries {
+    let mut lines = "".to_owned();
+    for history in history_page.items {
         let notes = match history.notes() {
             None => "-",
             Some(x) => x,
         };
-        let time = history.time().format("%Y年%m月%d日").to_string();
+        let time = timezone
+            .to_local(**history.time())
+            .format("%Y年%m月%d日")
+            .to_string();
+        let media_type = match history.media_type() {
+            Some(media_type) => format!(" ({})", media_type.name()),
+            None => "".to_owned(),
+        };
+        let edited_by = match history.edited_by() {
+            Some(actor_id) => format!(" *(admin edit by <@{}>)*", actor_id),
+            None => "".to_owned(),
+        };
         lines += &format!(
-            "{}: {} characters | {}\n",
+            "{}: {} characters{} | {}{}\n",
             time,
             format_with_commas(history.characters()),
-            notes
+            media_type,
+            notes,
+            edited_by
         );
     }
 
@@ -308,12 +1781,24 @@ pub async fn history(
     #[description = "The user you want to check"] user: Option<UserId>,
 ) -> Result<(), Error> {
     let user_id = user.unwrap_or_else(|| ctx.author().id).get();
-    let exists = {
-        let mut connection = ctx.data().connection.lock().unwrap();
-        let tx = connection.transaction()?;
-        let repository = SQLiteCharacterStatisticsRepository::new(&tx);
-        repository.exists(user_id)?
-    };
+    let (exists, settings) = ctx
+        .data()
+        .database
+        .read(move |connection| {
+            let tx = connection.transaction()?;
+            let repository = SQLiteCharacterStatisticsRepository::new(&tx);
+            let exists = repository.exists(user_id)?;
+            let settings = SQLiteUserSettingsRepository::new(&tx).get_settings(user_id)?;
+            Ok((exists, settings))
+        })
+        .await?;
+
+    if !settings.history_visible && user_id != ctx.author().id.get() {
+        let embed = create_base_embed().description("The user keeps their history private.");
+        ctx.send(CreateReply::default().embed(embed).ephemeral(true))
+            .await?;
+        return Ok(());
+    }
 
     if !exists {
         let embed = create_base_embed().description("The user hasn't made any logs.");
@@ -322,18 +1807,96 @@ pub async fn history(
         return Ok(());
     }
 
-    let length = {
-        let mut connection = ctx.data().connection.lock().unwrap();
-        let tx = connection.transaction().map_err(|e| e.to_string())?;
-        let mut repository = SQLiteCharacterStatisticsRepository::new(&tx);
+    let length = ctx
+        .data()
+        .database
+        .read(move |connection| {
+            let tx = connection.transaction()?;
+            let mut repository = SQLiteCharacterStatisticsRepository::new(&tx);
+            let length = service::total_history_pages(&mut repository, user_id)?;
+            tx.commit()?;
+            Ok(length)
+        })
+        .await?;
 
-        let entries = repository.get_total_log_entries(user_id)?;
-        tx.commit()?;
+    paginate(ctx, None, user_id, make_history_embed_by_page, length).await?;
 
-        entries.div_ceil(LOG_ENTRY_PAGE_SIZE)
-    };
+    Ok(())
+}
 
-    paginate(ctx, None, user_id, make_history_embed_by_page, length).await?;
+async fn make_audit_embed_by_page(
+    ctx: Context<'_>,
+    page: u64,
+    target_id: Option<u64>,
+) -> Result<CreateEmbed, Error> {
+    let (audit_entries, total_count) = ctx
+        .data()
+        .database
+        .read(move |connection| {
+            let tx = connection.transaction()?;
+            let repository = SQLiteAuditRepository::new(&tx);
+
+            let entries = repository.get_paginated_audit_entries(target_id, page)?;
+            let total_count = repository.get_total_audit_entries(target_id)?;
+            tx.commit()?;
+
+            Ok((entries, total_count))
+        })
+        .await?;
+
+    let embed_builder = create_base_embed().title(format!(
+        "Audit log (Page {} of {})",
+        page + 1,
+        total_count.div_ceil(AUDIT_PAGE_SIZE).max(1)
+    ));
+
+    let mut lines = "".to_owned();
+    for entry in audit_entries {
+        let actor = match entry.actor_id {
+            CLI_ACTOR_ID => "the command line".to_owned(),
+            actor_id => format!("<@{}>", actor_id),
+        };
+        lines += &format!(
+            "<t:{}:d> {} by {} on <@{}>: {} -> {} ({:+}) | {}\n",
+            entry.time.unix_timestamp(),
+            entry.action,
+            actor,
+            entry.target_id,
+            format_with_commas(entry.previous_total),
+            format_with_commas(entry.previous_total + entry.delta),
+            entry.delta,
+            entry.reason
+        );
+    }
+
+    if lines.is_empty() {
+        lines = "No admin changes were made.".to_owned();
+    }
+
+    Ok(embed_builder.description(lines))
+}
+
+/// Admin-only command to browse every change admins made to members' characters
+#[poise::command(slash_command, default_member_permissions = "ADMINISTRATOR")]
+pub async fn audit(
+    ctx: Context<'_>,
+    #[description = "Only show the changes made to this member"] user: Option<UserId>,
+) -> Result<(), Error> {
+    let target_id = user.map(|user| user.get());
+    let length = ctx
+        .data()
+        .database
+        .read(move |connection| {
+            let tx = connection.transaction()?;
+            let repository = SQLiteAuditRepository::new(&tx);
+            let total_count = repository.get_total_audit_entries(target_id)?;
+            tx.commit()?;
+
+            Ok(total_count.div_ceil(AUDIT_PAGE_SIZE).max(1))
+        })
+        .await?;
+
+    paginate(ctx, None, target_id, make_audit_embed_by_page, length).await?;
 
     Ok(())
 }
@@ -343,48 +1906,44 @@ async fn make_leaderboard_embed_by_page(
     page: u64,
     custom_context_data: (u64, String),
 ) -> Result<CreateEmbed, Error> {
-    let user_id = custom_context_data.0;
-    let user_name = custom_context_data.1.as_str();
+    let (user_id, user_name) = custom_context_data;
+    let name = user_name.clone();
     let start = Instant::now();
-    let (users, rank, users_count, stats) = {
-        let mut connection = ctx.data().connection.lock().unwrap();
-        let tx = connection.transaction().map_err(|e| e.to_string())?;
-        let mut repository = SQLiteCharacterStatisticsRepository::new(&tx);
-
-        let users = repository.get_paginated_active_users_by_characters(page)?;
-        let stats = repository.get_or_initialize_statistics(user_id, user_name)?;
-        let rank = repository.get_rank(&stats)?;
-
-        let users_count = repository.get_total_active_users()?;
-        tx.commit()?;
-        (users, rank, users_count, stats)
-    };
+    let leaderboard_page = ctx
+        .data()
+        .database
+        .write(move |connection| {
+            let tx = connection.transaction()?;
+            let mut repository = SQLiteCharacterStatisticsRepository::new(&tx);
+            let leaderboard_page =
+                service::leaderboard_page(&mut repository, user_id, &name, page)?;
+            tx.commit()?;
+            Ok(leaderboard_page)
+        })
+        .await?;
 
-    // there are 15 data per page
-    let total_pages = users_count.div_ceil(LEADERBOARD_PAGE_SIZE);
-    let is_on_leaderboard = rank >= 0 && (rank as u64) <= users_count;
     let embed_builder = create_base_embed()
         .title(format!(
             "Leaderboard (Page {} of {})",
-            page + 1,
-            total_pages
+            leaderboard_page.page + 1,
+            leaderboard_page.total_pages
         ))
-        .description(match is_on_leaderboard {
-            true => format!(
+        .description(match leaderboard_page.rank {
+            Some(rank) => format!(
                 "{} is currently rank {} of {}, with {} total characters.",
                 user_name,
                 rank,
-                users_count,
-                format_with_commas(stats.total_characters)
+                leaderboard_page.users_count,
+                format_with_commas(leaderboard_page.statistics.total_characters)
             ),
-            false => format!(
+            None => format!(
                 "{} is currently unranked, with no logged characters.",
                 user_name
             ),
         });
 
     let mut line = "".to_owned();
-    for (index, u) in users.iter().enumerate() {
+    for (index, u) in leaderboard_page.users.iter().enumerate() {
         let index: u64 = index.try_into().unwrap();
         let is_bold = user_id == u.get_user_id();
         let formatted = if is_bold {
@@ -433,38 +1992,30 @@ pub async fn rank(
         }
     };
 
-    let exists = {
-        let mut connection = ctx.data().connection.lock().unwrap();
-        let tx = connection.transaction()?;
-        let repository = SQLiteCharacterStatisticsRepository::new(&tx);
-        repository.exists(user_id)?
-    };
-
-    if !exists {
+    let (total_pages, rank) = ctx
+        .data()
+        .database
+        .read(move |connection| {
+            let tx = connection.transaction()?;
+            let mut repository = SQLiteCharacterStatisticsRepository::new(&tx);
+            let total_pages = service::total_leaderboard_pages(&mut repository)?;
+            let rank = service::rank(&mut repository, user_id)?;
+            tx.commit()?;
+            Ok((total_pages, rank))
+        })
+        .await?;
+
+    let Some((_, rank)) = rank else {
         let embed = create_base_embed().description("The user hasn't made any logs.");
         ctx.send(CreateReply::default().embed(embed).ephemeral(true))
             .await?;
         return Ok(());
-    }
-
-    let (total_pages, my_page) = {
-        let mut connection = ctx.data().connection.lock().unwrap();
-        let tx = connection.transaction().map_err(|e| e.to_string())?;
-        let mut repository = SQLiteCharacterStatisticsRepository::new(&tx);
-
-        let users_count = repository.get_total_active_users()?;
-        let stats = repository.get_or_initialize_statistics(user_id, &display_name)?;
-        let rank: u64 = repository.get_rank(&stats)?.try_into()?;
-        tx.commit()?;
-
-        let pages = users_count.div_ceil(LEADERBOARD_PAGE_SIZE);
-        let my_page = rank.div_ceil(LEADERBOARD_PAGE_SIZE) - 1;
-        (pages, my_page)
     };
+    let my_page = rank.map(leaderboard_page_of_rank);
 
     paginate(
         ctx,
-        Some(my_page),
+        my_page,
         (user_id, display_name),
         make_leaderboard_embed_by_page,
         total_pages,
@@ -477,16 +2028,17 @@ pub async fn rank(
 /// Shows the leaderboard.
 #[poise::command(slash_command)]
 pub async fn leaderboard(ctx: Context<'_>) -> Result<(), Error> {
-    let total_pages = {
-        let mut connection = ctx.data().connection.lock().unwrap();
-        let tx = connection.transaction().map_err(|e| e.to_string())?;
-        let mut repository = SQLiteCharacterStatisticsRepository::new(&tx);
-
-        let users_count = repository.get_total_active_users()?;
-        tx.commit()?;
-
-        users_count.div_ceil(LEADERBOARD_PAGE_SIZE)
-    };
+    let total_pages = ctx
+        .data()
+        .database
+        .read(move |connection| {
+            let tx = connection.transaction()?;
+            let mut repository = SQLiteCharacterStatisticsRepository::new(&tx);
+            let total_pages = service::total_leaderboard_pages(&mut repository)?;
+            tx.commit()?;
+            Ok(total_pages)
+        })
+        .await?;
 
     paginate(
         ctx,
@@ -528,16 +2080,243 @@ pub async fn roles(ctx: Context<'_>) -> Result<(), Error> {
 /// Shows the list of quizzes you need to unlock certain roles.
 #[poise::command(slash_command)]
 pub async fn quizzes(ctx: Context<'_>) -> Result<(), Error> {
+    // the same commands /quiz eligible shows, so both always match the accepted settings
+    let commands = QUIZ_REQUIREMENTS
+        .iter()
+        .map(|requirement| {
+            let role = Roles::gated_by_quiz(requirement.quiz_role)
+                .map_or_else(String::new, |gated| format!(" ({})", gated.role));
+            format!(
+                "{}{}: `{}`",
+                requirement.quiz_role,
+                role,
+                requirement.kotoba_command()
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
     let embed = create_base_embed()
         .title("Quizzes")
-        .description("Certain roles require you to pass a quiz (see /roles for more info). You're allowed to take the quiz as many times as you want. Take the quiz in #kotoba or #kotoba2. Quizzes must be taken in order (you can't skip quiz 1 and 2 by doing 3 first). 
-        
-        **Commands**
-        Quiz 1 (男爵): `k!quiz pq_1 15 nd mmq=4 font=5 atl=20`
-Quiz 2 (公爵): `k!quiz pq_2 20 nd mmq=4 font=5 atl=20`
-Quiz 3 (地仙): `k!quiz pq_3 20 nd mmq=4 font=5 atl=20`
-Quiz 4 (天仙): `k!quiz pq_4+animals+bugs+fish+plants+birds+vegetables+yojijukugo+countries 30 nd mmq=4 font=5 atl=20`
-Quiz 5 (上手): `k!quiz stations_full 100 nd mmq=4 font=5 atl=20`");
+        .description(format!(
+            "Certain roles require you to pass a quiz (see /roles for more info). You're allowed to take quizzes 1 to 3 as many times as you want. Quizzes 4 and 5 have a {} cooldown between attempts and only {} attempts are counted per {}, attempts made too early are ignored. Take the quiz in #kotoba or #kotoba2. Quizzes must be taken in order (you can't skip quiz 1 and 2 by doing 3 first).\n\n**Commands**\n{}",
+            format_duration(&LATE_QUIZ_COOLDOWN),
+            LATE_QUIZ_MAX_ATTEMPTS,
+            format_duration(&LATE_QUIZ_ATTEMPT_WINDOW),
+            commands
+        ));
+
+    ctx.send(CreateReply::default().embed(embed)).await?;
+    Ok(())
+}
+
+/// Commands related to the kotoba quizzes.
+#[poise::command(slash_command, subcommands("eligible", "stats"), subcommand_required)]
+pub async fn quiz(_: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Tells you which quiz to take next and whether you already have enough characters for it.
+#[poise::command(slash_command)]
+pub async fn eligible(ctx: Context<'_>) -> Result<(), Error> {
+    let user_id = ctx.author().id.get();
+    let name = ctx.author().display_name().to_owned();
+    let total_characters = ctx
+        .data()
+        .database
+        .read(move |connection| {
+            let tx = connection.transaction()?;
+            let mut repository = SQLiteCharacterStatisticsRepository::new(&tx);
+            let total_characters = if repository.exists(user_id)? {
+                repository
+                    .get_or_initialize_statistics(user_id, &name)?
+                    .total_characters
+            } else {
+                0
+            };
+            tx.commit()?;
+            Ok(total_characters)
+        })
+        .await?;
+
+    let member = ctx.author_member().await.unwrap().into_owned();
+    let guild = ctx.guild().unwrap().to_owned();
+    let roles = UserRoles::new(&member.roles, &guild.roles);
+
+    let next_quiz = match QuizRoles::next_quiz(&roles.quizzes) {
+        Some(next_quiz) => next_quiz,
+        None => {
+            let embed = create_base_embed()
+                .title("Quiz eligibility")
+                .description("You have already passed every quiz.");
+            ctx.send(CreateReply::default().embed(embed)).await?;
+            return Ok(());
+        }
+    };
+
+    let requirement = next_quiz.requirement();
+    let gated = Roles::gated_by_quiz(next_quiz).unwrap();
+    let character_message = if total_characters >= gated.characters {
+        format!(
+            "You have {} characters, which is enough for {}. Pass the quiz to get the role.",
+            format_with_commas(total_characters),
+            gated.role
+        )
+    } else {
+        format!(
+            "{} needs {} characters and you have {}. You need {} more characters before you can get {}.",
+            gated.role,
+            format_with_commas(gated.characters),
+            format_with_commas(total_characters),
+            format_with_commas(gated.characters - total_characters),
+            gated.role
+        )
+    };
+
+    let next_role_message = match Roles::next_role_requirement(&roles.quizzes, total_characters) {
+        Some(next_role) => format!("Your next role is {}.", next_role.role),
+        None => "You already have the highest role.".to_owned(),
+    };
+
+    let embed = create_base_embed()
+        .title("Quiz eligibility")
+        .description(format!(
+            "Your next quiz is {} ({}). {}",
+            next_quiz, gated.role, next_role_message
+        ))
+        .field(
+            "Command",
+            format!("`{}`", requirement.kotoba_command()),
+            false,
+        )
+        .field("Characters", character_message, false);
+
+    ctx.send(CreateReply::default().embed(embed)).await?;
+    Ok(())
+}
+
+fn format_quiz_statistics(statistics: &QuizStatistics) -> String {
+    format!(
+        "Attempts: {}\nPasses: {} ({:.1}% pass rate)\nAverage score: {:.1}\nBest score: {}",
+        format_with_commas(statistics.attempts as i64),
+        format_with_commas(statistics.passes as i64),
+        statistics.pass_rate(),
+        statistics.average_score,
+        statistics.best_score
+    )
+}
+
+async fn make_quiz_stats_embed_by_page(
+    ctx: Context<'_>,
+    page: u64,
+    quiz_role: QuizRoles,
+) -> Result<CreateEmbed, Error> {
+    let viewer_id = ctx.author().id.get();
+    let (statistics, passes, total_passes, settings) = ctx
+        .data()
+        .database
+        .read(move |connection| {
+            let tx = connection.transaction()?;
+            let repository = SQLiteQuizAttemptRepository::new(&tx);
+
+            let statistics = repository.get_quiz_statistics(quiz_role)?;
+            let passes = repository.get_paginated_quiz_passes(quiz_role, page)?;
+            let total_passes = repository.get_total_quiz_passes(quiz_role)?;
+            let settings = SQLiteUserSettingsRepository::new(&tx).get_settings(viewer_id)?;
+            tx.commit()?;
+
+            Ok((statistics, passes, total_passes, settings))
+        })
+        .await?;
+    let (timezone, notice) = timezone_or_utc(&settings);
+
+    let mut embed_builder = create_base_embed()
+        .title(format!(
+            "{} statistics (Page {} of {})",
+            quiz_role,
+            page + 1,
+            total_passes.div_ceil(QUIZ_PASS_PAGE_SIZE).max(1)
+        ))
+        .description(format_quiz_statistics(&statistics));
+    if let Some(notice) = notice {
+        embed_builder = embed_builder.field("Timezone", notice, false);
+    }
+
+    let mut lines = "".to_owned();
+    for (index, pass) in passes.iter().enumerate() {
+        let index: u64 = index.try_into().unwrap();
+        let name = match &pass.name {
+            Some(name) => name.to_owned(),
+            None => format!("<@{}>", pass.user_id),
+        };
+        lines += &format!(
+            "{}. {}: {}\n",
+            index + (page * QUIZ_PASS_PAGE_SIZE) + 1,
+            name,
+            timezone.to_local(*pass.time).format("%Y年%m月%d日")
+        );
+    }
+
+    if lines.is_empty() {
+        lines = "Nobody has passed this quiz yet.".to_owned();
+    }
+
+    Ok(embed_builder.field("Passed by", lines, false))
+}
+
+/// Shows attempt counts, pass rates, scores and who passed each quiz.
+#[poise::command(slash_command)]
+pub async fn stats(
+    ctx: Context<'_>,
+    #[description = "The quiz you want to check, leave empty for an overview"] quiz: Option<
+        QuizRoles,
+    >,
+) -> Result<(), Error> {
+    if let Some(quiz_role) = quiz {
+        let length = ctx
+            .data()
+            .database
+            .read(move |connection| {
+                let tx = connection.transaction()?;
+                let repository = SQLiteQuizAttemptRepository::new(&tx);
+                let total_passes = repository.get_total_quiz_passes(quiz_role)?;
+                tx.commit()?;
+
+                Ok(total_passes.div_ceil(QUIZ_PASS_PAGE_SIZE).max(1))
+            })
+            .await?;
+
+        paginate(ctx, None, quiz_role, make_quiz_stats_embed_by_page, length).await?;
+        return Ok(());
+    }
+
+    let all_statistics = ctx
+        .data()
+        .database
+        .read(move |connection| {
+            let tx = connection.transaction()?;
+            let repository = SQLiteQuizAttemptRepository::new(&tx);
+
+            let mut all_statistics = Vec::with_capacity(QUIZ_REQUIREMENTS.len());
+            for requirement in QUIZ_REQUIREMENTS.iter() {
+                let statistics = repository.get_quiz_statistics(requirement.quiz_role)?;
+                all_statistics.push((requirement.quiz_role, statistics));
+            }
+            tx.commit()?;
+
+            Ok(all_statistics)
+        })
+        .await?;
+
+    let mut embed = create_base_embed()
+        .title("Quiz statistics")
+        .description("Use `/quiz stats quiz:<quiz>` to see who passed a specific quiz.");
+    for (quiz_role, statistics) in all_statistics.iter() {
+        embed = embed.field(
+            quiz_role.to_string(),
+            format_quiz_statistics(statistics),
+            true,
+        );
+    }
 
     ctx.send(CreateReply::default().embed(embed)).await?;
     Ok(())
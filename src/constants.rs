@@ -2,20 +2,79 @@ use std::sync::LazyLock;
 
 use chrono::Duration;
 
-use crate::roles::{QuizRequirement, QuizRoles};
+use crate::roles::{AttemptLimit, QuizRequirement, QuizRoles};
 
 pub const KOTOBA_BOT_ID: u64 = 251239170058616833;
 
-/// in seconds -> 2 hours
-pub const USER_ACTIVE_STATUS_REFRESH_INTERVAL: i64 = Duration::hours(2).num_seconds();
-
 pub const CONGRATULATE_NEW_ROLE_CHANNEL_IDS: [u64; 1] = [735507346624741387];
 
 pub const QUIZ_TIME_LIMIT: i32 = 20000;
 pub const QUIZ_FONT: &str = "Eishiikaisho";
+/// the number passed to kotoba's `font=` option to select QUIZ_FONT
+pub const QUIZ_FONT_OPTION: i32 = 5;
+/// when enabled, passing a quiz only grants the quiz role if the member already has the characters for the role it gates
+pub const QUIZ_PASS_REQUIRES_CHARACTERS: bool = false;
+/// the minimum time between two counted attempts of quizzes 4 and 5
+pub const LATE_QUIZ_COOLDOWN: Duration = Duration::hours(1);
+/// how many attempts of quizzes 4 and 5 are counted inside LATE_QUIZ_ATTEMPT_WINDOW
+pub const LATE_QUIZ_MAX_ATTEMPTS: usize = 3;
+pub const LATE_QUIZ_ATTEMPT_WINDOW: Duration = Duration::days(1);
+
+/// the most characters a single log entry can add or remove, larger values are rejected as typos or abuse
+pub const MAX_CHARACTERS_PER_ENTRY: i64 = 10_000_000;
+
+/// logs of at least this many characters always wait for a moderator's approval
+pub const MODERATION_ABSOLUTE_THRESHOLD: i64 = 500_000;
+/// logs this many times larger than the user's recent average wait for approval too
+pub const MODERATION_RELATIVE_THRESHOLD: f64 = 20.0;
+/// the relative threshold only applies to logs of at least this many characters
+pub const MODERATION_RELATIVE_MINIMUM: i64 = 50_000;
+/// the relative threshold only applies with at least this many logs in the recent window
+pub const MODERATION_RECENT_ENTRIES: u64 = 5;
+/// in days, the window the recent average is taken over
+pub const MODERATION_RECENT_DAYS: i64 = 30;
+
+pub const DATABASE_PATH: &str = "./perdition.db";
+pub const BACKUP_DIRECTORY: &str = "./backups";
+/// how many backups are kept, the oldest ones are deleted after each backup
+pub const BACKUPS_KEPT: usize = 7;
+/// read-only connections next to the write connection, so reads don't wait for each other or for writes
+pub const DATABASE_READ_CONNECTIONS: usize = 4;
+/// how long a connection waits for a lock held by another connection before failing
+pub const DATABASE_BUSY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
 
 pub const LEADERBOARD_PAGE_SIZE: u64 = 15;
 pub const LOG_ENTRY_PAGE_SIZE: u64 = 15;
+pub const QUIZ_PASS_PAGE_SIZE: u64 = 15;
+pub const AUDIT_PAGE_SIZE: u64 = 15;
+pub const NAME_HISTORY_PAGE_SIZE: u64 = 15;
+
+/// the actor of audit entries for changes made from the command line, i.e. --verify-totals --repair
+pub const CLI_ACTOR_ID: u64 = 0;
+
+/// in bytes -> 25 MB, the default discord upload limit
+pub const MAX_ATTACHMENT_SIZE: u32 = 25 * 1024 * 1024;
+/// in bytes -> 64 MB, the most text an epub can unpack to before it's rejected
+pub const MAX_EXTRACTED_SIZE: u64 = 64 * 1024 * 1024;
+/// how many entries are shown before confirming an import
+pub const IMPORT_PREVIEW_SIZE: usize = 10;
+/// the most characters discord accepts in the value of an embed field
+pub const EMBED_FIELD_LIMIT: usize = 1024;
+/// how often the scheduler checks which jobs are due
+pub const SCHEDULER_TICK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+/// the default job schedules in UTC, each can be changed with a SCHEDULE_<JOB> env var, i.e. SCHEDULE_BACKUP="daily 03:30"
+/// the full sweep of the member list only catches joins and leaves missed while the bot was offline
+pub const ACTIVE_USERS_SCHEDULE: &str = "every 1d";
+pub const BACKUP_SCHEDULE: &str = "daily 04:00";
+/// reminders are checked this often, so they arrive at most this late
+pub const REMINDERS_SCHEDULE: &str = "every 1m";
+/// reminders missed for longer than this, i.e. while the bot was offline, aren't sent anymore
+pub const REMINDER_GRACE_PERIOD: Duration = Duration::hours(1);
+/// how many members each list of the digest shows
+pub const DIGEST_LIST_SIZE: usize = 10;
+
+/// how many users are listed in the replies of admin commands, the rest is summarized
+pub const ADMIN_LIST_SIZE: usize = 20;
 
 pub static QUIZ_REQUIREMENTS: LazyLock<Vec<QuizRequirement>> = LazyLock::new(|| {
     vec![
@@ -23,41 +82,72 @@ pub static QUIZ_REQUIREMENTS: LazyLock<Vec<QuizRequirement>> = LazyLock::new(||
             quiz_role: QuizRoles::Quiz1,
             score_limit: 15,
             max_missed_questions: 4,
-            unique_ids: vec!["281ebf61-e0aa-429e-a09f-f5b56079ee46".to_string()],
+            unique_ids: vec!["281ebf61-e0aa-429e-a09f-f5b56079ee46".to_owned()],
+            deck_names: vec!["pq_1".to_owned()],
+            cooldown: None,
+            attempt_limit: None,
         },
         QuizRequirement {
             quiz_role: QuizRoles::Quiz2,
             score_limit: 20,
             max_missed_questions: 4,
-            unique_ids: vec!["8982a22e-314d-4a08-a026-12e497299bb1".to_string()],
+            unique_ids: vec!["8982a22e-314d-4a08-a026-12e497299bb1".to_owned()],
+            deck_names: vec!["pq_2".to_owned()],
+            cooldown: None,
+            attempt_limit: None,
         },
         QuizRequirement {
             quiz_role: QuizRoles::Quiz3,
             score_limit: 20,
             max_missed_questions: 4,
-            unique_ids: vec!["14c54eb0-f77d-4611-b974-c1e109ef09da".to_string()],
+            unique_ids: vec!["14c54eb0-f77d-4611-b974-c1e109ef09da".to_owned()],
+            deck_names: vec!["pq_3".to_owned()],
+            cooldown: None,
+            attempt_limit: None,
         },
         QuizRequirement {
             quiz_role: QuizRoles::Quiz4,
             score_limit: 1,
             max_missed_questions: 4,
             unique_ids: vec![
-                "2bef521f-512c-490d-924d-b00086c10f2d".to_string(),
-                "animals".to_string(),
-                "bugs".to_string(),
-                "fish".to_string(),
-                "plants".to_string(),
-                "birds".to_string(),
-                "vegetables".to_string(),
-                "yojijukugo".to_string(),
-                "countries".to_string(),
+                "2bef521f-512c-490d-924d-b00086c10f2d".to_owned(),
+                "animals".to_owned(),
+                "bugs".to_owned(),
+                "fish".to_owned(),
+                "plants".to_owned(),
+                "birds".to_owned(),
+                "vegetables".to_owned(),
+                "yojijukugo".to_owned(),
+                "countries".to_owned(),
+            ],
+            deck_names: vec![
+                "pq_4".to_owned(),
+                "animals".to_owned(),
+                "bugs".to_owned(),
+                "fish".to_owned(),
+                "plants".to_owned(),
+                "birds".to_owned(),
+                "vegetables".to_owned(),
+                "yojijukugo".to_owned(),
+                "countries".to_owned(),
             ],
+            cooldown: Some(LATE_QUIZ_COOLDOWN),
+            attempt_limit: Some(AttemptLimit {
+                max_attempts: LATE_QUIZ_MAX_ATTEMPTS,
+                window: LATE_QUIZ_ATTEMPT_WINDOW,
+            }),
         },
         QuizRequirement {
             quiz_role: QuizRoles::Quiz5,
             score_limit: 100,
             max_missed_questions: 4,
-            unique_ids: vec!["stations_japan".to_string()],
+            unique_ids: vec!["stations_japan".to_owned()],
+            deck_names: vec!["stations_full".to_owned()],
+            cooldown: Some(LATE_QUIZ_COOLDOWN),
+            attempt_limit: Some(AttemptLimit {
+                max_attempts: LATE_QUIZ_MAX_ATTEMPTS,
+                window: LATE_QUIZ_ATTEMPT_WINDOW,
+            }),
         },
     ]
 });
@@ -0,0 +1,325 @@
+use std::{collections::HashMap, fmt};
+
+/// What kind of character a char is, as far as reading statistics are concerned
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CharacterClass {
+    Kanji,
+    Hiragana,
+    Katakana,
+    /// Ａ-Ｚ, ａ-ｚ and ０-９
+    FullWidthAlphanumeric,
+    /// Japanese, full-width and ASCII punctuation, i.e. 、。「」！？…
+    Punctuation,
+    /// Anything that isn't a letter, number or punctuation, i.e. ♪★○
+    Symbol,
+    Whitespace,
+    /// ASCII letters and digits, and accented latin letters
+    Latin,
+    /// Letters from other scripts and control characters
+    Other,
+}
+
+impl fmt::Display for CharacterClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let string = match self {
+            Self::Kanji => "Kanji",
+            Self::Hiragana => "Hiragana",
+            Self::Katakana => "Katakana",
+            Self::FullWidthAlphanumeric => "Full-width alphanumerics",
+            Self::Punctuation => "Punctuation",
+            Self::Symbol => "Symbols",
+            Self::Whitespace => "Whitespace",
+            Self::Latin => "Latin",
+            Self::Other => "Other",
+        };
+        f.write_str(string)
+    }
+}
+
+pub fn classify(c: char) -> CharacterClass {
+    match c {
+        // 々〆〇 and 〻 behave like kanji even though they live in the punctuation block
+        '\u{3005}'..='\u{3007}'
+        | '\u{303B}'
+        | '\u{3400}'..='\u{4DBF}'
+        | '\u{4E00}'..='\u{9FFF}'
+        | '\u{F900}'..='\u{FAFF}'
+        | '\u{20000}'..='\u{2FA1F}'
+        | '\u{30000}'..='\u{3134F}' => CharacterClass::Kanji,
+
+        '\u{3041}'..='\u{3096}' | '\u{309D}'..='\u{309F}' => CharacterClass::Hiragana,
+
+        // the middle dot ・ (U+30FB) sits between these ranges and is punctuation
+        '\u{30A1}'..='\u{30FA}'
+        | '\u{30FC}'..='\u{30FF}'
+        | '\u{31F0}'..='\u{31FF}'
+        | '\u{FF66}'..='\u{FF9F}' => CharacterClass::Katakana,
+
+        '\u{FF10}'..='\u{FF19}' | '\u{FF21}'..='\u{FF3A}' | '\u{FF41}'..='\u{FF5A}' => {
+            CharacterClass::FullWidthAlphanumeric
+        }
+
+        c if c.is_whitespace() => CharacterClass::Whitespace,
+
+        '\u{3001}'..='\u{3004}'
+        | '\u{3008}'..='\u{3011}'
+        | '\u{3014}'..='\u{301F}'
+        | '\u{3030}'
+        | '\u{303D}'
+        | '\u{30A0}'
+        | '\u{30FB}'
+        | '\u{2010}'..='\u{2027}'
+        | '\u{2030}'..='\u{205E}'
+        | '\u{FF01}'..='\u{FF0F}'
+        | '\u{FF1A}'..='\u{FF20}'
+        | '\u{FF3B}'..='\u{FF40}'
+        | '\u{FF5B}'..='\u{FF65}' => CharacterClass::Punctuation,
+        c if c.is_ascii_punctuation() => CharacterClass::Punctuation,
+
+        c if c.is_ascii_alphanumeric() => CharacterClass::Latin,
+        '\u{00C0}'..='\u{024F}' if c != '×' && c != '÷' => CharacterClass::Latin,
+
+        c if c.is_alphanumeric() || c.is_control() => CharacterClass::Other,
+        _ => CharacterClass::Symbol,
+    }
+}
+
+/// A named set of character classes that are counted as read characters
+#[derive(Debug)]
+pub struct CountingRules {
+    pub name: &'static str,
+    pub counted: &'static [CharacterClass],
+}
+
+impl CountingRules {
+    pub fn counts(&self, class: CharacterClass) -> bool {
+        self.counted.contains(&class)
+    }
+
+    pub fn count(&self, text: &str) -> i64 {
+        text.chars().filter(|c| self.counts(classify(*c))).count() as i64
+    }
+}
+
+/// Kanji and kana only, this is what /how_to_track asks everyone to log
+pub static JAPANESE_ONLY: CountingRules = CountingRules {
+    name: "JP only, no punctuation",
+    counted: &[
+        CharacterClass::Kanji,
+        CharacterClass::Hiragana,
+        CharacterClass::Katakana,
+    ],
+};
+
+/// Close to what ttu reader counts
+pub static JAPANESE_WITH_ALPHANUMERICS: CountingRules = CountingRules {
+    name: "JP and alphanumerics, no punctuation",
+    counted: &[
+        CharacterClass::Kanji,
+        CharacterClass::Hiragana,
+        CharacterClass::Katakana,
+        CharacterClass::FullWidthAlphanumeric,
+        CharacterClass::Latin,
+    ],
+};
+
+pub static JAPANESE_WITH_PUNCTUATION: CountingRules = CountingRules {
+    name: "JP with punctuation",
+    counted: &[
+        CharacterClass::Kanji,
+        CharacterClass::Hiragana,
+        CharacterClass::Katakana,
+        CharacterClass::FullWidthAlphanumeric,
+        CharacterClass::Punctuation,
+    ],
+};
+
+/// Every character except whitespace, like most generic character counters
+pub static ALL_VISIBLE: CountingRules = CountingRules {
+    name: "Everything except whitespace",
+    counted: &[
+        CharacterClass::Kanji,
+        CharacterClass::Hiragana,
+        CharacterClass::Katakana,
+        CharacterClass::FullWidthAlphanumeric,
+        CharacterClass::Punctuation,
+        CharacterClass::Symbol,
+        CharacterClass::Latin,
+        CharacterClass::Other,
+    ],
+};
+
+pub static COUNTING_RULES: [&CountingRules; 4] = [
+    &JAPANESE_ONLY,
+    &JAPANESE_WITH_ALPHANUMERICS,
+    &JAPANESE_WITH_PUNCTUATION,
+    &ALL_VISIBLE,
+];
+
+/// The rules used by every counting command and import in the bot
+pub static DEFAULT_RULES: &CountingRules = &JAPANESE_ONLY;
+
+/// Counts the characters of a text with the default rules
+pub fn count_characters(text: &str) -> i64 {
+    DEFAULT_RULES.count(text)
+}
+
+/// Returns how many characters of each class a text contains
+pub fn breakdown(text: &str) -> HashMap<CharacterClass, u64> {
+    let mut counts = HashMap::new();
+    for c in text.chars() {
+        *counts.entry(classify(c)).or_insert(0) += 1;
+    }
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_class(chars: &str, class: CharacterClass) {
+        for c in chars.chars() {
+            assert_eq!(classify(c), class, "{c:?} (U+{:04X})", c as u32);
+        }
+    }
+
+    #[test]
+    fn classifies_kanji() {
+        assert_class("漢字日本語読書上手天皇", CharacterClass::Kanji);
+        assert_class("々〆〇〻", CharacterClass::Kanji);
+        // extension A, compatibility and extension B
+        assert_class(
+            "\u{3400}\u{4DBF}\u{F900}\u{FAFF}\u{20000}\u{2A6DF}",
+            CharacterClass::Kanji,
+        );
+    }
+
+    #[test]
+    fn classifies_hiragana() {
+        assert_class("ぁあいうえおかがんゔゕゖ", CharacterClass::Hiragana);
+        assert_class("ゝゞゟ", CharacterClass::Hiragana);
+    }
+
+    #[test]
+    fn classifies_katakana() {
+        assert_class("ァアイウエオカガンヴヵヶヷヺ", CharacterClass::Katakana);
+        assert_class("ーヽヾヿ", CharacterClass::Katakana);
+        assert_class("ㇰㇱㇿ", CharacterClass::Katakana);
+        assert_class("ｦｱｲｳｴｵﾝｰﾞﾟ", CharacterClass::Katakana);
+    }
+
+    #[test]
+    fn classifies_full_width_alphanumerics() {
+        assert_class(
+            "０１２３４５６７８９",
+            CharacterClass::FullWidthAlphanumeric,
+        );
+        assert_class(
+            "ＡＢＣＸＹＺａｂｃｘｙｚ",
+            CharacterClass::FullWidthAlphanumeric,
+        );
+    }
+
+    #[test]
+    fn classifies_punctuation() {
+        assert_class(
+            "、。〃「」『』【】〔〕〜〽・゠",
+            CharacterClass::Punctuation,
+        );
+        assert_class("！？（）：；［］｛｝｡｢｣､･", CharacterClass::Punctuation);
+        assert_class("…‥―‐“”‘’※", CharacterClass::Punctuation);
+        assert_class("!\"#%&'()*,-./:;?@[\\]_{}", CharacterClass::Punctuation);
+    }
+
+    #[test]
+    fn classifies_symbols() {
+        assert_class("♪★☆○◯→←×÷©€😀", CharacterClass::Symbol);
+    }
+
+    #[test]
+    fn classifies_whitespace() {
+        assert_class(" \t\n\r\u{3000}\u{00A0}", CharacterClass::Whitespace);
+    }
+
+    #[test]
+    fn classifies_latin() {
+        assert_class("azAZ09éÉüñßŒ", CharacterClass::Latin);
+    }
+
+    #[test]
+    fn classifies_other() {
+        assert_class("한국어абвгΩ\u{0000}\u{007F}", CharacterClass::Other);
+    }
+
+    #[test]
+    fn japanese_only_excludes_punctuation_and_latin() {
+        let text = "「今日は、いい天気ですね！」 ＯＫ ok ♪";
+        assert_eq!(JAPANESE_ONLY.count(text), 10);
+    }
+
+    #[test]
+    fn japanese_with_alphanumerics_counts_latin_and_full_width() {
+        let text = "「今日は、いい天気ですね！」 ＯＫ ok ♪";
+        assert_eq!(JAPANESE_WITH_ALPHANUMERICS.count(text), 14);
+    }
+
+    #[test]
+    fn japanese_with_punctuation_counts_punctuation() {
+        let text = "「今日は、いい天気ですね！」 ＯＫ ok ♪";
+        assert_eq!(JAPANESE_WITH_PUNCTUATION.count(text), 16);
+    }
+
+    #[test]
+    fn all_visible_excludes_only_whitespace() {
+        let text = "「今日は、いい天気ですね！」 ＯＫ ok ♪";
+        assert_eq!(ALL_VISIBLE.count(text), 19);
+    }
+
+    #[test]
+    fn counts_characters_outside_the_basic_plane_once() {
+        assert_eq!(JAPANESE_ONLY.count("𠮷野家"), 3);
+    }
+
+    #[test]
+    fn empty_text_counts_nothing() {
+        for rules in COUNTING_RULES.iter() {
+            assert_eq!(rules.count(""), 0);
+        }
+    }
+
+    #[test]
+    fn default_rules_are_japanese_only() {
+        assert_eq!(DEFAULT_RULES.name, JAPANESE_ONLY.name);
+        assert_eq!(count_characters("吾輩は猫である。"), 7);
+    }
+
+    #[test]
+    fn rule_names_are_unique() {
+        for (i, a) in COUNTING_RULES.iter().enumerate() {
+            for b in COUNTING_RULES.iter().skip(i + 1) {
+                assert_ne!(a.name, b.name);
+            }
+        }
+    }
+
+    #[test]
+    fn breakdown_counts_every_class() {
+        let counts = breakdown("漢字かなカナ、ＡＢ ab♪");
+        assert_eq!(counts[&CharacterClass::Kanji], 2);
+        assert_eq!(counts[&CharacterClass::Hiragana], 2);
+        assert_eq!(counts[&CharacterClass::Katakana], 2);
+        assert_eq!(counts[&CharacterClass::Punctuation], 1);
+        assert_eq!(counts[&CharacterClass::FullWidthAlphanumeric], 2);
+        assert_eq!(counts[&CharacterClass::Whitespace], 1);
+        assert_eq!(counts[&CharacterClass::Latin], 2);
+        assert_eq!(counts[&CharacterClass::Symbol], 1);
+        assert!(!counts.contains_key(&CharacterClass::Other));
+    }
+
+    #[test]
+    fn breakdown_total_matches_char_count() {
+        let text = "「吾輩は猫である。名前はまだ無い。」\nI am a cat.";
+        let total: u64 = breakdown(text).values().sum();
+        assert_eq!(total, text.chars().count() as u64);
+    }
+}
@@ -0,0 +1,526 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+
+use rusqlite::{Connection, OpenFlags};
+
+use crate::{
+    constants::{DATABASE_BUSY_TIMEOUT, DATABASE_READ_CONNECTIONS},
+    Error,
+};
+
+/// The SQLite database. Queries run on tokio's blocking threads, so a slow query doesn't hold up the other commands.
+/// Writes go through a single connection, reads are spread over read-only connections that WAL mode lets run during a write.
+#[derive(Clone)]
+pub struct Database {
+    writer: Arc<Mutex<Connection>>,
+    readers: Arc<Vec<Mutex<Connection>>>,
+    next_reader: Arc<AtomicUsize>,
+}
+
+impl Database {
+    /// Opens the database file, creating it and its tables if needed
+    pub fn open(path: &str) -> rusqlite::Result<Database> {
+        let writer = Connection::open(path)?;
+        writer.pragma_update(None, "journal_mode", "WAL")?;
+        writer.pragma_update(None, "synchronous", "NORMAL")?;
+        writer.busy_timeout(DATABASE_BUSY_TIMEOUT)?;
+        create_schema(&writer)?;
+
+        let mut readers = Vec::with_capacity(DATABASE_READ_CONNECTIONS);
+        for _ in 0..DATABASE_READ_CONNECTIONS {
+            let reader = Connection::open_with_flags(
+                path,
+                OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+            )?;
+            reader.busy_timeout(DATABASE_BUSY_TIMEOUT)?;
+            readers.push(Mutex::new(reader));
+        }
+
+        Ok(Database {
+            writer: Arc::new(Mutex::new(writer)),
+            readers: Arc::new(readers),
+            next_reader: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    /// Moves the write-ahead log into the database file and empties it, so the file alone has every write
+    pub async fn checkpoint(&self) -> Result<(), Error> {
+        self.write(|connection| {
+            connection.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Runs `f` with the write connection on a blocking thread. Writes are serialized, so keep `f` short.
+    pub async fn write<T, F>(&self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut Connection) -> Result<T, Error> + Send + 'static,
+        T: Send + 'static,
+    {
+        let writer = self.writer.clone();
+        tokio::task::spawn_blocking(move || f(&mut writer.lock().unwrap())).await?
+    }
+
+    /// Runs `f` with one of the read-only connections on a blocking thread, anything that writes fails
+    pub async fn read<T, F>(&self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut Connection) -> Result<T, Error> + Send + 'static,
+        T: Send + 'static,
+    {
+        let readers = self.readers.clone();
+        let index = self.next_reader.fetch_add(1, Ordering::Relaxed) % readers.len();
+        tokio::task::spawn_blocking(move || f(&mut readers[index].lock().unwrap())).await?
+    }
+}
+
+/// Creates the tables that don't exist yet
+pub fn create_schema(connection: &Connection) -> rusqlite::Result<()> {
+    // Setup migration
+    connection.execute(
+        "
+-- Create the CharacterStatistics table
+CREATE TABLE IF NOT EXISTS CharacterStatistics (
+    user_id INTEGER PRIMARY KEY, -- the discord id of the user
+    total_characters INTEGER NOT NULL,
+    is_active INTEGER NOT NULL DEFAULT 1, -- 1 = TRUE, 0 = FALSE
+    name TEXT NOT NULL DEFAULT 'UNKNOWN', -- the global display name
+    last_seen INTEGER, -- Unix timestamp of the last time the user was seen on the server
+    nickname TEXT -- the server nickname, shown on the leaderboard instead of the name
+);    
+    ",
+        (),
+    )?;
+    add_column_if_missing(connection, "CharacterStatistics", "last_seen", "INTEGER")?;
+    add_column_if_missing(connection, "CharacterStatistics", "nickname", "TEXT")?;
+
+    connection.execute(
+        "
+-- Create the CharacterLogEntry table
+CREATE TABLE IF NOT EXISTS CharacterLogEntry (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL, -- Foreign key linking to CharacterStatistics
+    characters INTEGER NOT NULL,
+    time INTEGER NOT NULL, -- Store timestamp as Unix timestamp (64bits in SQLite)
+    notes TEXT, -- Optional field for notes
+    media_type TEXT, -- what was read, i.e. 'anime', NULL if unknown
+    FOREIGN KEY (user_id) REFERENCES CharacterStatistics (user_id)
+);
+    ",
+        (),
+    )?;
+    add_column_if_missing(connection, "CharacterLogEntry", "media_type", "TEXT")?;
+
+    connection.execute(
+        "
+-- Create the JobRun table, it keeps the last run of every scheduled job across restarts
+CREATE TABLE IF NOT EXISTS JobRun (
+    job TEXT PRIMARY KEY, -- the name of the job, i.e. 'backup'
+    time INTEGER NOT NULL, -- Unix timestamp of when the last run started
+    error TEXT -- why the last run failed, NULL if it succeeded
+);
+    ",
+        (),
+    )?;
+
+    // older databases kept the last active users refresh in a Metadata table
+    let has_metadata: bool = connection.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'Metadata')",
+        (),
+        |row| row.get(0),
+    )?;
+    if has_metadata {
+        connection.execute(
+            "
+INSERT OR IGNORE INTO JobRun (job, time)
+SELECT 'active_users', last_active_status_refresh FROM Metadata
+    ",
+            (),
+        )?;
+        connection.execute("DROP TABLE Metadata", ())?;
+    }
+
+    connection.execute(
+        "
+-- Create the QuizAttempt table
+CREATE TABLE IF NOT EXISTS QuizAttempt (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL, -- the discord id of the quiz taker
+    quiz TEXT NOT NULL, -- the quiz role name, i.e. 'Quiz 1'
+    score INTEGER NOT NULL,
+    passed INTEGER NOT NULL, -- 1 = TRUE, 0 = FALSE, only TRUE when the attempt granted the role
+    time INTEGER NOT NULL -- Unix timestamp of the kotoba game report
+);
+    ",
+        (),
+    )?;
+
+    connection.execute(
+        "
+-- Create the ImportedEntry table, it remembers what was imported from other tools so it isn't logged twice
+CREATE TABLE IF NOT EXISTS ImportedEntry (
+    user_id INTEGER NOT NULL,
+    source TEXT NOT NULL, -- the tool it was imported from, i.e. 'ttu'
+    key TEXT NOT NULL, -- identifies the record inside the source, i.e. the day and book title
+    characters INTEGER NOT NULL, -- the characters imported so far for the record
+    time INTEGER NOT NULL, -- Unix timestamp of the last import
+    PRIMARY KEY (user_id, source, key)
+);
+    ",
+        (),
+    )?;
+
+    connection.execute(
+        "
+-- Create the PendingLogEntry table, logs above the moderation thresholds wait here until a moderator approves them
+CREATE TABLE IF NOT EXISTS PendingLogEntry (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL, -- the discord id of the user who logged
+    name TEXT NOT NULL, -- the display name when logging
+    characters INTEGER NOT NULL,
+    time INTEGER NOT NULL, -- Unix timestamp of the log, kept when approved
+    notes TEXT,
+    media_type TEXT
+);
+    ",
+        (),
+    )?;
+
+    connection.execute(
+        "
+-- Create the AuditLog table, it records every change admins make to other users' characters
+CREATE TABLE IF NOT EXISTS AuditLog (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    actor_id INTEGER NOT NULL, -- the discord id of the admin, 0 for the command line
+    target_id INTEGER NOT NULL, -- the discord id of the changed user
+    action TEXT NOT NULL, -- i.e. 'edit_characters'
+    delta INTEGER NOT NULL, -- the change of the target's total characters
+    previous_total INTEGER NOT NULL,
+    reason TEXT NOT NULL,
+    log_entry_id INTEGER, -- the CharacterLogEntry made by the change, if any
+    time INTEGER NOT NULL -- Unix timestamp of the change
+);
+    ",
+        (),
+    )?;
+
+    connection.execute(
+        "
+-- Create the MigrationBatch table, it records which files of the old bot were migrated so the same file is refused the second time
+CREATE TABLE IF NOT EXISTS MigrationBatch (
+    id TEXT PRIMARY KEY, -- hash of the migrated file
+    entries INTEGER NOT NULL, -- the amount of log entries in the file
+    time INTEGER NOT NULL -- Unix timestamp of the migration
+);
+    ",
+        (),
+    )?;
+
+    connection.execute(
+        "
+-- Create the NameHistory table
+CREATE TABLE IF NOT EXISTS NameHistory (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL, -- the discord id of the renamed user
+    global_name TEXT NOT NULL,
+    nickname TEXT, -- the server nickname, NULL if they had none
+    time INTEGER NOT NULL -- Unix timestamp of when the names were first seen
+);
+    ",
+        (),
+    )?;
+
+    connection.execute(
+        "
+-- Create the UserSettings table, users without a row have the default settings
+CREATE TABLE IF NOT EXISTS UserSettings (
+    user_id INTEGER PRIMARY KEY, -- the discord id of the user
+    timezone TEXT NOT NULL DEFAULT 'UTC', -- IANA name, i.e. 'America/New_York'
+    default_media_type TEXT, -- i.e. 'anime', NULL if none
+    history_visible INTEGER NOT NULL DEFAULT 1, -- 1 = TRUE, 0 = FALSE
+    show_on_leaderboard INTEGER NOT NULL DEFAULT 1, -- 1 = TRUE, 0 = FALSE
+    reminder_dms INTEGER NOT NULL DEFAULT 1, -- 1 = TRUE, 0 = FALSE
+    review_dms INTEGER NOT NULL DEFAULT 1 -- 1 = TRUE, 0 = FALSE
+);
+    ",
+        (),
+    )?;
+
+    connection.execute(
+        "
+-- Create the Reminder table, it keeps the opt-in reminders to log that are sent as DMs
+CREATE TABLE IF NOT EXISTS Reminder (
+    user_id INTEGER PRIMARY KEY, -- the discord id of the reminded user
+    time TEXT NOT NULL, -- the time of day in their timezone from UserSettings, i.e. '21:00'
+    frequency TEXT NOT NULL, -- i.e. 'weekdays'
+    only_if_not_logged INTEGER NOT NULL, -- 1 = TRUE, 0 = FALSE
+    last_sent INTEGER -- Unix timestamp of when the reminder was last sent or skipped
+);
+    ",
+        (),
+    )?;
+
+    Ok(())
+}
+
+fn has_column(connection: &Connection, table: &str, column: &str) -> rusqlite::Result<bool> {
+    let mut stmt = connection.prepare(&format!("PRAGMA table_info({})", table))?;
+    let columns = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<rusqlite::Result<Vec<String>>>()?;
+    Ok(columns.iter().any(|name| name == column))
+}
+
+/// Adds a column to a table that was created before the column existed
+fn add_column_if_missing(
+    connection: &Connection,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> rusqlite::Result<()> {
+    if !has_column(connection, table, column)? {
+        connection.execute(
+            &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+            (),
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf, sync::mpsc, time::Duration};
+
+    use super::*;
+    use crate::repository::{
+        CharacterStatisticsRepository, MetadataRepository, SQLiteCharacterStatisticsRepository,
+        SQLiteMetadataRepository,
+    };
+
+    /// A database file that is deleted with its WAL files when dropped
+    struct TestDatabase {
+        path: PathBuf,
+        database: Database,
+    }
+
+    impl TestDatabase {
+        fn new(name: &str) -> TestDatabase {
+            let path = std::env::temp_dir().join(format!(
+                "immersion-bot-{}-{}.db",
+                name,
+                std::process::id()
+            ));
+            let database = Database::open(path.to_str().unwrap()).unwrap();
+            TestDatabase { path, database }
+        }
+    }
+
+    impl Drop for TestDatabase {
+        fn drop(&mut self) {
+            for suffix in ["", "-wal", "-shm"] {
+                let _ = fs::remove_file(format!("{}{}", self.path.display(), suffix));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_see_committed_writes() {
+        let test = TestDatabase::new("reads");
+        let journal_mode: String = test
+            .database
+            .read(|connection| {
+                Ok(connection.query_row("PRAGMA journal_mode", [], |row| row.get(0))?)
+            })
+            .await
+            .unwrap();
+        assert_eq!(journal_mode, "wal");
+
+        test.database
+            .write(|connection| {
+                let tx = connection.transaction()?;
+                SQLiteCharacterStatisticsRepository::new(&tx).add_log_entry(
+                    1,
+                    "user",
+                    100,
+                    &chrono::Utc::now(),
+                    None,
+                )?;
+                tx.commit()?;
+                Ok(())
+            })
+            .await
+            .unwrap();
+
+        // every reader sees the write, not just the next one
+        for _ in 0..DATABASE_READ_CONNECTIONS {
+            let statistics = test
+                .database
+                .read(|connection| {
+                    let tx = connection.transaction()?;
+                    SQLiteCharacterStatisticsRepository::new(&tx).get_statistics(1)
+                })
+                .await
+                .unwrap();
+            assert_eq!(statistics.unwrap().total_characters, 100);
+        }
+    }
+
+    #[tokio::test]
+    async fn checkpoint_empties_the_write_ahead_log() {
+        let test = TestDatabase::new("checkpoint");
+        test.database
+            .write(|connection| {
+                let tx = connection.transaction()?;
+                SQLiteMetadataRepository::new(&tx).add_migration_batch(
+                    "batch",
+                    1,
+                    &chrono::Utc::now(),
+                )?;
+                tx.commit()?;
+                Ok(())
+            })
+            .await
+            .unwrap();
+        let wal = format!("{}-wal", test.path.display());
+        assert!(fs::metadata(&wal).unwrap().len() > 0);
+
+        test.database.checkpoint().await.unwrap();
+        assert_eq!(fs::metadata(&wal).unwrap().len(), 0);
+        // the file alone has the write now
+        let connection = Connection::open_with_flags(
+            &test.path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )
+        .unwrap();
+        let batches: i64 = connection
+            .query_row("SELECT COUNT(*) FROM MigrationBatch", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(batches, 1);
+    }
+
+    #[tokio::test]
+    async fn readers_cant_write() {
+        let test = TestDatabase::new("readonly");
+        let result = test
+            .database
+            .read(|connection| {
+                let tx = connection.transaction()?;
+                SQLiteMetadataRepository::new(&tx).clear_job_run("backup")?;
+                tx.commit()?;
+                Ok(())
+            })
+            .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn reads_dont_wait_for_writes() {
+        let test = TestDatabase::new("concurrent");
+        let (started_sender, started) = mpsc::channel();
+        let (finish, finish_receiver) = mpsc::channel::<()>();
+
+        // hold a write transaction open until the read is done
+        let database = test.database.clone();
+        let write = tokio::spawn(async move {
+            database
+                .write(move |connection| {
+                    let tx = connection.transaction()?;
+                    SQLiteCharacterStatisticsRepository::new(&tx)
+                        .get_or_initialize_statistics(1, "user")?;
+                    started_sender.send(()).unwrap();
+                    finish_receiver.recv().unwrap();
+                    tx.commit()?;
+                    Ok(())
+                })
+                .await
+        });
+        started.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        let exists = test
+            .database
+            .read(|connection| {
+                let tx = connection.transaction()?;
+                SQLiteCharacterStatisticsRepository::new(&tx).exists(1)
+            })
+            .await
+            .unwrap();
+        // the uncommitted write isn't visible yet
+        assert!(!exists);
+
+        finish.send(()).unwrap();
+        write.await.unwrap().unwrap();
+    }
+
+    #[test]
+    fn create_schema_adds_missing_columns() {
+        let connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute(
+                "CREATE TABLE CharacterStatistics (
+                    user_id INTEGER PRIMARY KEY,
+                    total_characters INTEGER NOT NULL,
+                    is_active INTEGER NOT NULL DEFAULT 1,
+                    name TEXT NOT NULL DEFAULT 'UNKNOWN'
+                )",
+                (),
+            )
+            .unwrap();
+        connection
+            .execute(
+                "INSERT INTO CharacterStatistics (user_id, total_characters) VALUES (1, 100)",
+                (),
+            )
+            .unwrap();
+
+        // running it twice doesn't try to add the column again
+        create_schema(&connection).unwrap();
+        create_schema(&connection).unwrap();
+
+        let last_seen: Option<i64> = connection
+            .query_row(
+                "SELECT last_seen FROM CharacterStatistics WHERE user_id = 1",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(last_seen, None);
+    }
+
+    #[test]
+    fn create_schema_moves_the_last_refresh_to_the_job_runs() {
+        let connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute(
+                "CREATE TABLE Metadata (last_active_status_refresh INTEGER NOT NULL)",
+                (),
+            )
+            .unwrap();
+        connection
+            .execute(
+                "INSERT INTO Metadata (last_active_status_refresh) VALUES (1700000000)",
+                (),
+            )
+            .unwrap();
+
+        create_schema(&connection).unwrap();
+
+        let time: i64 = connection
+            .query_row(
+                "SELECT time FROM JobRun WHERE job = 'active_users'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(time, 1_700_000_000);
+        let tables: u64 = connection
+            .query_row(
+                "SELECT COUNT(*) FROM sqlite_master WHERE name = 'Metadata'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(tables, 0);
+    }
+}
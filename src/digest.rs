@@ -0,0 +1,499 @@
+use std::{cmp::Reverse, collections::HashMap, env::var};
+
+use chrono::{DateTime, Datelike, Duration, Months, NaiveTime, Utc};
+use serenity::all::{ChannelId, CreateEmbed, CreateMessage, GuildId, UserId};
+
+use crate::{
+    commands::create_base_embed,
+    constants::DIGEST_LIST_SIZE,
+    model::CharacterStatistics,
+    repository::{CharacterStatisticsRepository, SQLiteCharacterStatisticsRepository},
+    roles::{QuizRoles, Roles, UserRoles},
+    scheduler::JobContext,
+    utils::format_with_commas,
+    Error,
+};
+
+/// How much time a digest covers, the periods start at midnight UTC
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DigestPeriod {
+    Day,
+    Week,
+    Month,
+}
+
+impl DigestPeriod {
+    pub fn parse(string: &str) -> Option<DigestPeriod> {
+        match string {
+            "day" => Some(Self::Day),
+            "week" => Some(Self::Week),
+            "month" => Some(Self::Month),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Day => "day",
+            Self::Week => "week",
+            Self::Month => "month",
+        }
+    }
+
+    /// The default schedule of the digest job, right after a period ends
+    pub fn schedule(&self) -> &'static str {
+        match self {
+            Self::Day => "daily 00:00",
+            Self::Week => "weekly mon 00:00",
+            Self::Month => "monthly 00:00",
+        }
+    }
+
+    /// The start of the period that `time` is in, weeks start on monday
+    pub fn start_of(&self, time: DateTime<Utc>) -> DateTime<Utc> {
+        let date = time.date_naive();
+        let start = match self {
+            Self::Day => date,
+            Self::Week => date - Duration::days(date.weekday().num_days_from_monday().into()),
+            Self::Month => date.with_day(1).unwrap(),
+        };
+        start.and_time(NaiveTime::MIN).and_utc()
+    }
+
+    /// The start of the period before the one starting at `start`
+    pub fn previous_start(&self, start: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            Self::Day => start - Duration::days(1),
+            Self::Week => start - Duration::weeks(1),
+            Self::Month => start.checked_sub_months(Months::new(1)).unwrap(),
+        }
+    }
+}
+
+/// The channel the digest is posted to, the digest is off without it
+pub fn digest_channel() -> Option<ChannelId> {
+    var("DIGEST_CHANNEL_ID")
+        .ok()
+        .and_then(|id| id.parse().ok())
+        .map(ChannelId::new)
+}
+
+/// The period from the DIGEST_PERIOD env var (day, week or month), a week if it isn't set
+pub fn digest_period() -> Result<DigestPeriod, Error> {
+    match var("DIGEST_PERIOD") {
+        Err(_) => Ok(DigestPeriod::Week),
+        Ok(period) => DigestPeriod::parse(&period)
+            .ok_or_else(|| format!("DIGEST_PERIOD is not day, week or month: {}", period).into()),
+    }
+}
+
+/// A member who moved up the leaderboard during the period
+#[derive(Debug)]
+pub struct Climber {
+    pub statistics: CharacterStatistics,
+    /// None if they weren't on the leaderboard before the period
+    pub from: Option<usize>,
+    pub to: usize,
+}
+
+impl Climber {
+    /// How many places they moved up, newcomers count from the end of the leaderboard after the period
+    fn places(&self, ranked_after: usize) -> i64 {
+        self.from.unwrap_or(ranked_after + 1) as i64 - self.to as i64
+    }
+}
+
+#[derive(Debug)]
+pub struct Digest {
+    pub period: DigestPeriod,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    /// the members who logged the most, with the characters of the period as their total
+    pub top: Vec<CharacterStatistics>,
+    pub climbers: Vec<Climber>,
+    /// the members whose characters reached a higher role during the period
+    pub new_roles: Vec<(CharacterStatistics, Roles)>,
+    pub characters: i64,
+    pub readers: usize,
+    pub previous_characters: i64,
+    pub previous_readers: usize,
+}
+
+/// The leaderboard positions by user id, for totals sorted descendingly
+fn ranks(totals: &[CharacterStatistics]) -> HashMap<u64, usize> {
+    totals
+        .iter()
+        .filter(|statistics| statistics.total_characters > 0)
+        .enumerate()
+        .map(|(index, statistics)| (statistics.get_user_id(), index + 1))
+        .collect()
+}
+
+/// The characters logged and how many members logged any
+fn sum(totals: &[CharacterStatistics]) -> (i64, usize) {
+    let characters = totals.iter().map(|user| user.total_characters).sum();
+    let readers = totals
+        .iter()
+        .filter(|user| user.total_characters > 0)
+        .count();
+    (characters, readers)
+}
+
+/// Computes the digest of the last full period before `now` from the log entries.
+/// `quiz_roles` are the quizzes each member passed, the roles gated by other quizzes aren't counted as new roles.
+pub fn compute_digest(
+    repository: &mut impl CharacterStatisticsRepository,
+    quiz_roles: &HashMap<u64, Vec<QuizRoles>>,
+    period: DigestPeriod,
+    now: &DateTime<Utc>,
+) -> Result<Digest, Error> {
+    let end = period.start_of(*now);
+    let start = period.previous_start(end);
+    let previous_start = period.previous_start(start);
+
+    let during = repository.get_log_totals_between(Some(&start), &end)?;
+    let previous = repository.get_log_totals_between(Some(&previous_start), &start)?;
+    let totals_before = repository.get_log_totals_between(None, &start)?;
+    let totals_after = repository.get_log_totals_between(None, &end)?;
+
+    let ranks_before = ranks(&totals_before);
+    let totals_before: HashMap<u64, i64> = totals_before
+        .iter()
+        .map(|user| (user.get_user_id(), user.total_characters))
+        .collect();
+
+    let ranked_after = ranks(&totals_after).len();
+    let mut climbers = Vec::new();
+    let mut new_roles = Vec::new();
+    for (index, user) in totals_after.into_iter().enumerate() {
+        if user.total_characters <= 0 {
+            break;
+        }
+        let total_before = totals_before.get(&user.get_user_id()).copied().unwrap_or(0);
+        // the current quiz roles are used for both, a quiz passed during the period only adds its role when the characters did too
+        let quizzes = quiz_roles
+            .get(&user.get_user_id())
+            .map_or(&[][..], Vec::as_slice);
+        let role_before = Roles::from_characters_and_quiz_roles(quizzes, total_before);
+        if let Some(role) = Roles::from_characters_and_quiz_roles(quizzes, user.total_characters) {
+            // the roles aren't ordered by their characters, so compare the totals instead
+            if Some(role) != role_before && user.total_characters > total_before {
+                new_roles.push((
+                    CharacterStatistics::new(
+                        user.get_user_id(),
+                        user.total_characters,
+                        user.name.clone(),
+                    ),
+                    role,
+                ));
+            }
+        }
+
+        let climber = Climber {
+            from: ranks_before.get(&user.get_user_id()).copied(),
+            to: index + 1,
+            statistics: user,
+        };
+        if climber.from.is_none_or(|from| from > climber.to) {
+            climbers.push(climber);
+        }
+    }
+    climbers.sort_by_key(|climber| (Reverse(climber.places(ranked_after)), climber.to));
+    climbers.truncate(DIGEST_LIST_SIZE);
+    new_roles.sort_by_key(|(user, _)| Reverse(user.total_characters));
+    new_roles.truncate(DIGEST_LIST_SIZE);
+
+    let (characters, readers) = sum(&during);
+    let (previous_characters, previous_readers) = sum(&previous);
+    let top = during
+        .into_iter()
+        .filter(|user| user.total_characters > 0)
+        .take(DIGEST_LIST_SIZE)
+        .collect();
+
+    Ok(Digest {
+        period,
+        start,
+        end,
+        top,
+        climbers,
+        new_roles,
+        characters,
+        readers,
+        previous_characters,
+        previous_readers,
+    })
+}
+
+fn format_comparison(digest: &Digest) -> String {
+    let period = digest.period.name();
+    if digest.previous_characters <= 0 {
+        return format!("Nothing was logged the {} before.", period);
+    }
+    let change = (digest.characters - digest.previous_characters) as f64
+        / digest.previous_characters as f64
+        * 100.0;
+    format!(
+        "{:+.1}% compared to the {} before, when {} members read {} characters.",
+        change,
+        period,
+        digest.previous_readers,
+        format_with_commas(digest.previous_characters)
+    )
+}
+
+pub fn digest_embed(digest: &Digest) -> CreateEmbed {
+    let title = match digest.period {
+        DigestPeriod::Day => "Daily digest",
+        DigestPeriod::Week => "Weekly digest",
+        DigestPeriod::Month => "Monthly digest",
+    };
+    let description = format!(
+        "<t:{}:D> to <t:{}:D>\n{} members read {} characters this {}.\n{}",
+        digest.start.timestamp(),
+        (digest.end - Duration::seconds(1)).timestamp(),
+        digest.readers,
+        format_with_commas(digest.characters),
+        digest.period.name(),
+        format_comparison(digest)
+    );
+
+    let mut top = "".to_owned();
+    for (index, user) in digest.top.iter().enumerate() {
+        top += &format!(
+            "{}. {}: {} characters\n",
+            index + 1,
+            user.name,
+            format_with_commas(user.total_characters)
+        );
+    }
+    if top.is_empty() {
+        top = format!("Nobody logged this {}.", digest.period.name());
+    }
+
+    let mut climbers = "".to_owned();
+    for climber in digest.climbers.iter() {
+        let from = match climber.from {
+            Some(from) => format!("#{}", from),
+            None => "unranked".to_owned(),
+        };
+        climbers += &format!("{}: {} → #{}\n", climber.statistics.name, from, climber.to);
+    }
+    if climbers.is_empty() {
+        climbers = "Nobody moved up.".to_owned();
+    }
+
+    let mut new_roles = "".to_owned();
+    for (user, role) in digest.new_roles.iter() {
+        new_roles += &format!("{}: {}\n", user.name, role);
+    }
+    if new_roles.is_empty() {
+        new_roles = "Nobody reached a new role.".to_owned();
+    }
+
+    create_base_embed()
+        .title(title)
+        .description(description)
+        .field("Top readers", top, false)
+        .field("Biggest climbers", climbers, false)
+        .field("New roles", new_roles, false)
+}
+
+/// Posts the digest of the last period to the digest channel
+pub async fn post_digest(context: &JobContext) -> Result<(), Error> {
+    let channel = digest_channel().ok_or("DIGEST_CHANNEL_ID is not set.")?;
+    let period = digest_period()?;
+    let now = context.clock.now();
+    let guild_id = channel
+        .to_channel(&context.serenity)
+        .await?
+        .guild()
+        .ok_or("DIGEST_CHANNEL_ID is not a channel of a server.")?
+        .guild_id;
+    let quiz_roles = member_quiz_roles(&context.serenity, guild_id).await?;
+    let digest = context
+        .database
+        .read(move |connection| {
+            let tx = connection.transaction()?;
+            let mut repository = SQLiteCharacterStatisticsRepository::new(&tx);
+            compute_digest(&mut repository, &quiz_roles, period, &now)
+        })
+        .await?;
+
+    channel
+        .send_message(
+            &context.serenity.http,
+            CreateMessage::new().embed(digest_embed(&digest)),
+        )
+        .await?;
+    Ok(())
+}
+
+/// The quiz roles of every member of the guild by user id
+async fn member_quiz_roles(
+    ctx: &serenity::client::Context,
+    guild_id: GuildId,
+) -> Result<HashMap<u64, Vec<QuizRoles>>, Error> {
+    let guild_roles = guild_id.roles(ctx).await?;
+    let mut after: Option<UserId> = None;
+    let mut quiz_roles = HashMap::new();
+    loop {
+        let members = guild_id.members(ctx, None, after).await?;
+        let Some(last) = members.last() else {
+            break;
+        };
+        after = Some(last.user.id);
+        for member in members.iter() {
+            let roles = UserRoles::new(&member.roles, &guild_roles);
+            quiz_roles.insert(member.user.id.get(), roles.quizzes);
+        }
+    }
+    Ok(quiz_roles)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::memory_repository::MemoryCharacterStatisticsRepository;
+
+    /// Wednesday 2023-11-15 12:00:00 UTC
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2023, 11, 15, 12, 0, 0).unwrap()
+    }
+
+    fn day(day: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2023, 11, day, 12, 0, 0).unwrap()
+    }
+
+    fn log(
+        repository: &mut MemoryCharacterStatisticsRepository,
+        user_id: u64,
+        characters: i64,
+        time: DateTime<Utc>,
+    ) {
+        repository
+            .add_log_entry(
+                user_id,
+                &format!("user {}", user_id),
+                characters,
+                &time,
+                None,
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn periods() {
+        let midnight = |month, day| Utc.with_ymd_and_hms(2023, month, day, 0, 0, 0).unwrap();
+        assert_eq!(DigestPeriod::Day.start_of(now()), midnight(11, 15));
+        assert_eq!(DigestPeriod::Week.start_of(now()), midnight(11, 13));
+        assert_eq!(DigestPeriod::Month.start_of(now()), midnight(11, 1));
+
+        assert_eq!(
+            DigestPeriod::Week.previous_start(midnight(11, 13)),
+            midnight(11, 6)
+        );
+        assert_eq!(
+            DigestPeriod::Month.previous_start(midnight(11, 1)),
+            midnight(10, 1)
+        );
+    }
+
+    #[test]
+    fn weekly_digest() {
+        let mut repository = MemoryCharacterStatisticsRepository::new();
+        // the week before last
+        log(&mut repository, 1, 50_000, day(1));
+        // before the week
+        log(&mut repository, 1, 200_000, day(5));
+        log(&mut repository, 2, 150_000, day(5));
+        log(&mut repository, 3, 10_000, day(5));
+        // the week, monday the 6th to sunday the 12th
+        log(&mut repository, 2, 400_000, day(6));
+        log(&mut repository, 3, 20_000, day(8));
+        log(&mut repository, 4, 5_000, day(12));
+        // the current week isn't part of the digest
+        log(&mut repository, 3, 1_000_000, day(14));
+
+        let quiz_roles = HashMap::from([(2, vec![QuizRoles::Quiz1])]);
+        let digest =
+            compute_digest(&mut repository, &quiz_roles, DigestPeriod::Week, &now()).unwrap();
+
+        assert_eq!(
+            digest.start,
+            Utc.with_ymd_and_hms(2023, 11, 6, 0, 0, 0).unwrap()
+        );
+        assert_eq!(digest.characters, 425_000);
+        assert_eq!(digest.readers, 3);
+        assert_eq!(digest.previous_characters, 410_000);
+        assert_eq!(digest.previous_readers, 3);
+
+        let top: Vec<(u64, i64)> = digest
+            .top
+            .iter()
+            .map(|user| (user.get_user_id(), user.total_characters))
+            .collect();
+        assert_eq!(top, vec![(2, 400_000), (3, 20_000), (4, 5_000)]);
+
+        // 2 overtook 1, 4 joined the leaderboard behind 3
+        let climbers: Vec<(u64, Option<usize>, usize)> = digest
+            .climbers
+            .iter()
+            .map(|climber| (climber.statistics.get_user_id(), climber.from, climber.to))
+            .collect();
+        assert_eq!(climbers, vec![(2, Some(2), 1), (4, None, 4)]);
+
+        let new_roles: Vec<(u64, Roles)> = digest
+            .new_roles
+            .iter()
+            .map(|(user, role)| (user.get_user_id(), *role))
+            .collect();
+        assert_eq!(new_roles, vec![(2, Roles::Danshaku)]);
+
+        // without the quiz, 男爵 is out of reach and 平民 isn't new
+        let digest =
+            compute_digest(&mut repository, &HashMap::new(), DigestPeriod::Week, &now()).unwrap();
+        assert!(digest.new_roles.is_empty());
+    }
+
+    #[test]
+    fn newcomers_climb_from_the_end_of_the_leaderboard() {
+        let mut repository = MemoryCharacterStatisticsRepository::new();
+        log(&mut repository, 1, 100_000, day(5));
+        // three newcomers, one of them overtakes 1
+        log(&mut repository, 2, 200_000, day(6));
+        log(&mut repository, 3, 50_000, day(7));
+        log(&mut repository, 4, 10_000, day(8));
+
+        let digest =
+            compute_digest(&mut repository, &HashMap::new(), DigestPeriod::Week, &now()).unwrap();
+
+        let climbers: Vec<(u64, Option<usize>, usize)> = digest
+            .climbers
+            .iter()
+            .map(|climber| (climber.statistics.get_user_id(), climber.from, climber.to))
+            .collect();
+        assert_eq!(climbers, vec![(2, None, 1), (3, None, 3), (4, None, 4)]);
+        let places: Vec<i64> = digest
+            .climbers
+            .iter()
+            .map(|climber| climber.places(4))
+            .collect();
+        assert_eq!(places, vec![4, 2, 1]);
+    }
+
+    #[test]
+    fn empty_digest() {
+        let mut repository = MemoryCharacterStatisticsRepository::new();
+        let digest =
+            compute_digest(&mut repository, &HashMap::new(), DigestPeriod::Day, &now()).unwrap();
+        assert!(digest.top.is_empty());
+        assert!(digest.climbers.is_empty());
+        assert_eq!(
+            format_comparison(&digest),
+            "Nothing was logged the day before."
+        );
+    }
+}
@@ -0,0 +1,259 @@
+use std::{
+    io::{Cursor, Read},
+    sync::LazyLock,
+};
+
+use regex::Regex;
+use zip::ZipArchive;
+
+use crate::{constants::MAX_EXTRACTED_SIZE, Error};
+
+static HTML_TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<[^>]*>").unwrap());
+static ASS_OVERRIDE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\{[^}]*\}").unwrap());
+// ruby readings and their fallback parentheses, i.e. <rt>かんじ</rt>
+static HTML_RUBY: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?is)<(rt|rp)\b[^>]*>.*?</(rt|rp)>").unwrap());
+static HTML_HEAD: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?is)<head\b[^>]*>.*?</head>").unwrap());
+// aozora bunko style ruby, i.e. ｜漢字《かんじ》
+static AOZORA_RUBY: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"《[^》]*》|｜").unwrap());
+static TIMESTAMP_LINE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"-->").unwrap());
+
+/// The kinds of files we know how to read text from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileKind {
+    Text,
+    Srt,
+    Ass,
+    Vtt,
+    Epub,
+}
+
+impl FileKind {
+    pub fn from_file_name(file_name: &str) -> Option<FileKind> {
+        let extension = file_name.rsplit_once('.')?.1.to_lowercase();
+        match extension.as_str() {
+            "txt" => Some(Self::Text),
+            "srt" => Some(Self::Srt),
+            "ass" | "ssa" => Some(Self::Ass),
+            "vtt" => Some(Self::Vtt),
+            "epub" => Some(Self::Epub),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the readable text of a file, without markup, timestamps or ruby readings
+pub fn extract_text(kind: FileKind, bytes: &[u8]) -> Result<String, Error> {
+    match kind {
+        FileKind::Text => Ok(strip_aozora_ruby(&decode(bytes)?)),
+        FileKind::Srt => Ok(extract_srt(&decode(bytes)?)),
+        FileKind::Ass => Ok(extract_ass(&decode(bytes)?)),
+        FileKind::Vtt => Ok(extract_vtt(&decode(bytes)?)),
+        FileKind::Epub => extract_epub(bytes, MAX_EXTRACTED_SIZE),
+    }
+}
+
+fn decode(bytes: &[u8]) -> Result<String, Error> {
+    let text = std::str::from_utf8(bytes).map_err(|_| "The file must be encoded in UTF-8.")?;
+    Ok(text.trim_start_matches('\u{feff}').to_owned())
+}
+
+fn strip_aozora_ruby(text: &str) -> String {
+    AOZORA_RUBY.replace_all(text, "").into_owned()
+}
+
+/// The text lines of srt and vtt cues. Cues are blocks separated by blank lines, their text follows the timing line,
+/// so cue numbers, vtt identifiers and the blocks without a timing (the header, comments, styles and regions) are skipped.
+fn cue_lines(text: &str) -> Vec<&str> {
+    let mut lines = Vec::new();
+    let mut in_cue_text = false;
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            in_cue_text = false;
+        } else if TIMESTAMP_LINE.is_match(line) {
+            in_cue_text = true;
+        } else if in_cue_text {
+            lines.push(line);
+        }
+    }
+    lines
+}
+
+fn extract_srt(text: &str) -> String {
+    cue_lines(text)
+        .into_iter()
+        .map(|line| {
+            let line = HTML_TAG.replace_all(line, "");
+            ASS_OVERRIDE.replace_all(&line, "").into_owned()
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn extract_vtt(text: &str) -> String {
+    cue_lines(text)
+        .into_iter()
+        .map(|line| {
+            let line = HTML_RUBY.replace_all(line, "");
+            HTML_TAG.replace_all(&line, "").into_owned()
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn extract_ass(text: &str) -> String {
+    let mut lines = Vec::new();
+    for line in text.lines() {
+        let Some(dialogue) = line.trim().strip_prefix("Dialogue:") else {
+            continue;
+        };
+        // the text is everything after the 9th comma, it can contain commas itself
+        let Some(dialogue_text) = dialogue.splitn(10, ',').nth(9) else {
+            continue;
+        };
+        let dialogue_text = ASS_OVERRIDE.replace_all(dialogue_text, "");
+        lines.push(
+            dialogue_text
+                .replace("\\N", "\n")
+                .replace("\\n", "\n")
+                .replace("\\h", " "),
+        );
+    }
+    lines.join("\n")
+}
+
+fn extract_html(html: &str) -> String {
+    let html = HTML_HEAD.replace_all(html, "");
+    let html = HTML_RUBY.replace_all(&html, "");
+    let text = HTML_TAG.replace_all(&html, "");
+    text.replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Whether a chapter of an epub is its table of contents, which only repeats the chapter titles
+fn is_navigation_document(name: &str) -> bool {
+    let file_name = name.rsplit('/').next().unwrap_or(name);
+    let stem = file_name.split('.').next().unwrap_or(file_name);
+    matches!(stem, "nav" | "toc")
+}
+
+/// Returns the text of the chapters, failing once they unpack to more than max_size bytes
+fn extract_epub(bytes: &[u8], max_size: u64) -> Result<String, Error> {
+    let mut archive =
+        ZipArchive::new(Cursor::new(bytes)).map_err(|_| "The file isn't a valid epub.")?;
+
+    // the chapters are compressed, so a small file can unpack to far more than it looks like
+    let mut remaining = max_size;
+    let mut text = String::new();
+    for index in 0..archive.len() {
+        let file = archive.by_index(index)?;
+        let name = file.name().to_lowercase();
+        if !(name.ends_with(".xhtml") || name.ends_with(".html") || name.ends_with(".htm"))
+            || is_navigation_document(&name)
+        {
+            continue;
+        }
+
+        let mut html = Vec::new();
+        file.take(remaining + 1).read_to_end(&mut html)?;
+        if html.len() as u64 > remaining {
+            return Err("The epub is too large once unpacked.".into());
+        }
+        remaining -= html.len() as u64;
+
+        let html = String::from_utf8(html).map_err(|_| "The epub must be encoded in UTF-8.")?;
+        text += &extract_html(&html);
+        text.push('\n');
+    }
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use zip::{write::SimpleFileOptions, ZipWriter};
+
+    use super::*;
+
+    fn epub(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, content) in files {
+            writer
+                .start_file(*name, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn srt_keeps_only_the_cue_text() {
+        let srt = "\u{feff}1\r\n00:00:01,000 --> 00:00:02,000\r\n<i>こんにちは</i>\r\n{\\an8}元気？\r\n\r\n2\r\n00:00:03,000 --> 00:00:04,000\r\n100\r\n";
+        assert_eq!(
+            extract_text(FileKind::Srt, srt.as_bytes()).unwrap(),
+            "こんにちは\n元気？\n100"
+        );
+    }
+
+    #[test]
+    fn vtt_skips_identifiers_comments_and_styles() {
+        let vtt = "WEBVTT - 第一話\n\nNOTE 翻訳メモ\n\nSTYLE\n::cue { color: white }\n\nopening-1\n00:01.000 --> 00:02.000 align:start\n<v 先生><ruby>漢字<rt>かんじ</rt></ruby>です\n\n2\n00:03.000 --> 00:04.000\nはい\n";
+        assert_eq!(
+            extract_text(FileKind::Vtt, vtt.as_bytes()).unwrap(),
+            "漢字です\nはい"
+        );
+    }
+
+    #[test]
+    fn ass_keeps_the_dialogue_text() {
+        let ass = "[Script Info]\nTitle: 字幕\n\n[Events]\nFormat: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\nComment: 0,0:00:00.00,0:00:01.00,Default,,0,0,0,,メモ\nDialogue: 0,0:00:01.00,0:00:02.00,Default,,0,0,0,,{\\i1}はい、{\\i0}そうです\\Nまた明日\n";
+        assert_eq!(
+            extract_text(FileKind::Ass, ass.as_bytes()).unwrap(),
+            "はい、そうです\nまた明日"
+        );
+    }
+
+    #[test]
+    fn text_strips_aozora_ruby() {
+        let text = "｜青空《あおぞら》文庫の漢字《かんじ》";
+        assert_eq!(
+            extract_text(FileKind::Text, text.as_bytes()).unwrap(),
+            "青空文庫の漢字"
+        );
+        assert!(extract_text(FileKind::Text, &[0xff, 0xfe]).is_err());
+    }
+
+    #[test]
+    fn epub_strips_ruby_and_markup() {
+        let bytes = epub(&[(
+            "chapter1.xhtml",
+            "<html><head><title>題名</title></head><body><p><ruby>漢字<rp>(</rp><rt>かんじ</rt><rp>)</rp></ruby>&amp;本文</p></body></html>",
+        )]);
+        assert_eq!(extract_text(FileKind::Epub, &bytes).unwrap(), "漢字&本文\n");
+    }
+
+    #[test]
+    fn epub_skips_the_table_of_contents() {
+        let bytes = epub(&[
+            ("OEBPS/nav.xhtml", "<p>第一章</p>"),
+            ("OEBPS/toc.xhtml", "<p>第一章</p>"),
+            ("OEBPS/chapter1.xhtml", "<p>本文</p>"),
+            ("OEBPS/style.css", "p {}"),
+        ]);
+        assert_eq!(extract_text(FileKind::Epub, &bytes).unwrap(), "本文\n");
+    }
+
+    #[test]
+    fn epub_stops_at_the_unpacked_size_limit() {
+        let bytes = epub(&[("chapter1.xhtml", "あいう"), ("chapter2.xhtml", "えお")]);
+        assert!(extract_epub(&bytes, 15).is_ok());
+        assert!(extract_epub(&bytes, 14).is_err());
+    }
+}
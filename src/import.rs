@@ -0,0 +1,706 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    counting::count_characters,
+    model::CharacterLogEntry,
+    repository::ImportRepository,
+    timezone::Timezone,
+    utils::{fnv1a, format_duration},
+    Error,
+};
+
+pub const TTU_SOURCE: &str = "ttu";
+pub const TEXTHOOKER_SOURCE: &str = "texthooker";
+pub const MOKURO_SOURCE: &str = "mokuro";
+pub const FILE_SOURCE: &str = "file";
+
+/// One book on one day, as exported from the statistics page of ttu reader
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TtuStatistic {
+    pub title: String,
+    /// the local day of the statistic, i.e. "2025-03-31"
+    pub date_key: String,
+    pub characters_read: i64,
+    /// in seconds
+    #[serde(default)]
+    pub reading_time: f64,
+}
+
+/// Something read that can be logged, identified by keys that are unique for its source
+#[derive(Debug)]
+pub struct ImportEntry {
+    /// the keys paired with the total amount of characters the source reports for them, not the amount to log.
+    /// Most entries have one, i.e. the book title and day for ttu, texthooker sessions have one per line.
+    pub parts: Vec<(String, i64)>,
+    pub time: DateTime<Utc>,
+    pub notes: String,
+}
+
+/// The entries of an import that haven't been logged yet
+#[derive(Debug)]
+pub struct ImportPlan {
+    /// entries paired with the amount of characters that still need to be logged for them
+    pub entries: Vec<(ImportEntry, i64)>,
+    /// entries that were already fully imported before
+    pub skipped: usize,
+}
+
+impl ImportPlan {
+    /// Compares the entries with what was imported before, so re-importing the same export only logs what's new
+    pub fn new(
+        repository: &impl ImportRepository,
+        user_id: u64,
+        source: &str,
+        entries: Vec<ImportEntry>,
+    ) -> Result<ImportPlan, Error> {
+        let mut plan = ImportPlan {
+            entries: Vec::new(),
+            skipped: 0,
+        };
+
+        for entry in entries {
+            let mut delta = 0;
+            let mut is_new = false;
+            for (key, characters) in entry.parts.iter() {
+                match repository.get_imported_characters(user_id, source, key)? {
+                    // negative parts are corrections, they are logged once like any other part
+                    None if *characters != 0 => {
+                        delta += characters;
+                        is_new = true;
+                    }
+                    Some(imported) if *characters > imported => {
+                        delta += characters - imported;
+                        is_new = true;
+                    }
+                    _ => (),
+                }
+            }
+            if is_new {
+                plan.entries.push((entry, delta));
+            } else {
+                plan.skipped += 1;
+            }
+        }
+
+        plan.entries.sort_by_key(|(entry, _)| entry.time);
+        Ok(plan)
+    }
+
+    /// Skips the rows of a history file that are already in the log history, so importing your own export changes nothing
+    pub fn skip_logged_entries(&mut self, logged: &[CharacterLogEntry]) {
+        let mut logged_keys: HashMap<String, usize> = HashMap::new();
+        for entry in logged {
+            let notes = entry.notes().to_owned().unwrap_or_default();
+            *logged_keys
+                .entry(history_key(entry.time(), entry.characters(), &notes))
+                .or_default() += 1;
+        }
+
+        let before = self.entries.len();
+        // each log entry only accounts for one row, so logging the same thing twice in a file still imports the second one
+        self.entries.retain(|(entry, _)| {
+            let already_logged = entry
+                .parts
+                .iter()
+                .all(|(key, _)| logged_keys.get(key).is_some_and(|remaining| *remaining > 0));
+            if already_logged {
+                for (key, _) in entry.parts.iter() {
+                    *logged_keys.get_mut(key).unwrap() -= 1;
+                }
+            }
+            !already_logged
+        });
+        self.skipped += before - self.entries.len();
+    }
+
+    pub fn total_characters(&self) -> i64 {
+        self.entries.iter().map(|(_, delta)| delta).sum()
+    }
+}
+
+/// Parses a ttu reader statistics export, its days are in the timezone of the user
+pub fn parse_ttu_statistics(bytes: &[u8], timezone: &Timezone) -> Result<Vec<ImportEntry>, Error> {
+    let statistics: Vec<TtuStatistic> = serde_json::from_slice(bytes)
+        .map_err(|e| format!("The file isn't a ttu reader statistics export: {e}"))?;
+
+    let mut entries = Vec::with_capacity(statistics.len());
+    for statistic in statistics {
+        if statistic.characters_read <= 0 {
+            continue;
+        }
+
+        let date = NaiveDate::parse_from_str(&statistic.date_key, "%Y-%m-%d")
+            .map_err(|_| format!("Invalid date in the export: {}", statistic.date_key))?;
+        // ttu only knows the day, noon keeps it away from the ends of the day
+        let time = timezone.local_to_utc(date.and_time(NaiveTime::from_hms_opt(12, 0, 0).unwrap()));
+
+        let reading_time = chrono::Duration::seconds(statistic.reading_time.round() as i64);
+        let notes = if reading_time.num_seconds() > 0 {
+            format!(
+                "{} (ttu, {})",
+                statistic.title,
+                format_duration(&reading_time)
+            )
+        } else {
+            format!("{} (ttu)", statistic.title)
+        };
+
+        entries.push(ImportEntry {
+            parts: vec![(
+                format!("{}/{}", statistic.date_key, statistic.title),
+                statistic.characters_read,
+            )],
+            time,
+            notes,
+        });
+    }
+
+    Ok(entries)
+}
+
+/// One line of a texthooker-ui session, as saved in its exported line data
+#[derive(Debug, Deserialize)]
+pub struct TexthookerLine {
+    /// generated by texthooker-ui when the line is received, it stays the same when the session is saved again
+    pub id: String,
+    pub text: String,
+}
+
+/// Parses a session saved from texthooker-ui, either the exported json line data or the plain text lines.
+/// Every line is imported on its own, so saving the session again later only logs the lines added since.
+pub fn parse_texthooker_session(
+    file_name: &str,
+    bytes: &[u8],
+    title: &str,
+    time: DateTime<Utc>,
+) -> Result<ImportEntry, Error> {
+    let content = std::str::from_utf8(bytes).map_err(|_| "The file must be encoded in UTF-8.")?;
+    let content = content.trim_start_matches('\u{feff}');
+
+    let lines: Vec<TexthookerLine> = if file_name.to_lowercase().ends_with(".json") {
+        serde_json::from_str(content)
+            .map_err(|e| format!("The file isn't a texthooker-ui line export: {e}"))?
+    } else {
+        // plain text has no line ids, the same text is told apart by how often it appeared before
+        let mut occurrences: HashMap<&str, usize> = HashMap::new();
+        content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let occurrence = occurrences.entry(line).or_default();
+                *occurrence += 1;
+                TexthookerLine {
+                    id: format!("{:016x}/{}", fnv1a(line), occurrence),
+                    text: line.to_owned(),
+                }
+            })
+            .collect()
+    };
+
+    let parts: Vec<(String, i64)> = lines
+        .into_iter()
+        .map(|line| (line.id, count_characters(&line.text)))
+        .filter(|(_, characters)| *characters > 0)
+        .collect();
+    if parts.is_empty() {
+        return Err("The session doesn't contain any characters.".into());
+    }
+
+    Ok(ImportEntry {
+        parts,
+        time,
+        notes: format!("{} (texthooker)", title),
+    })
+}
+
+/// The progress of one volume, as saved by mokuro reader
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MokuroVolume {
+    /// the characters read so far in the volume
+    #[serde(default)]
+    pub chars: i64,
+    #[serde(default, alias = "series_title")]
+    pub series_title: Option<String>,
+    #[serde(default, alias = "volume_title")]
+    pub volume_title: Option<String>,
+    #[serde(default)]
+    pub last_progress_update: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum MokuroExport {
+    Wrapped {
+        volumes: HashMap<String, MokuroVolume>,
+    },
+    Volumes(HashMap<String, MokuroVolume>),
+}
+
+/// Parses the volume data exported from mokuro reader, keyed by the volume id.
+/// Each entry holds the total characters read in the volume, the import plan only logs what's new since the last import.
+pub fn parse_mokuro_volumes(
+    bytes: &[u8],
+    fallback_time: DateTime<Utc>,
+) -> Result<Vec<ImportEntry>, Error> {
+    let export: MokuroExport = serde_json::from_slice(bytes)
+        .map_err(|e| format!("The file isn't a mokuro reader volume export: {e}"))?;
+    let volumes = match export {
+        MokuroExport::Wrapped { volumes } => volumes,
+        MokuroExport::Volumes(volumes) => volumes,
+    };
+
+    let mut entries = Vec::with_capacity(volumes.len());
+    for (id, volume) in volumes {
+        if volume.chars <= 0 {
+            continue;
+        }
+
+        let title = match (volume.series_title, volume.volume_title) {
+            (Some(series), Some(volume)) if volume.contains(&series) => volume,
+            (Some(series), Some(volume)) => format!("{series} {volume}"),
+            (Some(title), None) | (None, Some(title)) => title,
+            (None, None) => id.to_owned(),
+        };
+        let time = volume
+            .last_progress_update
+            .and_then(|time| DateTime::parse_from_rfc3339(&time).ok())
+            .map_or(fallback_time, |time| time.to_utc());
+
+        entries.push(ImportEntry {
+            parts: vec![(id, volume.chars)],
+            time,
+            notes: format!("{} (mokuro)", title),
+        });
+    }
+
+    Ok(entries)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, poise::ChoiceParameter)]
+pub enum HistoryFormat {
+    #[name = "csv"]
+    Csv,
+    #[name = "json"]
+    Json,
+}
+
+impl HistoryFormat {
+    pub fn from_file_name(file_name: &str) -> Option<HistoryFormat> {
+        let extension = file_name.rsplit_once('.')?.1.to_lowercase();
+        match extension.as_str() {
+            "csv" => Some(Self::Csv),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Json => "json",
+        }
+    }
+}
+
+/// One row of a history export, the format accepted back by /import file
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HistoryRow {
+    /// RFC 3339, or just the day (YYYY-MM-DD) when importing
+    pub time: String,
+    pub characters: i64,
+    #[serde(default)]
+    pub notes: Option<String>,
+}
+
+impl HistoryRow {
+    fn from_log_entry(entry: &CharacterLogEntry) -> HistoryRow {
+        HistoryRow {
+            time: entry.time().to_rfc3339().unwrap_or_default(),
+            characters: entry.characters(),
+            notes: entry.notes().to_owned(),
+        }
+    }
+
+    /// Days without a time are taken as noon in the timezone
+    fn to_import_entry(&self, timezone: &Timezone) -> Result<Option<ImportEntry>, String> {
+        let time = match DateTime::parse_from_rfc3339(self.time.trim()) {
+            Ok(time) => time.to_utc(),
+            Err(_) => timezone.local_to_utc(
+                NaiveDate::parse_from_str(self.time.trim(), "%Y-%m-%d")
+                    .map_err(|_| format!("invalid time `{}`", self.time))?
+                    .and_time(NaiveTime::from_hms_opt(12, 0, 0).unwrap()),
+            ),
+        };
+        let notes = self.notes.to_owned().unwrap_or_default();
+        // empty rows wouldn't be logged anyway
+        if self.characters == 0 {
+            return Ok(None);
+        }
+
+        let key = history_key(&time, self.characters, &notes);
+        Ok(Some(ImportEntry {
+            parts: vec![(key, self.characters)],
+            time,
+            notes,
+        }))
+    }
+}
+
+/// Identifies a log by its time, characters and notes, the same for a row and the log entry it was exported from
+fn history_key(time: &DateTime<Utc>, characters: i64, notes: &str) -> String {
+    format!(
+        "{:016x}",
+        fnv1a(&format!("{}|{}|{}", time.timestamp(), characters, notes))
+    )
+}
+
+/// Serializes the full log history of a user
+pub fn export_history(
+    entries: &[CharacterLogEntry],
+    format: HistoryFormat,
+) -> Result<Vec<u8>, Error> {
+    let rows: Vec<HistoryRow> = entries.iter().map(HistoryRow::from_log_entry).collect();
+    match format {
+        HistoryFormat::Json => Ok(serde_json::to_vec_pretty(&rows)?),
+        HistoryFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            for row in rows.iter() {
+                writer.serialize(row)?;
+            }
+            Ok(writer.into_inner().map_err(|e| e.to_string())?)
+        }
+    }
+}
+
+/// Parses a history file, either an export of this bot or a spreadsheet with time, characters and notes columns.
+/// Every invalid row is reported with its line number, nothing should be imported if there is any.
+/// Rows with only a day are placed in the timezone of the user.
+pub fn parse_history(
+    bytes: &[u8],
+    format: HistoryFormat,
+    timezone: &Timezone,
+) -> Result<Result<Vec<ImportEntry>, Vec<String>>, Error> {
+    let mut rows: Vec<(usize, Result<HistoryRow, String>)> = Vec::new();
+    match format {
+        HistoryFormat::Json => {
+            let values: Vec<Value> = serde_json::from_slice(bytes)
+                .map_err(|e| format!("The file isn't a json array of logs: {e}"))?;
+            for (index, value) in values.into_iter().enumerate() {
+                let row = serde_json::from_value::<HistoryRow>(value).map_err(|e| e.to_string());
+                rows.push((index + 1, row));
+            }
+        }
+        HistoryFormat::Csv => {
+            let mut reader = csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_reader(bytes);
+            // accept headers in any case, i.e. from spreadsheets
+            let headers: csv::StringRecord =
+                reader.headers()?.iter().map(|h| h.to_lowercase()).collect();
+            for record in reader.records() {
+                // quoted notes can span lines, so the line a record starts on is taken from the reader
+                let (position, row) = match record {
+                    Ok(record) => (
+                        record.position().cloned(),
+                        record
+                            .deserialize::<HistoryRow>(Some(&headers))
+                            .map_err(|e| e.to_string()),
+                    ),
+                    Err(error) => (error.position().cloned(), Err(error.to_string())),
+                };
+                let line = position.map_or(0, |position| position.line() as usize);
+                rows.push((line, row));
+            }
+        }
+    }
+
+    let mut entries = Vec::with_capacity(rows.len());
+    let mut errors = Vec::new();
+    for (line, row) in rows {
+        match row.and_then(|row| row.to_import_entry(timezone)) {
+            Ok(Some(entry)) => entries.push(entry),
+            Ok(None) => (),
+            Err(error) => errors.push(format!("Line {}: {}", line, error)),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(Ok(entries))
+    } else {
+        Ok(Err(errors))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use rusqlite::Connection;
+
+    use serenity::all::Timestamp;
+
+    use super::*;
+    use crate::{database::create_schema, repository::SQLiteImportRepository};
+
+    const TEXTHOOKER_LINES: &[u8] = include_bytes!("../fixtures/texthooker-ui-lines.json");
+
+    fn time() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2025, 3, 1, 12, 0, 0).unwrap()
+    }
+
+    /// Plans the import of some entries and records them as imported, returns the characters that were logged
+    fn import_entries(
+        repository: &mut SQLiteImportRepository,
+        source: &str,
+        entries: Vec<ImportEntry>,
+    ) -> i64 {
+        let plan = ImportPlan::new(repository, 1, source, entries).unwrap();
+        for (entry, _) in plan.entries.iter() {
+            for (key, characters) in entry.parts.iter() {
+                repository
+                    .set_imported_characters(1, source, key, *characters, &time())
+                    .unwrap();
+            }
+        }
+        plan.total_characters()
+    }
+
+    fn import(repository: &mut SQLiteImportRepository, entry: ImportEntry) -> i64 {
+        import_entries(repository, TEXTHOOKER_SOURCE, vec![entry])
+    }
+
+    #[test]
+    fn ttu_statistics() {
+        let export = r#"[
+            {"title": "本", "dateKey": "2025-03-01", "charactersRead": 5000, "readingTime": 3600},
+            {"title": "本", "dateKey": "2025-03-02", "charactersRead": 0, "readingTime": 0},
+            {"title": "別の本", "dateKey": "2025-03-02", "charactersRead": 200}
+        ]"#;
+        let entries = parse_ttu_statistics(export.as_bytes(), &Timezone::utc()).unwrap();
+
+        let entries: Vec<(&str, i64, DateTime<Utc>, &str)> = entries
+            .iter()
+            .map(|entry| {
+                (
+                    entry.parts[0].0.as_str(),
+                    entry.parts[0].1,
+                    entry.time,
+                    entry.notes.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                ("2025-03-01/本", 5_000, time(), "本 (ttu, 1 hour)"),
+                (
+                    "2025-03-02/別の本",
+                    200,
+                    Utc.with_ymd_and_hms(2025, 3, 2, 12, 0, 0).unwrap(),
+                    "別の本 (ttu)"
+                ),
+            ]
+        );
+
+        let invalid = r#"[{"title": "本", "dateKey": "03/01", "charactersRead": 1}]"#;
+        assert!(parse_ttu_statistics(invalid.as_bytes(), &Timezone::utc()).is_err());
+        assert!(parse_ttu_statistics(b"{}", &Timezone::utc()).is_err());
+    }
+
+    #[test]
+    fn ttu_imports_again_only_log_what_was_read_since() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        create_schema(&connection).unwrap();
+        let tx = connection.transaction().unwrap();
+        let mut repository = SQLiteImportRepository::new(&tx);
+
+        let export = |characters: i64| {
+            let export = format!(
+                r#"[
+                    {{"title": "本", "dateKey": "2025-03-01", "charactersRead": 5000}},
+                    {{"title": "本", "dateKey": "2025-03-02", "charactersRead": {characters}}}
+                ]"#
+            );
+            parse_ttu_statistics(export.as_bytes(), &Timezone::utc()).unwrap()
+        };
+        assert_eq!(
+            import_entries(&mut repository, TTU_SOURCE, export(1_000)),
+            6_000
+        );
+        // the same day was read further, the other day is unchanged
+        assert_eq!(
+            import_entries(&mut repository, TTU_SOURCE, export(1_500)),
+            500
+        );
+        assert_eq!(
+            import_entries(&mut repository, TTU_SOURCE, export(1_500)),
+            0
+        );
+    }
+
+    #[test]
+    fn mokuro_volumes() {
+        let wrapped = r#"{"volumes": {
+            "a": {"chars": 1200, "series_title": "漫画", "volume_title": "漫画 01", "lastProgressUpdate": "2025-03-01T12:00:00Z"},
+            "b": {"chars": 300, "seriesTitle": "漫画", "volumeTitle": "02"},
+            "c": {"chars": 0, "volumeTitle": "03"}
+        }}"#;
+        let mut entries = parse_mokuro_volumes(wrapped.as_bytes(), time()).unwrap();
+        entries.sort_by(|a, b| a.parts[0].0.cmp(&b.parts[0].0));
+        let entries: Vec<(&str, i64, &str)> = entries
+            .iter()
+            .map(|entry| {
+                (
+                    entry.parts[0].0.as_str(),
+                    entry.parts[0].1,
+                    entry.notes.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                ("a", 1_200, "漫画 01 (mokuro)"),
+                ("b", 300, "漫画 02 (mokuro)")
+            ]
+        );
+
+        // the volumes without the wrapper, their time falls back to the time of the import
+        let volumes = r#"{"d": {"chars": 10}}"#;
+        let entries = parse_mokuro_volumes(volumes.as_bytes(), time()).unwrap();
+        assert_eq!(entries[0].notes, "d (mokuro)");
+        assert_eq!(entries[0].time, time());
+        assert!(parse_mokuro_volumes(b"[]", time()).is_err());
+    }
+
+    #[test]
+    fn mokuro_imports_again_only_log_what_was_read_since() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        create_schema(&connection).unwrap();
+        let tx = connection.transaction().unwrap();
+        let mut repository = SQLiteImportRepository::new(&tx);
+
+        let export = |characters: i64| {
+            let export = format!(r#"{{"a": {{"chars": {characters}}}, "b": {{"chars": 50}}}}"#);
+            parse_mokuro_volumes(export.as_bytes(), time()).unwrap()
+        };
+        assert_eq!(
+            import_entries(&mut repository, MOKURO_SOURCE, export(100)),
+            150
+        );
+        assert_eq!(
+            import_entries(&mut repository, MOKURO_SOURCE, export(180)),
+            80
+        );
+        // progress that went back isn't logged as negative
+        assert_eq!(
+            import_entries(&mut repository, MOKURO_SOURCE, export(120)),
+            0
+        );
+    }
+
+    #[test]
+    fn texthooker_ui_line_export() {
+        let entry =
+            parse_texthooker_session("lines.json", TEXTHOOKER_LINES, "ゲーム", time()).unwrap();
+        assert_eq!(entry.parts.len(), 3);
+        assert_eq!(entry.parts[0].0, "5f0c3a4e-9d1b-4c2a-8e7f-1a2b3c4d5e6f");
+        assert_eq!(entry.notes, "ゲーム (texthooker)");
+        assert!(parse_texthooker_session("lines.json", b"{}", "ゲーム", time()).is_err());
+    }
+
+    #[test]
+    fn texthooker_sessions_saved_again_only_log_the_new_lines() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        create_schema(&connection).unwrap();
+        let tx = connection.transaction().unwrap();
+        let mut repository = SQLiteImportRepository::new(&tx);
+
+        let session = |text: &str| {
+            parse_texthooker_session("session.txt", text.as_bytes(), "ゲーム", time()).unwrap()
+        };
+        assert_eq!(import(&mut repository, session("あいう\nえお\n")), 5);
+        assert_eq!(import(&mut repository, session("あいう\nえお\n")), 0);
+        // a repeated line is new, the earlier lines are not
+        assert_eq!(
+            import(&mut repository, session("あいう\nえお\n\nかきく\nえお\n")),
+            5
+        );
+
+        let lines = std::str::from_utf8(TEXTHOOKER_LINES).unwrap();
+        let first_two: Vec<Value> =
+            serde_json::from_str::<Vec<Value>>(lines).unwrap()[..2].to_vec();
+        let first_two = serde_json::to_vec(&first_two).unwrap();
+        let partial = parse_texthooker_session("lines.json", &first_two, "ゲーム", time()).unwrap();
+        let full =
+            parse_texthooker_session("lines.json", TEXTHOOKER_LINES, "ゲーム", time()).unwrap();
+        let last_line = full.parts[2].1;
+        import(&mut repository, partial);
+        assert_eq!(import(&mut repository, full), last_line);
+    }
+
+    #[test]
+    fn csv_errors_point_at_the_line_of_the_row() {
+        let csv = "Time,Characters,Notes\n2025-03-01,100,\"two\nlines\"\n2025-03-02,many,\n2025-03-03,300,\n";
+        let errors = parse_history(csv.as_bytes(), HistoryFormat::Csv, &Timezone::utc())
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("Line 4: "), "{}", errors[0]);
+
+        let csv = "time,characters,notes\n2025-03-01,100,\"two\nlines\"\n";
+        let entries = parse_history(csv.as_bytes(), HistoryFormat::Csv, &Timezone::utc())
+            .unwrap()
+            .unwrap();
+        assert_eq!(entries[0].notes, "two\nlines");
+    }
+
+    #[test]
+    fn importing_an_export_again_changes_nothing() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        create_schema(&connection).unwrap();
+        let tx = connection.transaction().unwrap();
+        let repository = SQLiteImportRepository::new(&tx);
+
+        let logged = vec![
+            CharacterLogEntry::new(
+                1,
+                5_000,
+                &Timestamp::from(time()),
+                Some("本".to_owned()),
+                None,
+                None,
+            ),
+            // an admin correction
+            CharacterLogEntry::new(1, -1_000, &Timestamp::from(time()), None, Some(2), None),
+            CharacterLogEntry::new(1, 300, &Timestamp::from(time()), None, None, None),
+        ];
+        for format in [HistoryFormat::Csv, HistoryFormat::Json] {
+            let bytes = export_history(&logged, format).unwrap();
+            let entries = parse_history(&bytes, format, &Timezone::utc())
+                .unwrap()
+                .unwrap();
+            assert_eq!(entries.len(), 3);
+
+            let mut plan = ImportPlan::new(&repository, 1, FILE_SOURCE, entries).unwrap();
+            assert_eq!(plan.total_characters(), 4_300);
+            plan.skip_logged_entries(&logged);
+            assert!(plan.entries.is_empty());
+            assert_eq!(plan.skipped, 3);
+
+            // an account with only the first log gets the rest, the correction included
+            let entries = parse_history(&bytes, format, &Timezone::utc())
+                .unwrap()
+                .unwrap();
+            let mut plan = ImportPlan::new(&repository, 2, FILE_SOURCE, entries).unwrap();
+            plan.skip_logged_entries(&logged[..1]);
+            assert_eq!(plan.entries.len(), 2);
+            assert_eq!(plan.total_characters(), -700);
+        }
+    }
+}
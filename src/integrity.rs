@@ -0,0 +1,57 @@
+use rusqlite::Connection;
+
+use crate::{
+    clock::Clock,
+    model::{AuditAction, AuditEntry, TotalMismatch},
+    repository::{
+        AuditRepository, CharacterStatisticsRepository, SQLiteAuditRepository,
+        SQLiteCharacterStatisticsRepository,
+    },
+    utils::format_with_commas,
+    Error,
+};
+
+/// Recomputes every user's total from their log entries and returns the users where it drifted.
+/// With repaired_by, the stored totals are overwritten with the recomputed ones and each repair is audited under that actor.
+pub fn recompute_totals(
+    connection: &mut Connection,
+    repaired_by: Option<u64>,
+    clock: &dyn Clock,
+) -> Result<Vec<TotalMismatch>, Error> {
+    let tx = connection.transaction()?;
+    let mismatches = SQLiteCharacterStatisticsRepository::new(&tx).get_total_mismatches()?;
+
+    if let Some(actor_id) = repaired_by {
+        let mut repo = SQLiteCharacterStatisticsRepository::new(&tx);
+        let mut audit_repo = SQLiteAuditRepository::new(&tx);
+        let time = clock.timestamp();
+        for mismatch in mismatches.iter() {
+            repo.set_total_characters(mismatch.user_id, mismatch.computed_total)?;
+            audit_repo.add_audit_entry(&AuditEntry {
+                actor_id,
+                target_id: mismatch.user_id,
+                action: AuditAction::RepairTotal,
+                delta: mismatch.computed_total - mismatch.stored_total,
+                previous_total: mismatch.stored_total,
+                reason: "Recomputed the total from the log entries".to_owned(),
+                log_entry_id: None,
+                time,
+            })?;
+        }
+        tx.commit()?;
+    }
+
+    Ok(mismatches)
+}
+
+pub fn format_mismatch(mismatch: &TotalMismatch) -> String {
+    let difference = mismatch.stored_total - mismatch.computed_total;
+    format!(
+        "{} ({}): stored {}, entries add up to {} ({:+})",
+        mismatch.name,
+        mismatch.user_id,
+        format_with_commas(mismatch.stored_total),
+        format_with_commas(mismatch.computed_total),
+        difference
+    )
+}
@@ -85,6 +85,7 @@ pub struct Deck {
     pub unique_id: String,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Question {
     #[serde(rename = "deckUniqueId")]
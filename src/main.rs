@@ -1,32 +1,52 @@
 #![warn(clippy::str_to_string)]
 
+mod clock;
 mod commands;
 mod constants;
+mod counting;
+mod database;
+mod digest;
+mod extract;
+mod import;
+mod integrity;
 mod kotoba;
+#[cfg(test)]
+mod memory_repository;
 mod migrate;
 mod model;
+mod moderation;
+mod reminders;
 mod repository;
 mod roles;
+mod scheduler;
+mod service;
+mod timezone;
 mod utils;
 
-use ::serenity::all::{Member, PartialGuild, UserId};
-use chrono::{TimeZone, Utc};
-use constants::USER_ACTIVE_STATUS_REFRESH_INTERVAL;
+use ::serenity::all::Interaction;
+use clock::{Clock, SystemClock};
+use constants::{CLI_ACTOR_ID, DATABASE_PATH};
+use database::Database;
+use digest::digest_channel;
 use dotenv::dotenv;
-use migrate::{get_json_data, migrate};
-use model::Data;
-use poise::serenity_prelude as serenity;
+use integrity::{format_mismatch, recompute_totals};
+use migrate::{get_json_data, migrate, print_deltas, MigrationDelta};
+use model::{Data, MemberNames};
+use moderation::{handle_moderation_interaction, moderation_channel};
+use poise::{serenity_prelude as serenity, ChoiceParameter};
+use reminders::{handle_reminder_interaction, handle_reminder_modal};
 use repository::{
-    CharacterStatisticsRepository, MetadataRepository, SQLiteCharacterStatisticsRepository,
-    SQLiteMetadataRepository,
+    MetadataRepository, SQLiteCharacterStatisticsRepository, SQLiteMetadataRepository,
+    SQLiteNameHistoryRepository,
 };
 use reqwest::Client;
 use roles::QuizRoles;
 use rusqlite::Connection;
+use scheduler::{configured_guild, Job, JobContext, Scheduler};
 use std::{
-    collections::HashMap,
+    collections::BTreeMap,
     env::{self, var},
-    sync::{Arc, Mutex},
+    sync::Arc,
     time::Duration,
 };
 
@@ -52,12 +72,16 @@ async fn on_error(error: poise::FrameworkError<'_, Data, Error>) {
 }
 
 async fn setup_discord_bot(data: Data) {
+    let database = data.database.clone();
     // FrameworkOptions contains all of poise's configuration option in one struct
     // Every option can be omitted to use its default value
     let options = poise::FrameworkOptions {
         commands: vec![
             commands::help(),
             commands::log_characters(),
+            commands::count(),
+            commands::import(),
+            commands::export(),
             commands::history(),
             commands::usage(),
             commands::how_to_track(),
@@ -65,7 +89,12 @@ async fn setup_discord_bot(data: Data) {
             commands::rank(),
             commands::leaderboard(),
             commands::quizzes(),
+            commands::quiz(),
             commands::edit_characters(),
+            commands::audit(),
+            commands::remind(),
+            commands::settings(),
+            commands::admin(),
         ],
         prefix_options: poise::PrefixFrameworkOptions {
             // commands only, no prefix messages
@@ -86,12 +115,14 @@ async fn setup_discord_bot(data: Data) {
         // This code is run after a command if it was successful (returned Ok)
         post_command: |ctx| {
             Box::pin(async move {
-                let guild: PartialGuild = ctx.partial_guild().await.unwrap();
-                let user_data = ctx.data();
-                let result = refresh_active_users(ctx.serenity_context(), user_data, &guild).await;
-                match result {
-                    Ok(_) => (),
-                    Err(error) => println!("Error occured when refreshing active users: {}", error),
+                // the author is on the server, so keep their names and last seen time up to date
+                let Some(member) = ctx.author_member().await else {
+                    return;
+                };
+                let user_id = member.user.id.get();
+                let names = MemberNames::of(&member);
+                if let Err(error) = record_member_seen(ctx.data(), user_id, names).await {
+                    println!("Error occured when recording the author as seen: {}", error);
                 }
             })
         },
@@ -106,10 +137,15 @@ async fn setup_discord_bot(data: Data) {
     };
 
     let framework = poise::Framework::builder()
-        .setup(move |ctx, _ready, framework| {
+        .setup(move |ctx, ready, framework| {
             Box::pin(async move {
-                println!("Logged in as {}", _ready.user.name);
+                println!("Logged in as {}", ready.user.name);
                 poise::builtins::register_globally(ctx, &framework.options().commands).await?;
+                data.scheduler.start(JobContext {
+                    serenity: ctx.clone(),
+                    database: data.database.clone(),
+                    clock: data.clock.clone(),
+                });
                 Ok(data)
             })
         })
@@ -126,7 +162,36 @@ async fn setup_discord_bot(data: Data) {
         .framework(framework)
         .await;
 
-    client.unwrap().start().await.unwrap()
+    let mut client = client.unwrap();
+    let shard_manager = client.shard_manager.clone();
+    tokio::select! {
+        result = client.start() => result.unwrap(),
+        _ = shutdown_signal() => {
+            println!("Shutting down...");
+            shard_manager.shutdown_all().await;
+        }
+    }
+
+    // the runner only keeps the database file, so everything in the write-ahead log has to be in it
+    if let Err(error) = database.checkpoint().await {
+        println!("Failed to checkpoint the database: {error}");
+    }
+}
+
+/// Waits for ctrl-c, or for SIGTERM, which is how CI cancels a run
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut terminate =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+                .expect("Unable to listen for SIGTERM");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
 }
 
 async fn event_handler(
@@ -135,204 +200,224 @@ async fn event_handler(
     framework: poise::FrameworkContext<'_, Data, Error>,
 ) -> Result<(), Error> {
     match event {
-        serenity::FullEvent::Ready { data_about_bot } => {
-            println!("Running ready event");
-            for guild in &data_about_bot.guilds {
-                let partial_guild = guild.id.to_partial_guild(ctx).await?;
-                refresh_active_users(ctx, framework.user_data, &partial_guild).await?;
-            }
-        }
         serenity::FullEvent::GuildMemberAddition { new_member } => {
-            let mut conn = framework.user_data.connection.lock().unwrap();
-            let tx = conn.transaction()?;
-            let mut repository = SQLiteCharacterStatisticsRepository::new(&tx);
-            if repository.exists(new_member.user.id.get())? {
-                repository.set_active_status(
-                    new_member.user.id.get(),
-                    true,
-                    Some(new_member.user.display_name()),
-                )?;
+            let user_id = new_member.user.id.get();
+            let names = MemberNames::of(new_member);
+            if record_member_seen(framework.user_data, user_id, names).await? {
                 println!("{} returned", new_member.display_name());
             }
-            tx.commit()?;
+        }
+        serenity::FullEvent::GuildMemberUpdate {
+            event: member_update,
+            ..
+        } => {
+            // nickname and global name changes arrive here, so the leaderboard shows them right away
+            let user_id = member_update.user.id.get();
+            let names = MemberNames::new(
+                member_update.user.display_name(),
+                member_update.nick.as_deref(),
+            );
+            if record_member_seen(framework.user_data, user_id, names).await? {
+                println!("Updated the names of {}", member_update.user.name);
+            }
         }
         serenity::FullEvent::GuildMemberRemoval {
             guild_id: _,
             user,
             member_data_if_available: _,
         } => {
-            let mut conn = framework.user_data.connection.lock().unwrap();
-            let tx = conn.transaction()?;
-            let mut repository = SQLiteCharacterStatisticsRepository::new(&tx);
-            repository.set_active_status(user.id.get(), false, Some(user.display_name()))?;
+            let user_id = user.id.get();
+            let name = user.display_name().to_owned();
+            let now = framework.user_data.clock.now();
+            framework
+                .user_data
+                .database
+                .write(move |connection| {
+                    let tx = connection.transaction()?;
+                    let mut repository = SQLiteCharacterStatisticsRepository::new(&tx);
+                    service::member_left(&mut repository, user_id, &name, &now)?;
+                    tx.commit()?;
+                    Ok(())
+                })
+                .await?;
             println!("{} left", user.display_name());
-            tx.commit()?;
         }
-        serenity::FullEvent::Message { new_message } => {
-            let result = QuizRoles::handle_quiz_roles(ctx, new_message, framework.user_data).await;
-            if result.is_err() {
-                println!("Handle quiz role error: {}", result.unwrap_err());
+        serenity::FullEvent::InteractionCreate {
+            interaction: Interaction::Component(interaction),
+        } => {
+            let result = handle_moderation_interaction(ctx, interaction, framework.user_data).await;
+            if let Err(error) = result {
+                println!("Handle moderation error: {}", error);
             }
-        }
-        _ => {}
-    }
-    Ok(())
-}
-
-async fn refresh_active_users(
-    ctx: &serenity::Context,
-    user_data: &Data,
-    guild: &PartialGuild,
-) -> Result<(), Error> {
-    println!("Reloading active users...");
-    let should_refresh = {
-        let mut conn = user_data.connection.lock().unwrap();
-        let tx = conn.transaction()?;
-        let repository = SQLiteMetadataRepository::new(&tx);
-        let last_refresh = repository.get_last_active_status_refresh()?;
-        tx.commit()?;
-        match last_refresh {
-            None => true,
-            Some(last_refresh) => {
-                let elapsed = Utc::now().timestamp() - last_refresh.timestamp();
-                elapsed > USER_ACTIVE_STATUS_REFRESH_INTERVAL
+            if let Err(error) = handle_reminder_interaction(ctx, interaction).await {
+                println!("Handle reminder error: {}", error);
             }
         }
-    };
-
-    if !should_refresh {
-        println!("No need to reload, 2 hours haven't passed");
-        return Ok(());
-    }
-
-    let mut after: Option<UserId> = None;
-    let mut members: HashMap<UserId, Member> = HashMap::with_capacity(2500);
-    loop {
-        let temp_members = guild.members(ctx, None, after).await?;
-        if temp_members.is_empty() {
-            break;
-        }
-        after = Some(temp_members.last().unwrap().user.id);
-        for m in temp_members.into_iter() {
-            members.insert(m.user.id, m);
-        }
-    }
-
-    let mut conn = user_data.connection.lock().unwrap();
-    let tx = conn.transaction()?;
-    let mut repository = SQLiteCharacterStatisticsRepository::new(&tx);
-
-    let mut page_number = 0;
-    loop {
-        let users = repository.get_paginated_users_by_id(page_number)?;
-        if users.is_empty() {
-            break;
+        serenity::FullEvent::InteractionCreate {
+            interaction: Interaction::Modal(interaction),
+        } => {
+            let result = handle_reminder_modal(ctx, interaction, framework.user_data).await;
+            if let Err(error) = result {
+                println!("Handle reminder log error: {}", error);
+            }
         }
-        for u in users.iter() {
-            let member = members.get(&UserId::from(u.get_user_id()));
-            let name = match member {
-                None => None,
-                Some(member) => Some(member.user.display_name()),
-            };
-            repository.set_active_status(u.get_user_id(), member.is_some(), name)?;
+        serenity::FullEvent::Message { new_message } => {
+            let result = QuizRoles::handle_quiz_roles(ctx, new_message, framework.user_data).await;
+            if let Err(error) = result {
+                println!("Handle quiz role error: {}", error);
+            }
         }
-        page_number += 1;
+        _ => {}
     }
-
-    let mut metadata_repository = SQLiteMetadataRepository::new(&tx);
-    metadata_repository.set_last_active_status_refresh(Utc::now())?;
-
-    tx.commit()?;
-    println!("Done reloading active users");
     Ok(())
 }
 
-fn setup_sqlite_connection() -> rusqlite::Result<Connection> {
-    let connection = Connection::open("./perdition.db")?;
-
-    // Setup migration
-    connection.execute(
-        "
--- Create the CharacterStatistics table
-CREATE TABLE IF NOT EXISTS CharacterStatistics (
-    user_id INTEGER PRIMARY KEY, -- the discord id of the user
-    total_characters INTEGER NOT NULL,
-    is_active INTEGER NOT NULL DEFAULT 1, -- 1 = TRUE, 0 = FALSE
-    name TEXT NOT NULL DEFAULT 'UNKNOWN'
-);    
-    ",
-        (),
-    )?;
-
-    connection.execute(
-        "
--- Create the CharacterLogEntry table
-CREATE TABLE IF NOT EXISTS CharacterLogEntry (
-    id INTEGER PRIMARY KEY AUTOINCREMENT,
-    user_id INTEGER NOT NULL, -- Foreign key linking to CharacterStatistics
-    characters INTEGER NOT NULL,
-    time INTEGER NOT NULL, -- Store timestamp as Unix timestamp (64bits in SQLite)
-    notes TEXT, -- Optional field for notes
-    FOREIGN KEY (user_id) REFERENCES CharacterStatistics (user_id)
-);
-    ",
-        (),
-    )?;
-
-    // Setup migration
-    connection.execute(
-        "
-CREATE TABLE IF NOT EXISTS Metadata (
-    last_active_status_refresh INTEGER NOT NULL
-);    
-        ",
-        (),
-    )?;
-
-    Ok(connection)
+/// Marks a member as active with their latest names and the current time as last seen.
+/// Returns false if the member never logged anything, they aren't stored then.
+async fn record_member_seen(data: &Data, user_id: u64, names: MemberNames) -> Result<bool, Error> {
+    let now = data.clock.now();
+    data.database
+        .write(move |connection| {
+            let tx = connection.transaction()?;
+            let mut repository = SQLiteCharacterStatisticsRepository::new(&tx);
+            let mut name_history_repository = SQLiteNameHistoryRepository::new(&tx);
+            let seen = service::member_seen(
+                &mut repository,
+                &mut name_history_repository,
+                user_id,
+                &names,
+                &now,
+            )?;
+            tx.commit()?;
+            Ok(seen)
+        })
+        .await
 }
 
 #[tokio::main]
 async fn main() {
     dotenv().ok();
 
-    let mut connection = setup_sqlite_connection().expect("Failed to open an SQLite connection!");
+    let database = Database::open(DATABASE_PATH).expect("Failed to open an SQLite connection!");
     let http_client = Client::new();
+    let clock: Arc<dyn Clock> = Arc::new(SystemClock);
 
     // migrate old json data (if needed)
+    // usage: --migrate <path> [--dry-run]
     let args: Vec<String> = env::args().collect();
     if args.len() > 2 && args[1] == "--migrate" {
-        let path = &args[2];
+        let path = args[2].clone();
+        let dry_run = args.iter().skip(3).any(|arg| arg == "--dry-run");
         println!("Migrating file: {}", path);
-        let result = handle_migrate(&mut connection, path);
+        let migrate_clock = clock.clone();
+        let result = database
+            .write(move |connection| {
+                handle_migrate(connection, &path, dry_run, migrate_clock.as_ref())
+            })
+            .await;
         match result {
-            Err(error) => {
-                println!("Failed to migrate json data: {error}");
+            Err(error) => println!("Failed to migrate json data: {error}"),
+            Ok(deltas) if dry_run => {
+                print_deltas(&deltas);
+                println!("Dry run, nothing was migrated");
             }
-            _ => (),
-        };
+            Ok(deltas) => {
+                print_deltas(&deltas);
 
-        // after successful migration, we need to refresh active users
-        let transaction = connection
-            .transaction()
-            .expect("Unable to refresh active users after migration");
-        let mut repo = SQLiteMetadataRepository::new(&transaction);
-        let time = Utc
-            .timestamp_opt(0, 0)
-            .single()
-            .expect("Date conversion error in migration!");
-        repo.set_last_active_status_refresh(time).unwrap();
-        transaction.commit().unwrap();
+                // after successful migration, we need to refresh active users
+                database
+                    .write(|connection| {
+                        let transaction = connection.transaction()?;
+                        let mut repo = SQLiteMetadataRepository::new(&transaction);
+                        repo.clear_job_run(Job::ActiveUsers.name())?;
+                        transaction.commit()?;
+                        Ok(())
+                    })
+                    .await
+                    .expect("Unable to refresh active users after migration");
+                println!("Migrated {} users", deltas.len());
+            }
+        }
+
+        // a dry run only reports what would change, it doesn't start the bot
+        if dry_run {
+            return;
+        }
+    }
+
+    // recompute every total from the log entries, usage: --verify-totals [--repair]
+    if args.len() > 1 && args[1] == "--verify-totals" {
+        let repair = args.iter().skip(2).any(|arg| arg == "--repair");
+        let repaired_by = repair.then_some(CLI_ACTOR_ID);
+        let repair_clock = clock.clone();
+        let result = database
+            .write(move |connection| {
+                recompute_totals(connection, repaired_by, repair_clock.as_ref())
+            })
+            .await;
+        match result {
+            Err(error) => println!("Failed to verify totals: {error}"),
+            Ok(mismatches) => {
+                for mismatch in mismatches.iter() {
+                    println!("{}", format_mismatch(mismatch));
+                }
+                match (mismatches.len(), repair) {
+                    (0, _) => println!("All totals match their log entries"),
+                    (count, true) => println!("Repaired {} totals", count),
+                    (count, false) => {
+                        println!("{} totals don't match, use --repair to fix them", count)
+                    }
+                }
+            }
+        }
+        return;
+    }
+
+    // startup integrity check, only warns since the totals might have been edited on purpose
+    let verify_clock = clock.clone();
+    let result = database
+        .read(move |connection| recompute_totals(connection, None, verify_clock.as_ref()))
+        .await;
+    match result {
+        Err(error) => println!("Failed to verify totals: {error}"),
+        Ok(mismatches) => {
+            for mismatch in mismatches.iter() {
+                println!("Warning: total mismatch for {}", format_mismatch(mismatch));
+            }
+            if !mismatches.is_empty() {
+                println!(
+                    "Run with --verify-totals --repair or use /admin verify_totals to fix them"
+                );
+            }
+        }
+    }
+
+    if moderation_channel().is_none() {
+        println!("Warning: MODERATION_CHANNEL_ID is not set, large logs won't be moderated");
+    }
+    if digest_channel().is_none() {
+        println!("Warning: DIGEST_CHANNEL_ID is not set, no digests will be posted");
+    }
+    if configured_guild().is_none() {
+        println!("Warning: GUILD_ID is not set, the active users won't be refreshed");
     }
 
+    let scheduler = Scheduler::new(clock.now()).expect("Invalid job schedule");
     let data = Data {
-        connection: Mutex::new(connection),
+        database,
         http_client,
+        clock,
+        scheduler,
     };
     setup_discord_bot(data).await
 }
 
-fn handle_migrate(connection: &mut Connection, path: &str) -> Result<(), Error> {
+fn handle_migrate(
+    connection: &mut Connection,
+    path: &str,
+    dry_run: bool,
+    clock: &dyn Clock,
+) -> Result<BTreeMap<u64, MigrationDelta>, Error> {
     let old_data = get_json_data(path)?;
-    migrate(connection, old_data)
+    migrate(connection, old_data, dry_run, clock)
 }
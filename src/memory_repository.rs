@@ -0,0 +1,559 @@
+use std::{cmp::Reverse, collections::BTreeMap, ops::Neg};
+
+use chrono::{DateTime, Utc};
+use serenity::all::Timestamp;
+
+use crate::{
+    constants::{LEADERBOARD_PAGE_SIZE, LOG_ENTRY_PAGE_SIZE, NAME_HISTORY_PAGE_SIZE},
+    model::{
+        CharacterLogEntry, CharacterStatistics, JobRun, MediaType, MemberNames, NameChange,
+        TotalMismatch, UserSettings,
+    },
+    reminders::Reminder,
+    repository::{
+        CharacterStatisticsRepository, MetadataRepository, NameHistoryRepository,
+        ReminderRepository, UserSettingsRepository,
+    },
+    Error,
+};
+
+struct MemoryUser {
+    name: String,
+    total_characters: i64,
+    is_active: bool,
+    last_seen: Option<DateTime<Utc>>,
+    nickname: Option<String>,
+}
+
+struct MemoryLogEntry {
+    id: u64,
+    user_id: u64,
+    characters: i64,
+    time: DateTime<Utc>,
+    notes: Option<String>,
+    media_type: Option<MediaType>,
+}
+
+/// Keeps the statistics in memory with the same rules as the SQLite repository, for testing the service without a db
+#[derive(Default)]
+pub struct MemoryCharacterStatisticsRepository {
+    users: BTreeMap<u64, MemoryUser>,
+    entries: Vec<MemoryLogEntry>,
+    next_entry_id: u64,
+}
+
+impl MemoryCharacterStatisticsRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn last_seen(&self, user_id: u64) -> Option<DateTime<Utc>> {
+        self.users.get(&user_id)?.last_seen
+    }
+
+    fn to_statistics(user_id: u64, user: &MemoryUser) -> CharacterStatistics {
+        CharacterStatistics::new(user_id, user.total_characters, user.name.to_owned())
+    }
+
+    fn to_log_entry(entry: &MemoryLogEntry) -> CharacterLogEntry {
+        CharacterLogEntry::new(
+            entry.user_id,
+            entry.characters,
+            &Timestamp::from(entry.time),
+            entry.notes.clone(),
+            None,
+            entry.media_type,
+        )
+    }
+
+    /// Active users with characters, in leaderboard order
+    fn ranked_users(&self) -> Vec<CharacterStatistics> {
+        let mut users: Vec<CharacterStatistics> = self
+            .users
+            .iter()
+            .filter(|(_, user)| user.is_active && user.total_characters > 0)
+            .map(|(user_id, user)| {
+                let name = user.nickname.as_ref().unwrap_or(&user.name);
+                CharacterStatistics::new(*user_id, user.total_characters, name.to_owned())
+            })
+            .collect();
+        users.sort_by(|a, b| {
+            b.total_characters
+                .cmp(&a.total_characters)
+                .then(a.get_user_id().cmp(&b.get_user_id()))
+        });
+        users
+    }
+}
+
+fn page<T>(items: Vec<T>, page_number: u64, page_size: u64) -> Vec<T> {
+    items
+        .into_iter()
+        .skip((page_number * page_size) as usize)
+        .take(page_size as usize)
+        .collect()
+}
+
+impl CharacterStatisticsRepository for MemoryCharacterStatisticsRepository {
+    fn add_log_entry_with_id(
+        &mut self,
+        user_id: u64,
+        name: &str,
+        characters: i64,
+        time: &DateTime<Utc>,
+        notes: Option<String>,
+    ) -> Result<(CharacterStatistics, Option<u64>), Error> {
+        let old_statistics = self.get_or_initialize_statistics(user_id, name)?;
+
+        let characters = if characters >= 0 {
+            characters
+        } else {
+            characters.clamp(old_statistics.total_characters.neg(), 0)
+        };
+
+        let mut entry_id = None;
+        if characters != 0 || notes.as_ref().is_some_and(|n| !n.trim().is_empty()) {
+            self.next_entry_id += 1;
+            self.entries.push(MemoryLogEntry {
+                id: self.next_entry_id,
+                user_id,
+                characters,
+                time: *time,
+                notes,
+                media_type: None,
+            });
+            entry_id = Some(self.next_entry_id);
+        }
+
+        let total_characters = old_statistics
+            .total_characters
+            .checked_add(characters)
+            .ok_or("The total characters would overflow.")?;
+
+        let user = self.users.get_mut(&user_id).unwrap();
+        user.total_characters = total_characters;
+        user.name = name.to_owned();
+
+        Ok((
+            CharacterStatistics::new(user_id, total_characters, name.to_owned()),
+            entry_id,
+        ))
+    }
+
+    fn exists(&self, user_id: u64) -> Result<bool, Error> {
+        Ok(self.users.contains_key(&user_id))
+    }
+
+    fn get_statistics(&self, user_id: u64) -> Result<Option<CharacterStatistics>, Error> {
+        Ok(self
+            .users
+            .get(&user_id)
+            .map(|user| Self::to_statistics(user_id, user)))
+    }
+
+    fn get_or_initialize_statistics(
+        &mut self,
+        user_id: u64,
+        name: &str,
+    ) -> Result<CharacterStatistics, Error> {
+        let user = self.users.entry(user_id).or_insert_with(|| MemoryUser {
+            name: name.to_owned(),
+            total_characters: 0,
+            is_active: true,
+            last_seen: None,
+            nickname: None,
+        });
+        Ok(CharacterStatistics::new(
+            user_id,
+            user.total_characters,
+            name.to_owned(),
+        ))
+    }
+
+    fn get_rank(&mut self, statistics: &CharacterStatistics) -> Result<Option<i32>, Error> {
+        Ok(self
+            .ranked_users()
+            .iter()
+            .position(|user| user.get_user_id() == statistics.get_user_id())
+            .map(|index| index as i32 + 1))
+    }
+
+    fn set_active_status(
+        &mut self,
+        user_id: u64,
+        active: bool,
+        latest_name: Option<&str>,
+    ) -> Result<(), Error> {
+        if let Some(user) = self.users.get_mut(&user_id) {
+            user.is_active = active;
+            if let Some(name) = latest_name {
+                user.name = name.to_owned();
+            }
+        }
+        Ok(())
+    }
+
+    fn set_last_seen(&mut self, user_id: u64, time: &DateTime<Utc>) -> Result<(), Error> {
+        if let Some(user) = self.users.get_mut(&user_id) {
+            user.last_seen = Some(*time);
+        }
+        Ok(())
+    }
+
+    fn set_names(&mut self, user_id: u64, names: &MemberNames) -> Result<(), Error> {
+        if let Some(user) = self.users.get_mut(&user_id) {
+            user.name = names.global_name.to_owned();
+            user.nickname = names.nickname.clone();
+        }
+        Ok(())
+    }
+
+    fn get_paginated_active_users_by_characters(
+        &mut self,
+        page_number: u64,
+    ) -> Result<Vec<CharacterStatistics>, Error> {
+        Ok(page(
+            self.ranked_users(),
+            page_number,
+            LEADERBOARD_PAGE_SIZE,
+        ))
+    }
+
+    fn get_paginated_users_by_id(
+        &mut self,
+        page_number: u64,
+    ) -> Result<Vec<CharacterStatistics>, Error> {
+        let users = self
+            .users
+            .iter()
+            .map(|(user_id, user)| Self::to_statistics(*user_id, user))
+            .collect();
+        Ok(page(users, page_number, LEADERBOARD_PAGE_SIZE))
+    }
+
+    fn get_total_active_users(&mut self) -> Result<u64, Error> {
+        Ok(self.ranked_users().len() as u64)
+    }
+
+    fn get_paginated_log_entries_by_time(
+        &mut self,
+        user_id: u64,
+        page_number: u64,
+    ) -> Result<Vec<CharacterLogEntry>, Error> {
+        let mut entries: Vec<&MemoryLogEntry> = self
+            .entries
+            .iter()
+            .filter(|entry| entry.user_id == user_id)
+            .collect();
+        entries.sort_by_key(|entry| Reverse(entry.time));
+        let entries = entries.into_iter().map(Self::to_log_entry).collect();
+        Ok(page(entries, page_number, LOG_ENTRY_PAGE_SIZE))
+    }
+
+    fn get_total_log_entries(&mut self, user_id: u64) -> Result<u64, Error> {
+        Ok(self
+            .entries
+            .iter()
+            .filter(|entry| entry.user_id == user_id)
+            .count() as u64)
+    }
+
+    fn get_log_average_since(
+        &mut self,
+        user_id: u64,
+        since: &DateTime<Utc>,
+    ) -> Result<(u64, f64), Error> {
+        let recent: Vec<i64> = self
+            .entries
+            .iter()
+            .filter(|entry| {
+                entry.user_id == user_id
+                    && entry.time.timestamp() >= since.timestamp()
+                    && entry.characters > 0
+            })
+            .map(|entry| entry.characters)
+            .collect();
+        if recent.is_empty() {
+            return Ok((0, 0.0));
+        }
+        let average = recent.iter().sum::<i64>() as f64 / recent.len() as f64;
+        Ok((recent.len() as u64, average))
+    }
+
+    fn get_log_totals_between(
+        &mut self,
+        since: Option<&DateTime<Utc>>,
+        until: &DateTime<Utc>,
+    ) -> Result<Vec<CharacterStatistics>, Error> {
+        let mut totals: BTreeMap<u64, i64> = BTreeMap::new();
+        for entry in self.entries.iter() {
+            let after_since = since.is_none_or(|since| entry.time >= *since);
+            if after_since && entry.time < *until {
+                *totals.entry(entry.user_id).or_default() += entry.characters;
+            }
+        }
+        let mut result: Vec<CharacterStatistics> = totals
+            .into_iter()
+            .filter_map(|(user_id, characters)| {
+                let user = self.users.get(&user_id).filter(|user| user.is_active)?;
+                let name = user.nickname.as_ref().unwrap_or(&user.name);
+                Some(CharacterStatistics::new(
+                    user_id,
+                    characters,
+                    name.to_owned(),
+                ))
+            })
+            .collect();
+        result.sort_by_key(|statistics| Reverse(statistics.total_characters));
+        Ok(result)
+    }
+
+    fn get_all_log_entries(&mut self, user_id: u64) -> Result<Vec<CharacterLogEntry>, Error> {
+        let mut entries: Vec<&MemoryLogEntry> = self
+            .entries
+            .iter()
+            .filter(|entry| entry.user_id == user_id)
+            .collect();
+        entries.sort_by(|a, b| a.time.cmp(&b.time).then(a.id.cmp(&b.id)));
+        Ok(entries.into_iter().map(Self::to_log_entry).collect())
+    }
+
+    fn get_total_mismatches(&mut self) -> Result<Vec<TotalMismatch>, Error> {
+        let mut result = Vec::new();
+        for (user_id, user) in self.users.iter() {
+            let computed_total: i64 = self
+                .entries
+                .iter()
+                .filter(|entry| entry.user_id == *user_id)
+                .map(|entry| entry.characters)
+                .sum();
+            if computed_total != user.total_characters {
+                result.push(TotalMismatch {
+                    user_id: *user_id,
+                    name: user.name.to_owned(),
+                    stored_total: user.total_characters,
+                    computed_total,
+                });
+            }
+        }
+        Ok(result)
+    }
+
+    fn set_total_characters(&mut self, user_id: u64, total_characters: i64) -> Result<(), Error> {
+        if let Some(user) = self.users.get_mut(&user_id) {
+            user.total_characters = total_characters;
+        }
+        Ok(())
+    }
+
+    fn move_log_entries(&mut self, from_user_id: u64, to_user_id: u64) -> Result<u64, Error> {
+        let mut moved = 0;
+        for entry in self
+            .entries
+            .iter_mut()
+            .filter(|entry| entry.user_id == from_user_id)
+        {
+            entry.user_id = to_user_id;
+            moved += 1;
+        }
+        Ok(moved)
+    }
+
+    fn delete_log_entries(&mut self, user_id: u64) -> Result<u64, Error> {
+        let before = self.entries.len();
+        self.entries.retain(|entry| entry.user_id != user_id);
+        Ok((before - self.entries.len()) as u64)
+    }
+
+    fn set_media_type(&mut self, log_entry_id: u64, media_type: MediaType) -> Result<(), Error> {
+        if let Some(entry) = self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.id == log_entry_id)
+        {
+            entry.media_type = Some(media_type);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryMetadataRepository {
+    job_runs: Vec<JobRun>,
+    migration_batches: Vec<String>,
+}
+
+impl MemoryMetadataRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl MetadataRepository for MemoryMetadataRepository {
+    fn get_job_run(&self, job: &str) -> Result<Option<JobRun>, Error> {
+        Ok(self.job_runs.iter().find(|run| run.job == job).cloned())
+    }
+
+    fn set_job_run(&mut self, run: &JobRun) -> Result<(), Error> {
+        self.clear_job_run(&run.job)?;
+        self.job_runs.push(run.clone());
+        Ok(())
+    }
+
+    fn clear_job_run(&mut self, job: &str) -> Result<(), Error> {
+        self.job_runs.retain(|run| run.job != job);
+        Ok(())
+    }
+
+    fn is_migration_applied(&self, batch_id: &str) -> Result<bool, Error> {
+        Ok(self.migration_batches.iter().any(|id| id == batch_id))
+    }
+
+    fn has_migration_batches(&self) -> Result<bool, Error> {
+        Ok(!self.migration_batches.is_empty())
+    }
+
+    fn add_migration_batch(
+        &mut self,
+        batch_id: &str,
+        _entries: u64,
+        _time: &DateTime<Utc>,
+    ) -> Result<(), Error> {
+        if self.is_migration_applied(batch_id)? {
+            return Err(format!("Migration batch {} already exists.", batch_id).into());
+        }
+        self.migration_batches.push(batch_id.to_owned());
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryNameHistoryRepository {
+    changes: Vec<NameChange>,
+}
+
+impl MemoryNameHistoryRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn matching(&self, user_id: Option<u64>, name: Option<&str>) -> Vec<&NameChange> {
+        let name = name.map(|name| name.to_lowercase());
+        let contains_name = |change: &NameChange| match &name {
+            None => true,
+            Some(name) => {
+                change.names.global_name.to_lowercase().contains(name)
+                    || change
+                        .names
+                        .nickname
+                        .as_ref()
+                        .is_some_and(|nickname| nickname.to_lowercase().contains(name))
+            }
+        };
+        // newest first, the later of two changes at the same time was added last
+        let mut changes: Vec<&NameChange> = self
+            .changes
+            .iter()
+            .rev()
+            .filter(|change| user_id.is_none_or(|user_id| change.user_id == user_id))
+            .filter(|change| contains_name(change))
+            .collect();
+        changes.sort_by_key(|change| Reverse(change.time));
+        changes
+    }
+}
+
+impl NameHistoryRepository for MemoryNameHistoryRepository {
+    fn add_name_change(&mut self, change: &NameChange) -> Result<(), Error> {
+        self.changes.push(change.clone());
+        Ok(())
+    }
+
+    fn get_latest_names(&self, user_id: u64) -> Result<Option<MemberNames>, Error> {
+        Ok(self
+            .matching(Some(user_id), None)
+            .first()
+            .map(|change| change.names.clone()))
+    }
+
+    fn get_paginated_name_changes(
+        &self,
+        user_id: Option<u64>,
+        name: Option<&str>,
+        page_number: u64,
+    ) -> Result<Vec<NameChange>, Error> {
+        let changes = self.matching(user_id, name).into_iter().cloned().collect();
+        Ok(page(changes, page_number, NAME_HISTORY_PAGE_SIZE))
+    }
+
+    fn get_total_name_changes(
+        &self,
+        user_id: Option<u64>,
+        name: Option<&str>,
+    ) -> Result<u64, Error> {
+        Ok(self.matching(user_id, name).len() as u64)
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryReminderRepository {
+    reminders: BTreeMap<u64, Reminder>,
+}
+
+impl MemoryReminderRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ReminderRepository for MemoryReminderRepository {
+    fn get_reminder(&self, user_id: u64) -> Result<Option<Reminder>, Error> {
+        Ok(self.reminders.get(&user_id).cloned())
+    }
+
+    fn get_reminders(&self) -> Result<Vec<Reminder>, Error> {
+        Ok(self.reminders.values().cloned().collect())
+    }
+
+    fn set_reminder(&mut self, reminder: &Reminder) -> Result<(), Error> {
+        self.reminders.insert(reminder.user_id, reminder.clone());
+        Ok(())
+    }
+
+    fn delete_reminder(&mut self, user_id: u64) -> Result<bool, Error> {
+        Ok(self.reminders.remove(&user_id).is_some())
+    }
+
+    fn set_reminder_sent(&mut self, user_id: u64, time: &DateTime<Utc>) -> Result<(), Error> {
+        if let Some(reminder) = self.reminders.get_mut(&user_id) {
+            reminder.last_sent = Some(*time);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryUserSettingsRepository {
+    settings: BTreeMap<u64, UserSettings>,
+}
+
+impl MemoryUserSettingsRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl UserSettingsRepository for MemoryUserSettingsRepository {
+    fn get_settings(&self, user_id: u64) -> Result<UserSettings, Error> {
+        Ok(self
+            .settings
+            .get(&user_id)
+            .cloned()
+            .unwrap_or_else(|| UserSettings::new(user_id)))
+    }
+
+    fn set_settings(&mut self, settings: &UserSettings) -> Result<(), Error> {
+        self.settings.insert(settings.user_id, settings.clone());
+        Ok(())
+    }
+}
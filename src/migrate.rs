@@ -1,41 +1,345 @@
-use std::{error::Error, fs::File, io::BufReader};
+use std::{
+    collections::{btree_map::Entry, BTreeMap},
+    error::Error,
+    fs,
+};
 
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use rusqlite::Connection;
 use serde::{Deserialize, Serialize};
 
-use crate::repository::{CharacterStatisticsRepository, SQLiteCharacterStatisticsRepository};
+use crate::{
+    clock::Clock,
+    model::CharacterLogEntry,
+    repository::{
+        CharacterStatisticsRepository, MetadataRepository, SQLiteCharacterStatisticsRepository,
+        SQLiteMetadataRepository,
+    },
+    utils::{fnv1a, format_with_commas},
+};
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum OldTimestamp {
+    /// Unix timestamp in seconds or milliseconds
+    Unix(i64),
+    /// RFC 3339, i.e. "2024-05-01T10:00:00Z"
+    Text(String),
+}
+
+impl OldTimestamp {
+    fn to_date_time(&self) -> Option<DateTime<Utc>> {
+        match self {
+            // nothing was logged before 2001 in seconds, so larger values are in milliseconds
+            Self::Unix(time) if time.abs() >= 100_000_000_000 => {
+                DateTime::from_timestamp_millis(*time)
+            }
+            Self::Unix(time) => DateTime::from_timestamp(*time, 0),
+            Self::Text(time) => DateTime::parse_from_rfc3339(time)
+                .ok()
+                .map(|time| time.to_utc()),
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct OldCharacterLog {
-    characters: i32,
+    characters: i64,
     #[serde(alias = "userID")]
     user_id: u64,
+    #[serde(default, alias = "time", alias = "date")]
+    timestamp: Option<OldTimestamp>,
+    #[serde(default, alias = "note")]
+    notes: Option<String>,
+    #[serde(default, alias = "username", alias = "displayName")]
+    name: Option<String>,
+}
+
+/// The notes of migrated logs that didn't have any, the first version of --migrate used them for every log
+const MIGRATION_NOTES: &str = "Migrate from previous bot";
+
+/// The old data of one file, identified by the hash of its contents
+pub struct OldData {
+    pub batch_id: String,
+    pub logs: Vec<OldCharacterLog>,
+}
+
+/// How a migration changes the total of one user
+#[derive(Debug, Default)]
+pub struct MigrationDelta {
+    pub name: String,
+    pub entries: u64,
+    /// rows that were already in the log history, i.e. from migrating the file before
+    pub skipped: u64,
+    pub previous_total: i64,
+    pub new_total: i64,
+}
+
+/// Reads the old bot's json data. Every row is validated, a single malformed row fails the whole file.
+pub fn get_json_data(path: &str) -> Result<OldData, Box<dyn Error + Send + Sync>> {
+    let content = fs::read_to_string(path)?;
+    let rows: Vec<serde_json::Value> = serde_json::from_str(&content)?;
+
+    let mut logs = Vec::with_capacity(rows.len());
+    let mut errors = Vec::new();
+    for (index, row) in rows.into_iter().enumerate() {
+        match serde_json::from_value::<OldCharacterLog>(row) {
+            Ok(log) => {
+                if log
+                    .timestamp
+                    .as_ref()
+                    .is_some_and(|t| t.to_date_time().is_none())
+                {
+                    errors.push(format!("Row {}: invalid timestamp", index + 1));
+                } else {
+                    logs.push(log);
+                }
+            }
+            Err(error) => errors.push(format!("Row {}: {}", index + 1, error)),
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(format!(
+            "Malformed rows, nothing was migrated:\n{}",
+            errors.join("\n")
+        )
+        .into());
+    }
+
+    Ok(OldData {
+        batch_id: format!("{:016x}", fnv1a(&content)),
+        logs,
+    })
 }
 
-pub fn get_json_data(path: &str) -> Result<Vec<OldCharacterLog>, Box<dyn Error + Send + Sync>> {
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
-    let old_data: Vec<OldCharacterLog> = serde_json::from_reader(reader)?;
-    Ok(old_data)
+/// Whether a log entry is what the first version of --migrate, which didn't record batches, made of an old log
+fn is_migrated_from(entry: &CharacterLogEntry, log: &OldCharacterLog) -> bool {
+    if entry.characters() != log.characters
+        || entry.notes().as_deref() != Some(log.notes.as_deref().unwrap_or(MIGRATION_NOTES))
+    {
+        return false;
+    }
+    match log.timestamp.as_ref().and_then(OldTimestamp::to_date_time) {
+        // it logged everything at the time of the migration, which can't be before the old log
+        Some(time) => **entry.time() >= time,
+        // without a timestamp, the amount and the notes are all there is to compare
+        None => true,
+    }
 }
 
+/// Adds the old logs to the db in one transaction.
+/// A file that was migrated before is refused. Databases migrated before batches were recorded
+/// skip the rows that are already in the log history instead.
+/// With dry_run, nothing is committed and the returned deltas show what would happen.
 pub fn migrate(
     connection: &mut Connection,
-    old_data: Vec<OldCharacterLog>,
-) -> Result<(), Box<dyn Error + Send + Sync>> {
+    old_data: OldData,
+    dry_run: bool,
+    clock: &dyn Clock,
+) -> Result<BTreeMap<u64, MigrationDelta>, Box<dyn Error + Send + Sync>> {
     let tx = connection.transaction()?;
+    let now = clock.now();
+
+    let skip_logged_rows = {
+        let mut metadata_repo = SQLiteMetadataRepository::new(&tx);
+        if metadata_repo.is_migration_applied(&old_data.batch_id)? {
+            return Err(format!(
+                "This file was already migrated (batch {}), nothing was migrated",
+                old_data.batch_id
+            )
+            .into());
+        }
+        let skip_logged_rows = !metadata_repo.has_migration_batches()?;
+        metadata_repo.add_migration_batch(&old_data.batch_id, old_data.logs.len() as u64, &now)?;
+        skip_logged_rows
+    };
+
     let mut repo = SQLiteCharacterStatisticsRepository::new(&tx);
+    let mut deltas: BTreeMap<u64, MigrationDelta> = BTreeMap::new();
+    // the log entries from before this migration, each one can only account for one row
+    let mut logged: BTreeMap<u64, Vec<CharacterLogEntry>> = BTreeMap::new();
+    for data in old_data.logs.into_iter() {
+        let existing = repo.get_statistics(data.user_id)?;
+        let user_logs = match logged.entry(data.user_id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(repo.get_all_log_entries(data.user_id)?),
+        };
+        if let Some(index) = user_logs
+            .iter()
+            .position(|entry| skip_logged_rows && is_migrated_from(entry, &data))
+        {
+            user_logs.swap_remove(index);
+            let delta = deltas
+                .entry(data.user_id)
+                .or_insert_with(|| MigrationDelta {
+                    name: existing
+                        .as_ref()
+                        .map_or_else(String::new, |e| e.name.to_owned()),
+                    previous_total: existing.as_ref().map_or(0, |e| e.total_characters),
+                    new_total: existing.as_ref().map_or(0, |e| e.total_characters),
+                    ..Default::default()
+                });
+            delta.skipped += 1;
+            continue;
+        }
+
+        // keep the name we already know unless the old data has one
+        let name = match (data.name, &existing) {
+            (Some(name), _) => name,
+            (None, Some(existing)) => existing.name.to_owned(),
+            (None, None) => "Unknown".to_owned(),
+        };
+        let time = data
+            .timestamp
+            .and_then(|timestamp| timestamp.to_date_time())
+            .unwrap_or(now);
+        let notes = data.notes.unwrap_or_else(|| MIGRATION_NOTES.to_owned());
+
+        let delta = deltas
+            .entry(data.user_id)
+            .or_insert_with(|| MigrationDelta {
+                previous_total: existing.map_or(0, |e| e.total_characters),
+                ..Default::default()
+            });
+        let statistics =
+            repo.add_log_entry(data.user_id, &name, data.characters, &time, Some(notes))?;
+        delta.name = name;
+        delta.entries += 1;
+        delta.new_total = statistics.total_characters;
+    }
 
-    for data in old_data.iter() {
-        repo.add_log_entry(
-            data.user_id,
-            "Unknown",
-            data.characters,
-            &Utc::now(),
-            Some("Migrate from previous bot".to_owned()),
-        )?;
+    if dry_run {
+        // dropping the transaction rolls it back
+        return Ok(deltas);
     }
+
     tx.commit()?;
-    Ok(())
+    Ok(deltas)
+}
+
+pub fn print_deltas(deltas: &BTreeMap<u64, MigrationDelta>) {
+    for (user_id, delta) in deltas.iter() {
+        println!(
+            "{} ({}): {} entries, {} already migrated, {} -> {} ({:+})",
+            user_id,
+            delta.name,
+            delta.entries,
+            delta.skipped,
+            format_with_commas(delta.previous_total),
+            format_with_commas(delta.new_total),
+            delta.new_total - delta.previous_total
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::{clock::FakeClock, database::create_schema};
+
+    fn connection() -> Connection {
+        let connection = Connection::open_in_memory().unwrap();
+        create_schema(&connection).unwrap();
+        connection
+    }
+
+    fn old_data(batch_id: &str, timestamp: Option<OldTimestamp>) -> OldData {
+        OldData {
+            batch_id: batch_id.to_owned(),
+            logs: vec![OldCharacterLog {
+                characters: 1_000,
+                user_id: 1,
+                timestamp,
+                notes: None,
+                name: Some("user".to_owned()),
+            }],
+        }
+    }
+
+    fn log_times(connection: &mut Connection) -> Vec<i64> {
+        let tx = connection.transaction().unwrap();
+        let entries = SQLiteCharacterStatisticsRepository::new(&tx)
+            .get_all_log_entries(1)
+            .unwrap();
+        entries
+            .iter()
+            .map(|entry| entry.time().unix_timestamp())
+            .collect()
+    }
+
+    #[test]
+    fn logs_without_a_timestamp_use_the_migration_time() {
+        let mut connection = connection();
+        let clock = FakeClock::new(Utc.timestamp_opt(1_700_000_000, 0).unwrap());
+
+        migrate(&mut connection, old_data("a", None), false, &clock).unwrap();
+        clock.set(Utc.timestamp_opt(1_800_000_000, 0).unwrap());
+        let mut data = old_data("b", Some(OldTimestamp::Unix(1_600_000_000_000)));
+        data.logs[0].characters = 2_000;
+        migrate(&mut connection, data, false, &clock).unwrap();
+
+        assert_eq!(
+            log_times(&mut connection),
+            vec![1_600_000_000, 1_700_000_000]
+        );
+    }
+
+    #[test]
+    fn rows_are_migrated_once() {
+        let mut connection = connection();
+        let clock = FakeClock::new(Utc.timestamp_opt(1_700_000_000, 0).unwrap());
+
+        let deltas = migrate(&mut connection, old_data("a", None), true, &clock).unwrap();
+        assert_eq!(deltas[&1].new_total, 1_000);
+        // a dry run doesn't add anything, not even the batch
+        migrate(&mut connection, old_data("a", None), false, &clock).unwrap();
+        let error = migrate(&mut connection, old_data("a", None), false, &clock).unwrap_err();
+        assert!(error.to_string().contains("already migrated"));
+        assert_eq!(log_times(&mut connection).len(), 1);
+    }
+
+    #[test]
+    fn rows_migrated_before_batches_were_recorded_are_skipped() {
+        let mut connection = connection();
+        let clock = FakeClock::new(Utc.timestamp_opt(1_800_000_000, 0).unwrap());
+        {
+            // what the first version of --migrate logged, at the time of the migration
+            let tx = connection.transaction().unwrap();
+            SQLiteCharacterStatisticsRepository::new(&tx)
+                .add_log_entry(
+                    1,
+                    "Unknown",
+                    1_000,
+                    &Utc.timestamp_opt(1_700_000_000, 0).unwrap(),
+                    Some(MIGRATION_NOTES.to_owned()),
+                )
+                .unwrap();
+            tx.commit().unwrap();
+        }
+
+        let mut data = old_data("a", Some(OldTimestamp::Unix(1_600_000_000_000)));
+        data.logs.push(OldCharacterLog {
+            characters: 1_000,
+            user_id: 1,
+            timestamp: Some(OldTimestamp::Unix(1_650_000_000_000)),
+            notes: None,
+            name: None,
+        });
+        // a different amount is a different row, even with the same notes
+        data.logs.push(OldCharacterLog {
+            characters: 500,
+            user_id: 1,
+            timestamp: Some(OldTimestamp::Unix(1_600_000_000_000)),
+            notes: None,
+            name: None,
+        });
+        let deltas = migrate(&mut connection, data, false, &clock).unwrap();
+        // only one of the rows was in the log history
+        assert_eq!(deltas[&1].skipped, 1);
+        assert_eq!(deltas[&1].entries, 2);
+        assert_eq!(deltas[&1].new_total, 2_500);
+    }
 }
@@ -1,27 +1,37 @@
-use std::sync::Mutex;
+use std::{fmt, sync::Arc};
 
+use chrono::{DateTime, Utc};
+use poise::ChoiceParameter;
 use reqwest::Client;
-use rusqlite::Connection;
 use serde::{Deserialize, Serialize};
-use serenity::all::Timestamp;
+use serenity::all::{Member, Timestamp};
+
+use crate::{
+    clock::Clock, database::Database, roles::QuizRoles, scheduler::Scheduler, timezone::Timezone,
+    Error,
+};
 
 // Custom user data passed to all command functions
 pub struct Data {
-    /// connection to sqlite for db
-    pub connection: Mutex<Connection>,
+    /// the sqlite db, queries run off the async runtime
+    pub database: Database,
     /// needed to make calls to the kotoba API for quizzes
     pub http_client: Client,
+    /// every lookup of the current time goes through this
+    pub clock: Arc<dyn Clock>,
+    /// runs the recurring jobs, i.e. backups
+    pub scheduler: Scheduler,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct CharacterStatistics {
     user_id: u64,
     pub name: String,
-    pub total_characters: i32,
+    pub total_characters: i64,
 }
 
 impl CharacterStatistics {
-    pub fn new(user_id: u64, total_characters: i32, name: String) -> CharacterStatistics {
+    pub fn new(user_id: u64, total_characters: i64, name: String) -> CharacterStatistics {
         CharacterStatistics {
             total_characters,
             user_id,
@@ -37,13 +47,17 @@ impl CharacterStatistics {
 #[derive(Debug, Deserialize, Serialize)]
 pub struct CharacterLogEntry {
     user_id: u64,
-    characters: i32,
+    characters: i64,
     time: Timestamp,
     notes: Option<String>,
+    /// the admin who made this entry for the user, None if the user logged it themselves
+    edited_by: Option<u64>,
+    /// None for logs made before media types existed or without one
+    media_type: Option<MediaType>,
 }
 
 impl CharacterLogEntry {
-    pub fn characters(&self) -> i32 {
+    pub fn characters(&self) -> i64 {
         self.characters
     }
 
@@ -55,17 +69,313 @@ impl CharacterLogEntry {
         &self.notes
     }
 
+    pub fn edited_by(&self) -> Option<u64> {
+        self.edited_by
+    }
+
+    pub fn media_type(&self) -> Option<MediaType> {
+        self.media_type
+    }
+
     pub fn new(
         user_id: u64,
-        characters: i32,
+        characters: i64,
         time: &Timestamp,
         notes: Option<String>,
+        edited_by: Option<u64>,
+        media_type: Option<MediaType>,
     ) -> CharacterLogEntry {
         CharacterLogEntry {
             user_id,
             characters,
             time: time.to_owned(),
             notes,
+            edited_by,
+            media_type,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct QuizAttempt {
+    user_id: u64,
+    quiz_role: QuizRoles,
+    score: i32,
+    /// false when the score was reached but the character gate kept the role back
+    passed: bool,
+    time: Timestamp,
+}
+
+impl QuizAttempt {
+    pub fn user_id(&self) -> u64 {
+        self.user_id
+    }
+
+    pub fn quiz_role(&self) -> QuizRoles {
+        self.quiz_role
+    }
+
+    pub fn score(&self) -> i32 {
+        self.score
+    }
+
+    pub fn passed(&self) -> bool {
+        self.passed
+    }
+
+    pub fn time(&self) -> &Timestamp {
+        &self.time
+    }
+
+    pub fn new(
+        user_id: u64,
+        quiz_role: QuizRoles,
+        score: i32,
+        passed: bool,
+        time: &Timestamp,
+    ) -> QuizAttempt {
+        QuizAttempt {
+            user_id,
+            quiz_role,
+            score,
+            passed,
+            time: time.to_owned(),
+        }
+    }
+}
+
+/// Aggregated results of every counted attempt on a quiz
+#[derive(Debug)]
+pub struct QuizStatistics {
+    pub attempts: u64,
+    pub passes: u64,
+    pub average_score: f64,
+    pub best_score: i32,
+}
+
+impl QuizStatistics {
+    /// Returns the pass rate as a percentage
+    pub fn pass_rate(&self) -> f64 {
+        if self.attempts == 0 {
+            return 0.0;
+        }
+        self.passes as f64 * 100.0 / self.attempts as f64
+    }
+}
+
+/// The first time a user passed a quiz
+#[derive(Debug)]
+pub struct QuizPass {
+    pub user_id: u64,
+    pub name: Option<String>,
+    pub time: Timestamp,
+}
+
+/// A user whose stored total doesn't match the sum of their log entries
+#[derive(Debug)]
+pub struct TotalMismatch {
+    pub user_id: u64,
+    pub name: String,
+    pub stored_total: i64,
+    pub computed_total: i64,
+}
+
+/// A log above the moderation thresholds, it doesn't count until a moderator approves it
+#[derive(Debug)]
+pub struct PendingLogEntry {
+    pub id: u64,
+    pub user_id: u64,
+    pub name: String,
+    pub characters: i64,
+    pub time: Timestamp,
+    pub notes: Option<String>,
+    pub media_type: Option<MediaType>,
+}
+
+/// The kinds of changes to someone else's characters that are recorded in the audit log
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuditAction {
+    EditCharacters,
+    RepairTotal,
+    ApproveLog,
+    RejectLog,
+    SetTotal,
+    MergeUsers,
+    Wipe,
+}
+
+impl fmt::Display for AuditAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let string = match self {
+            Self::EditCharacters => "edit_characters",
+            Self::RepairTotal => "repair_total",
+            Self::ApproveLog => "approve_log",
+            Self::RejectLog => "reject_log",
+            Self::SetTotal => "set_total",
+            Self::MergeUsers => "merge_users",
+            Self::Wipe => "wipe",
+        };
+        f.write_str(string)
+    }
+}
+
+impl AuditAction {
+    pub fn from_string(string: &str) -> Option<AuditAction> {
+        match string {
+            "edit_characters" => Some(Self::EditCharacters),
+            "repair_total" => Some(Self::RepairTotal),
+            "approve_log" => Some(Self::ApproveLog),
+            "reject_log" => Some(Self::RejectLog),
+            "set_total" => Some(Self::SetTotal),
+            "merge_users" => Some(Self::MergeUsers),
+            "wipe" => Some(Self::Wipe),
+            _ => None,
+        }
+    }
+}
+
+/// One admin-initiated change, actor_id is CLI_ACTOR_ID for changes made from the command line
+#[derive(Debug)]
+pub struct AuditEntry {
+    pub actor_id: u64,
+    pub target_id: u64,
+    pub action: AuditAction,
+    /// the change of the target's total characters
+    pub delta: i64,
+    pub previous_total: i64,
+    pub reason: String,
+    /// the log entry made by the change, if any
+    pub log_entry_id: Option<u64>,
+    pub time: Timestamp,
+}
+
+/// The two names a member is shown with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemberNames {
+    /// the global display name, or the username if they don't have one
+    pub global_name: String,
+    /// the nickname on the server, shown instead of the global name when set
+    pub nickname: Option<String>,
+}
+
+impl MemberNames {
+    pub fn new(global_name: &str, nickname: Option<&str>) -> MemberNames {
+        MemberNames {
+            global_name: global_name.to_owned(),
+            nickname: nickname.map(|nickname| nickname.to_owned()),
+        }
+    }
+
+    pub fn of(member: &Member) -> MemberNames {
+        MemberNames::new(member.user.display_name(), member.nick.as_deref())
+    }
+}
+
+/// The names a member had from `time` on
+#[derive(Debug, Clone)]
+pub struct NameChange {
+    pub user_id: u64,
+    pub names: MemberNames,
+    pub time: Timestamp,
+}
+
+/// The last run of a scheduled job
+#[derive(Debug, Clone)]
+pub struct JobRun {
+    pub job: String,
+    /// when the run started
+    pub time: DateTime<Utc>,
+    /// why the run failed, None if it succeeded
+    pub error: Option<String>,
+}
+
+/// What was read, stored with each log
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize, poise::ChoiceParameter)]
+#[serde(rename_all = "snake_case")]
+pub enum MediaType {
+    #[name = "novel"]
+    Novel,
+    #[name = "visual_novel"]
+    VisualNovel,
+    #[name = "manga"]
+    Manga,
+    #[name = "anime"]
+    Anime,
+    #[name = "game"]
+    Game,
+    #[name = "podcast"]
+    Podcast,
+    #[name = "other"]
+    Other,
+}
+
+/// The choices of /settings me for the default media type, the media types and none to clear it
+#[derive(Debug, Clone, Copy, PartialEq, poise::ChoiceParameter)]
+pub enum DefaultMediaType {
+    #[name = "none"]
+    None,
+    #[name = "novel"]
+    Novel,
+    #[name = "visual_novel"]
+    VisualNovel,
+    #[name = "manga"]
+    Manga,
+    #[name = "anime"]
+    Anime,
+    #[name = "game"]
+    Game,
+    #[name = "podcast"]
+    Podcast,
+    #[name = "other"]
+    Other,
+}
+
+impl DefaultMediaType {
+    /// The media type with the same name, None for none
+    pub fn media_type(self) -> Option<MediaType> {
+        MediaType::from_name(self.name())
+    }
+}
+
+/// The preferences a user set with /settings me
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserSettings {
+    pub user_id: u64,
+    /// an IANA name, i.e. Asia/Tokyo, dates are shown and grouped in it
+    pub timezone: String,
+    /// used for logs that don't say what was read
+    pub default_media_type: Option<MediaType>,
+    /// whether others can see their /history
+    pub history_visible: bool,
+    pub show_on_leaderboard: bool,
+    /// whether /remind sends its DMs, turning it off keeps the reminder
+    pub reminder_dms: bool,
+    /// whether they get a DM when a moderator reviews their log
+    pub review_dms: bool,
+}
+
+impl UserSettings {
+    /// The settings of users who never changed them
+    pub fn new(user_id: u64) -> UserSettings {
+        UserSettings {
+            user_id,
+            timezone: "UTC".to_owned(),
+            default_media_type: None,
+            history_visible: true,
+            show_on_leaderboard: true,
+            reminder_dms: true,
+            review_dms: true,
         }
     }
+
+    /// The timezone to show dates in, an error if it's missing from the tz database
+    pub fn timezone(&self) -> Result<Timezone, Error> {
+        Timezone::load(&self.timezone).ok_or_else(|| {
+            format!(
+                "The timezone `{}` can't be loaded from the tz database.",
+                self.timezone
+            )
+            .into()
+        })
+    }
 }
@@ -0,0 +1,298 @@
+use std::env::var;
+
+use serenity::all::{
+    ButtonStyle, CacheHttp, ChannelId, ComponentInteraction, CreateActionRow, CreateButton,
+    CreateInteractionResponse, CreateInteractionResponseMessage, CreateMessage, Permissions,
+    UserId,
+};
+
+use crate::{
+    commands::create_base_embed,
+    constants::{
+        MODERATION_ABSOLUTE_THRESHOLD, MODERATION_RECENT_ENTRIES, MODERATION_RELATIVE_MINIMUM,
+        MODERATION_RELATIVE_THRESHOLD,
+    },
+    model::{AuditAction, AuditEntry, Data, PendingLogEntry},
+    repository::{
+        AuditRepository, CharacterStatisticsRepository, ModerationRepository,
+        SQLiteAuditRepository, SQLiteCharacterStatisticsRepository, SQLiteModerationRepository,
+        SQLiteUserSettingsRepository, UserSettingsRepository,
+    },
+    roles::update_member_roles,
+    utils::format_with_commas,
+    Error,
+};
+
+const APPROVE_PREFIX: &str = "moderation_approve:";
+const REJECT_PREFIX: &str = "moderation_reject:";
+
+/// The channel where large logs wait for approval, moderation is disabled without it
+pub fn moderation_channel() -> Option<ChannelId> {
+    var("MODERATION_CHANNEL_ID")
+        .ok()
+        .and_then(|id| id.parse().ok())
+        .map(ChannelId::new)
+}
+
+/// Returns why a log needs a moderator's approval, None if it can be logged right away
+pub fn moderation_reason(
+    characters: i64,
+    recent_entries: u64,
+    recent_average: f64,
+) -> Option<String> {
+    if characters >= MODERATION_ABSOLUTE_THRESHOLD {
+        return Some(format!(
+            "At least {} characters in one log",
+            format_with_commas(MODERATION_ABSOLUTE_THRESHOLD)
+        ));
+    }
+
+    if characters >= MODERATION_RELATIVE_MINIMUM
+        && recent_entries >= MODERATION_RECENT_ENTRIES
+        && characters as f64 > recent_average * MODERATION_RELATIVE_THRESHOLD
+    {
+        return Some(format!(
+            "More than {} times their recent average of {} characters per log",
+            MODERATION_RELATIVE_THRESHOLD,
+            format_with_commas(recent_average.round() as i64)
+        ));
+    }
+
+    None
+}
+
+/// Posts a pending log in the moderation channel with Approve and Reject buttons
+pub async fn send_to_moderation(
+    cache_http: impl CacheHttp,
+    channel_id: ChannelId,
+    entry: &PendingLogEntry,
+    reason: &str,
+) -> Result<(), Error> {
+    let embed = create_base_embed()
+        .title(format!(
+            "{} wants to log {} characters",
+            entry.name,
+            format_with_commas(entry.characters)
+        ))
+        .description(format!(
+            "<@{}> at <t:{}:f>\nNotes: {}",
+            entry.user_id,
+            entry.time.unix_timestamp(),
+            entry.notes.as_deref().unwrap_or("-")
+        ))
+        .field("Reason", reason, false);
+
+    let components = CreateActionRow::Buttons(vec![
+        CreateButton::new(format!("{}{}", APPROVE_PREFIX, entry.id))
+            .label("Approve")
+            .style(ButtonStyle::Success),
+        CreateButton::new(format!("{}{}", REJECT_PREFIX, entry.id))
+            .label("Reject")
+            .style(ButtonStyle::Danger),
+    ]);
+
+    channel_id
+        .send_message(
+            cache_http,
+            CreateMessage::new()
+                .embed(embed)
+                .components(vec![components]),
+        )
+        .await?;
+    Ok(())
+}
+
+/// Handles the Approve and Reject buttons of the moderation channel, other interactions are ignored
+pub async fn handle_moderation_interaction(
+    ctx: &serenity::client::Context,
+    interaction: &ComponentInteraction,
+    data: &Data,
+) -> Result<(), Error> {
+    let custom_id = &interaction.data.custom_id;
+    let (approved, id) = if let Some(id) = custom_id.strip_prefix(APPROVE_PREFIX) {
+        (true, id)
+    } else if let Some(id) = custom_id.strip_prefix(REJECT_PREFIX) {
+        (false, id)
+    } else {
+        return Ok(());
+    };
+    let id: u64 = id.parse()?;
+
+    let is_moderator = interaction
+        .member
+        .as_ref()
+        .and_then(|member| member.permissions)
+        .is_some_and(|permissions| permissions.contains(Permissions::MANAGE_MESSAGES));
+    if !is_moderator {
+        return respond_ephemeral(ctx, interaction, "Only moderators can review logs.").await;
+    }
+
+    let actor_id = interaction.user.id.get();
+    let time = data.clock.timestamp();
+    let result = data
+        .database
+        .write(move |connection| {
+            let tx = connection.transaction()?;
+            let mut moderation_repository = SQLiteModerationRepository::new(&tx);
+            let result = match moderation_repository.take_pending_log_entry(id)? {
+                Some(entry) => {
+                    let mut repository = SQLiteCharacterStatisticsRepository::new(&tx);
+                    let previous_total = repository
+                        .get_statistics(entry.user_id)?
+                        .map_or(0, |statistics| statistics.total_characters);
+                    let (statistics, log_entry_id) = if approved {
+                        let (statistics, log_entry_id) = repository.add_log_entry_with_id(
+                            entry.user_id,
+                            &entry.name,
+                            entry.characters,
+                            &entry.time,
+                            entry.notes.clone(),
+                        )?;
+                        if let (Some(log_entry_id), Some(media_type)) =
+                            (log_entry_id, entry.media_type)
+                        {
+                            repository.set_media_type(log_entry_id, media_type)?;
+                        }
+                        (Some(statistics), log_entry_id)
+                    } else {
+                        (None, None)
+                    };
+
+                    let mut audit_repository = SQLiteAuditRepository::new(&tx);
+                    audit_repository.add_audit_entry(&AuditEntry {
+                        actor_id,
+                        target_id: entry.user_id,
+                        action: if approved {
+                            AuditAction::ApproveLog
+                        } else {
+                            AuditAction::RejectLog
+                        },
+                        delta: statistics
+                            .as_ref()
+                            .map_or(0, |statistics| statistics.total_characters - previous_total),
+                        previous_total,
+                        reason: format!(
+                            "Reviewed a pending log of {} characters",
+                            format_with_commas(entry.characters)
+                        ),
+                        log_entry_id,
+                        time,
+                    })?;
+                    let settings =
+                        SQLiteUserSettingsRepository::new(&tx).get_settings(entry.user_id)?;
+                    Some((entry, statistics, settings.review_dms))
+                }
+                None => None,
+            };
+            tx.commit()?;
+            Ok(result)
+        })
+        .await?;
+
+    let Some((entry, statistics, review_dms)) = result else {
+        return respond_ephemeral(ctx, interaction, "This log was already reviewed.").await;
+    };
+
+    let verdict = if approved { "Approved" } else { "Rejected" };
+    let embed = create_base_embed()
+        .title(format!(
+            "{} {} characters from {}",
+            verdict,
+            format_with_commas(entry.characters),
+            entry.name
+        ))
+        .description(format!(
+            "<@{}> at <t:{}:f>\nNotes: {}\n{} by <@{}>",
+            entry.user_id,
+            entry.time.unix_timestamp(),
+            entry.notes.as_deref().unwrap_or("-"),
+            verdict,
+            interaction.user.id
+        ));
+    interaction
+        .create_response(
+            ctx,
+            CreateInteractionResponse::UpdateMessage(
+                CreateInteractionResponseMessage::new()
+                    .embed(embed)
+                    .components(vec![]),
+            ),
+        )
+        .await?;
+
+    // the member could have left in the meantime, so roles and DMs are best effort
+    let user_id = UserId::new(entry.user_id);
+    let notice = format!(
+        "Your log of {} characters was {} by a moderator.",
+        format_with_commas(entry.characters),
+        verdict.to_lowercase()
+    );
+    if review_dms {
+        if let Ok(channel) = user_id.create_dm_channel(ctx).await {
+            let _ = channel.say(ctx, notice).await;
+        }
+    }
+
+    if let (Some(statistics), Some(guild_id)) = (statistics, interaction.guild_id) {
+        update_member_roles(ctx, guild_id, user_id, &statistics).await?;
+    }
+
+    Ok(())
+}
+
+async fn respond_ephemeral(
+    ctx: &serenity::client::Context,
+    interaction: &ComponentInteraction,
+    message: &str,
+) -> Result<(), Error> {
+    interaction
+        .create_response(
+            ctx,
+            CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content(message)
+                    .ephemeral(true),
+            ),
+        )
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // an average low enough that the relative threshold is crossed by every amount below
+    const LOW_AVERAGE: f64 = 1_000.0;
+
+    #[test]
+    fn absolute_threshold() {
+        assert!(moderation_reason(MODERATION_ABSOLUTE_THRESHOLD - 1, 0, 0.0).is_none());
+        assert!(moderation_reason(MODERATION_ABSOLUTE_THRESHOLD, 0, 0.0).is_some());
+    }
+
+    #[test]
+    fn relative_minimum() {
+        let entries = MODERATION_RECENT_ENTRIES;
+        assert!(moderation_reason(MODERATION_RELATIVE_MINIMUM - 1, entries, LOW_AVERAGE).is_none());
+        assert!(moderation_reason(MODERATION_RELATIVE_MINIMUM, entries, LOW_AVERAGE).is_some());
+    }
+
+    #[test]
+    fn recent_entries() {
+        let characters = MODERATION_RELATIVE_MINIMUM;
+        let entries = MODERATION_RECENT_ENTRIES;
+        assert!(moderation_reason(characters, entries - 1, LOW_AVERAGE).is_none());
+        assert!(moderation_reason(characters, entries, LOW_AVERAGE).is_some());
+    }
+
+    #[test]
+    fn relative_threshold() {
+        let characters = MODERATION_RELATIVE_MINIMUM;
+        let entries = MODERATION_RECENT_ENTRIES;
+        // exactly the threshold times the average is still fine, only more than that is moderated
+        let average = characters as f64 / MODERATION_RELATIVE_THRESHOLD;
+        assert!(moderation_reason(characters, entries, average).is_none());
+        assert!(moderation_reason(characters, entries, average - 1.0).is_some());
+    }
+}
@@ -0,0 +1,475 @@
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, Utc, Weekday};
+use poise::serenity_prelude as serenity;
+use serenity::all::{
+    ActionRowComponent, ButtonStyle, ComponentInteraction, CreateActionRow, CreateButton,
+    CreateInputText, CreateInteractionResponse, CreateInteractionResponseMessage, CreateMessage,
+    CreateModal, EditInteractionResponse, InputTextStyle, ModalInteraction, UserId,
+};
+
+use crate::{
+    commands::{create_base_embed, format_role_progress, log_characters_for_user, LogOutcome},
+    constants::REMINDER_GRACE_PERIOD,
+    model::Data,
+    repository::{
+        CharacterStatisticsRepository, ReminderRepository, SQLiteCharacterStatisticsRepository,
+        SQLiteReminderRepository, SQLiteUserSettingsRepository, UserSettingsRepository,
+    },
+    roles::update_member_roles,
+    scheduler::{configured_guild, JobContext},
+    service::RoleProgress,
+    timezone::Timezone,
+    utils::{check_entry_characters, format_with_commas},
+    Error,
+};
+
+const LOG_NOW_ID: &str = "reminder_log_now";
+const LOG_MODAL_ID: &str = "reminder_log_modal";
+const CHARACTERS_INPUT_ID: &str = "characters";
+const NOTES_INPUT_ID: &str = "notes";
+
+/// On which days a reminder is sent
+#[derive(Clone, Copy, Debug, PartialEq, poise::ChoiceParameter)]
+pub enum ReminderFrequency {
+    #[name = "daily"]
+    Daily,
+    #[name = "weekdays"]
+    Weekdays,
+    #[name = "weekends"]
+    Weekends,
+}
+
+impl ReminderFrequency {
+    fn includes(&self, day: Weekday) -> bool {
+        let weekend = matches!(day, Weekday::Sat | Weekday::Sun);
+        match self {
+            Self::Daily => true,
+            Self::Weekdays => !weekend,
+            Self::Weekends => weekend,
+        }
+    }
+}
+
+/// A user's opt-in reminder to log, the time of day is in the timezone of their settings
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reminder {
+    pub user_id: u64,
+    pub time: NaiveTime,
+    pub frequency: ReminderFrequency,
+    /// skips the reminder on days they already logged something
+    pub only_if_not_logged: bool,
+    /// when the reminder was last handled, whether it was sent or skipped
+    pub last_sent: Option<DateTime<Utc>>,
+}
+
+impl Reminder {
+    /// The reminder time on a local day, None if that day isn't included
+    fn time_on(&self, date: NaiveDate, timezone: &Timezone) -> Option<DateTime<Utc>> {
+        if !self.frequency.includes(date.weekday()) {
+            return None;
+        }
+        Some(timezone.local_to_utc(date.and_time(self.time)))
+    }
+
+    /// The latest time the reminder was due at or before `now`
+    pub fn last_due(&self, now: DateTime<Utc>, timezone: &Timezone) -> Option<DateTime<Utc>> {
+        let today = timezone.to_local(now).date_naive();
+        (0..8)
+            .filter_map(|days| self.time_on(today - Duration::days(days), timezone))
+            .find(|time| *time <= now)
+    }
+
+    /// The first time the reminder is due after `now`
+    pub fn next_due(&self, now: DateTime<Utc>, timezone: &Timezone) -> Option<DateTime<Utc>> {
+        let today = timezone.to_local(now).date_naive();
+        (0..8)
+            .filter_map(|days| self.time_on(today + Duration::days(days), timezone))
+            .find(|time| *time > now)
+    }
+
+    /// Whether the reminder should be handled now. Reminders missed for longer than
+    /// REMINDER_GRACE_PERIOD, i.e. while the bot was offline, wait for the next time.
+    pub fn is_due(&self, now: DateTime<Utc>, timezone: &Timezone) -> bool {
+        self.last_due(now, timezone).is_some_and(|due| {
+            now - due <= REMINDER_GRACE_PERIOD && self.last_sent.is_none_or(|sent| sent < due)
+        })
+    }
+}
+
+/// Marks every due reminder as handled and returns the ones to send. The ones of users who turned off
+/// reminder DMs are skipped, and the ones of users who already logged that day if they asked for it.
+pub fn take_due_reminders(
+    reminder_repository: &mut impl ReminderRepository,
+    settings_repository: &impl UserSettingsRepository,
+    repository: &mut impl CharacterStatisticsRepository,
+    now: DateTime<Utc>,
+) -> Result<Vec<Reminder>, Error> {
+    let mut due = Vec::new();
+    for reminder in reminder_repository.get_reminders()? {
+        let settings = settings_repository.get_settings(reminder.user_id)?;
+        // without its timezone the reminder would be sent at the wrong time
+        let timezone = match settings.timezone() {
+            Ok(timezone) => timezone,
+            Err(error) => {
+                println!("Skipped the reminder of {}: {}", reminder.user_id, error);
+                continue;
+            }
+        };
+        if !reminder.is_due(now, &timezone) {
+            continue;
+        }
+        reminder_repository.set_reminder_sent(reminder.user_id, &now)?;
+
+        if !settings.reminder_dms {
+            continue;
+        }
+        if reminder.only_if_not_logged {
+            let since = timezone.start_of_day(now);
+            let (logs_today, _) = repository.get_log_average_since(reminder.user_id, &since)?;
+            if logs_today > 0 {
+                continue;
+            }
+        }
+        due.push(reminder);
+    }
+    Ok(due)
+}
+
+/// DMs every user whose reminder is due
+pub async fn send_reminders(context: &JobContext) -> Result<(), Error> {
+    let now = context.clock.now();
+    let due = context
+        .database
+        .write(move |connection| {
+            let tx = connection.transaction()?;
+            let due = take_due_reminders(
+                &mut SQLiteReminderRepository::new(&tx),
+                &SQLiteUserSettingsRepository::new(&tx),
+                &mut SQLiteCharacterStatisticsRepository::new(&tx),
+                now,
+            )?;
+            tx.commit()?;
+            Ok(due)
+        })
+        .await?;
+
+    for reminder in due {
+        // closed DMs of one user shouldn't keep the others from getting theirs
+        if let Err(error) = send_reminder(&context.serenity, reminder.user_id).await {
+            println!(
+                "Failed to send a reminder to {}: {}",
+                reminder.user_id, error
+            );
+        }
+    }
+    Ok(())
+}
+
+async fn send_reminder(ctx: &serenity::Context, user_id: u64) -> Result<(), Error> {
+    let embed = create_base_embed()
+        .title("Time to log your immersion!")
+        .description("Press Log now to log what you read today. You can change or turn off these reminders with /remind.");
+    let components = CreateActionRow::Buttons(vec![CreateButton::new(LOG_NOW_ID)
+        .label("Log now")
+        .style(ButtonStyle::Primary)]);
+
+    UserId::new(user_id)
+        .direct_message(
+            ctx,
+            CreateMessage::new()
+                .embed(embed)
+                .components(vec![components]),
+        )
+        .await?;
+    Ok(())
+}
+
+/// Opens the log modal when Log now is pressed on a reminder, other interactions are ignored
+pub async fn handle_reminder_interaction(
+    ctx: &serenity::Context,
+    interaction: &ComponentInteraction,
+) -> Result<(), Error> {
+    if interaction.data.custom_id != LOG_NOW_ID {
+        return Ok(());
+    }
+
+    let modal = CreateModal::new(LOG_MODAL_ID, "Log characters").components(vec![
+        CreateActionRow::InputText(
+            CreateInputText::new(
+                InputTextStyle::Short,
+                "Characters read",
+                CHARACTERS_INPUT_ID,
+            )
+            .placeholder("4000"),
+        ),
+        CreateActionRow::InputText(
+            CreateInputText::new(InputTextStyle::Short, "Notes", NOTES_INPUT_ID)
+                .placeholder("Episode 1 of Love Live season 1")
+                .required(false),
+        ),
+    ]);
+    interaction
+        .create_response(ctx, CreateInteractionResponse::Modal(modal))
+        .await?;
+    Ok(())
+}
+
+/// Logs the characters of a submitted log modal the same way /log_characters does, other modals are ignored
+pub async fn handle_reminder_modal(
+    ctx: &serenity::Context,
+    interaction: &ModalInteraction,
+    data: &Data,
+) -> Result<(), Error> {
+    if interaction.data.custom_id != LOG_MODAL_ID {
+        return Ok(());
+    }
+
+    let input = |custom_id: &str| {
+        interaction
+            .data
+            .components
+            .iter()
+            .flat_map(|row| &row.components)
+            .find_map(|component| match component {
+                ActionRowComponent::InputText(input) if input.custom_id == custom_id => {
+                    input.value.clone()
+                }
+                _ => None,
+            })
+            .filter(|value| !value.trim().is_empty())
+    };
+
+    let characters = input(CHARACTERS_INPUT_ID).unwrap_or_default();
+    let characters = match characters.trim().replace(',', "").parse::<i64>() {
+        Ok(characters) => characters,
+        Err(_) => {
+            let message = format!("{} is not an amount of characters.", characters.trim());
+            return respond(ctx, interaction, &message).await;
+        }
+    };
+    if let Err(error) = check_entry_characters(characters) {
+        return respond(ctx, interaction, &error.to_string()).await;
+    }
+
+    // updating the roles can take longer than discord waits for a response
+    interaction.defer(ctx).await?;
+
+    let user_id = interaction.user.id;
+    let name = interaction.user.display_name().to_owned();
+    let notes = input(NOTES_INPUT_ID).map(|notes| notes.trim().to_owned());
+    let outcome = log_characters_for_user(
+        ctx,
+        data,
+        user_id.get(),
+        name.clone(),
+        characters,
+        notes,
+        None,
+    )
+    .await?;
+
+    let mut response = EditInteractionResponse::new();
+    let embed = match outcome {
+        LogOutcome::Pending => create_base_embed().description(format!(
+            "Your log of {} characters is waiting for a moderator's approval. It will count towards your total once approved.",
+            format_with_commas(characters)
+        )),
+        LogOutcome::Logged(result) => {
+            // DMs have no guild, so the roles are updated on the configured one
+            let mut roles = None;
+            if let Some(guild_id) = configured_guild() {
+                if let Some((previous_roles, congratulation)) =
+                    update_member_roles(ctx, guild_id, user_id, &result.statistics).await?
+                {
+                    // the same congratulation /log_characters replies with
+                    if let Some(congratulation) = congratulation {
+                        response = response.content(congratulation);
+                    }
+                    roles = Some(previous_roles);
+                }
+            }
+
+            let rank_message = match result.rank {
+                Some(rank) => format!("You are currently rank {} on the leaderboard", rank),
+                None => "You are currently not on the leaderboard".to_owned(),
+            };
+            let progress = RoleProgress::new(
+                &roles.map(|roles| roles.quizzes).unwrap_or_default(),
+                result.statistics.total_characters,
+            );
+            create_base_embed()
+                .title(format!(
+                    "{} logged {} characters!",
+                    name,
+                    format_with_commas(characters)
+                ))
+                .description(format!(
+                    "Total characters logged: {}",
+                    format_with_commas(result.statistics.total_characters)
+                ))
+                .field(rank_message, format_role_progress(&progress), false)
+        }
+    };
+    interaction
+        .edit_response(ctx, response.embed(embed))
+        .await?;
+    Ok(())
+}
+
+async fn respond(
+    ctx: &serenity::Context,
+    interaction: &ModalInteraction,
+    message: &str,
+) -> Result<(), Error> {
+    interaction
+        .create_response(
+            ctx,
+            CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .embed(create_base_embed().description(message)),
+            ),
+        )
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::{
+        memory_repository::{
+            MemoryCharacterStatisticsRepository, MemoryReminderRepository,
+            MemoryUserSettingsRepository,
+        },
+        model::UserSettings,
+    };
+
+    fn utc(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        // 2025-03-03 is a monday
+        Utc.with_ymd_and_hms(2025, 3, day, hour, minute, 0).unwrap()
+    }
+
+    fn reminder(frequency: ReminderFrequency) -> Reminder {
+        Reminder {
+            user_id: 1,
+            time: NaiveTime::from_hms_opt(21, 0, 0).unwrap(),
+            frequency,
+            only_if_not_logged: false,
+            last_sent: None,
+        }
+    }
+
+    #[test]
+    fn reminders_are_due_at_the_local_time() {
+        let reminder = reminder(ReminderFrequency::Daily);
+        let utc_timezone = Timezone::utc();
+        assert!(!reminder.is_due(utc(3, 20, 59), &utc_timezone));
+        assert!(reminder.is_due(utc(3, 21, 0), &utc_timezone));
+        assert!(reminder.is_due(utc(3, 21, 30), &utc_timezone));
+        assert_eq!(
+            reminder.next_due(utc(3, 21, 0), &utc_timezone),
+            Some(utc(4, 21, 0))
+        );
+
+        let sent = Reminder {
+            last_sent: Some(utc(3, 21, 1)),
+            ..reminder.clone()
+        };
+        assert!(!sent.is_due(utc(3, 21, 30), &utc_timezone));
+        assert!(sent.is_due(utc(4, 21, 0), &utc_timezone));
+
+        // missed while offline
+        assert!(!reminder.is_due(utc(3, 23, 0), &utc_timezone));
+    }
+
+    #[test]
+    fn frequencies_skip_days() {
+        let utc_timezone = Timezone::utc();
+        let weekdays = reminder(ReminderFrequency::Weekdays);
+        // friday the 7th is a weekday, saturday the 8th isn't
+        assert!(weekdays.is_due(utc(7, 21, 0), &utc_timezone));
+        assert!(!weekdays.is_due(utc(8, 21, 0), &utc_timezone));
+        assert_eq!(
+            weekdays.next_due(utc(7, 21, 0), &utc_timezone),
+            Some(utc(10, 21, 0))
+        );
+
+        let weekends = reminder(ReminderFrequency::Weekends);
+        assert!(!weekends.is_due(utc(7, 21, 0), &utc_timezone));
+        assert!(weekends.is_due(utc(8, 21, 0), &utc_timezone));
+        assert_eq!(
+            weekends.last_due(utc(10, 21, 0), &utc_timezone),
+            Some(utc(9, 21, 0))
+        );
+    }
+
+    #[test]
+    fn take_due_reminders_uses_the_settings() {
+        let mut reminders = MemoryReminderRepository::new();
+        let mut settings = MemoryUserSettingsRepository::new();
+        let mut repository = MemoryCharacterStatisticsRepository::new();
+        for user_id in 1..=3 {
+            reminders
+                .set_reminder(&Reminder {
+                    user_id,
+                    only_if_not_logged: true,
+                    ..reminder(ReminderFrequency::Daily)
+                })
+                .unwrap();
+        }
+        settings
+            .set_settings(&UserSettings {
+                reminder_dms: false,
+                ..UserSettings::new(3)
+            })
+            .unwrap();
+
+        // user 1 already logged today, user 2 only yesterday
+        repository
+            .add_log_entry(1, "user", 100, &utc(3, 1, 0), None)
+            .unwrap();
+        repository
+            .add_log_entry(2, "user", 100, &utc(2, 23, 0), None)
+            .unwrap();
+
+        let due =
+            take_due_reminders(&mut reminders, &settings, &mut repository, utc(3, 21, 0)).unwrap();
+        assert_eq!(
+            due.iter()
+                .map(|reminder| reminder.user_id)
+                .collect::<Vec<_>>(),
+            vec![2]
+        );
+        // all of them were handled, so none is sent again that day
+        let due =
+            take_due_reminders(&mut reminders, &settings, &mut repository, utc(3, 21, 5)).unwrap();
+        assert!(due.is_empty());
+        assert_eq!(
+            reminders.get_reminder(3).unwrap().unwrap().last_sent,
+            Some(utc(3, 21, 0))
+        );
+    }
+
+    #[test]
+    fn reminders_without_a_loadable_timezone_are_skipped() {
+        let mut reminders = MemoryReminderRepository::new();
+        let mut settings = MemoryUserSettingsRepository::new();
+        let mut repository = MemoryCharacterStatisticsRepository::new();
+        reminders
+            .set_reminder(&reminder(ReminderFrequency::Daily))
+            .unwrap();
+        settings
+            .set_settings(&UserSettings {
+                timezone: "Nowhere/Gone".to_owned(),
+                ..UserSettings::new(1)
+            })
+            .unwrap();
+
+        let due =
+            take_due_reminders(&mut reminders, &settings, &mut repository, utc(3, 21, 0)).unwrap();
+        assert!(due.is_empty());
+        // it's sent once the timezone is picked again
+        assert_eq!(reminders.get_reminder(1).unwrap().unwrap().last_sent, None);
+    }
+}
@@ -1,28 +1,56 @@
 use std::ops::Neg;
 
 use crate::{
-    constants::{LEADERBOARD_PAGE_SIZE, LOG_ENTRY_PAGE_SIZE},
+    constants::{
+        AUDIT_PAGE_SIZE, LEADERBOARD_PAGE_SIZE, LOG_ENTRY_PAGE_SIZE, NAME_HISTORY_PAGE_SIZE,
+        QUIZ_PASS_PAGE_SIZE,
+    },
     Error,
 };
-use chrono::{DateTime, TimeZone, Utc};
+use chrono::{DateTime, NaiveTime, TimeZone, Utc};
+use poise::ChoiceParameter;
 use rusqlite::{params, OptionalExtension, Transaction};
 use serenity::all::Timestamp;
 
-use crate::model::{CharacterLogEntry, CharacterStatistics};
+use crate::{
+    model::{
+        AuditAction, AuditEntry, CharacterLogEntry, CharacterStatistics, JobRun, MediaType,
+        MemberNames, NameChange, PendingLogEntry, QuizAttempt, QuizPass, QuizStatistics,
+        TotalMismatch, UserSettings,
+    },
+    reminders::{Reminder, ReminderFrequency},
+    roles::QuizRoles,
+};
 
 pub trait CharacterStatisticsRepository {
     fn add_log_entry(
         &mut self,
         user_id: u64,
         name: &str,
-        characters: i32,
+        characters: i64,
         time: &DateTime<Utc>,
         notes: Option<String>,
-    ) -> Result<CharacterStatistics, Error>;
+    ) -> Result<CharacterStatistics, Error> {
+        let (statistics, _) = self.add_log_entry_with_id(user_id, name, characters, time, notes)?;
+        Ok(statistics)
+    }
+
+    /// Same as add_log_entry, but also returns the id of the new log entry. None if nothing was inserted because the log was empty.
+    fn add_log_entry_with_id(
+        &mut self,
+        user_id: u64,
+        name: &str,
+        characters: i64,
+        time: &DateTime<Utc>,
+        notes: Option<String>,
+    ) -> Result<(CharacterStatistics, Option<u64>), Error>;
 
     /// Checks if a user has logged before. Doesn't add the user to the db.
     fn exists(&self, user_id: u64) -> Result<bool, Error>;
 
+    /// Returns the statistics of a user with the name stored in the db, None if the user never logged. Doesn't add the user to the db.
+    fn get_statistics(&self, user_id: u64) -> Result<Option<CharacterStatistics>, Error>;
+
     /// Returns the total logged characters of a user. If the user doesn't exist in the db, this also inserts the user to the db.
     fn get_or_initialize_statistics(
         &mut self,
@@ -30,7 +58,8 @@ pub trait CharacterStatisticsRepository {
         name: &str,
     ) -> Result<CharacterStatistics, Error>;
 
-    fn get_rank(&mut self, statistics: &CharacterStatistics) -> Result<i32, Error>;
+    /// Returns the position of a user on the leaderboard, None if they aren't on it because they're inactive, have no characters or hid themselves in their settings
+    fn get_rank(&mut self, statistics: &CharacterStatistics) -> Result<Option<i32>, Error>;
 
     /// Inactive means that the user has left the server and won't be shown in the leaderboards
     /// Because the user could already be gone when we change the active status, we don't always know their latest_name
@@ -42,7 +71,14 @@ pub trait CharacterStatisticsRepository {
         latest_name: Option<&str>,
     ) -> Result<(), Error>;
 
+    /// Records when a user was last seen on the server, i.e. when they joined, left or used a command. Unknown users are ignored.
+    fn set_last_seen(&mut self, user_id: u64, time: &DateTime<Utc>) -> Result<(), Error>;
+
+    /// Stores the global name as the name and the nickname separately, the leaderboard shows the nickname when set. Unknown users are ignored.
+    fn set_names(&mut self, user_id: u64, names: &MemberNames) -> Result<(), Error>;
+
     /// Returns a list of active users according to the (LEADERBOARD_PAGE_SIZE constant), sorted by the amount of characters logged descendingly.
+    /// Users who hid themselves from the leaderboard in their settings are left out, here and in get_total_active_users.
     fn get_paginated_active_users_by_characters(
         &mut self,
         page_number: u64,
@@ -64,11 +100,187 @@ pub trait CharacterStatisticsRepository {
     ) -> Result<Vec<CharacterLogEntry>, Error>;
 
     fn get_total_log_entries(&mut self, user_id: u64) -> Result<u64, Error>;
+
+    /// Returns how many positive log entries a user made at or after `since`, and their average size
+    fn get_log_average_since(
+        &mut self,
+        user_id: u64,
+        since: &DateTime<Utc>,
+    ) -> Result<(u64, f64), Error>;
+
+    /// Returns the active users on the leaderboard with the sum of their log entries made at or after `since` and before `until`, as their total.
+    /// None for `since` sums every entry before `until`. Sorted by the sum descendingly.
+    fn get_log_totals_between(
+        &mut self,
+        since: Option<&DateTime<Utc>>,
+        until: &DateTime<Utc>,
+    ) -> Result<Vec<CharacterStatistics>, Error>;
+
+    /// Returns every log entry of a user, sorted by time created ascendingly
+    fn get_all_log_entries(&mut self, user_id: u64) -> Result<Vec<CharacterLogEntry>, Error>;
+
+    /// Returns the users whose total_characters differs from the sum of their log entries, sorted by the user id.
+    fn get_total_mismatches(&mut self) -> Result<Vec<TotalMismatch>, Error>;
+
+    /// Overwrites the stored total of a user without adding a log entry
+    fn set_total_characters(&mut self, user_id: u64, total_characters: i64) -> Result<(), Error>;
+
+    /// Moves every log entry of a user to another user, returns how many were moved. Doesn't change the totals.
+    fn move_log_entries(&mut self, from_user_id: u64, to_user_id: u64) -> Result<u64, Error>;
+
+    /// Deletes every log entry of a user, returns how many were deleted. Doesn't change the total.
+    fn delete_log_entries(&mut self, user_id: u64) -> Result<u64, Error>;
+
+    fn set_media_type(&mut self, log_entry_id: u64, media_type: MediaType) -> Result<(), Error>;
 }
 
 pub trait MetadataRepository {
-    fn get_last_active_status_refresh(&self) -> Result<Option<DateTime<Utc>>, Error>;
-    fn set_last_active_status_refresh(&mut self, time: DateTime<Utc>) -> Result<(), Error>;
+    /// Returns the last run of a scheduled job, None if it never ran
+    fn get_job_run(&self, job: &str) -> Result<Option<JobRun>, Error>;
+    /// Replaces the last run of a scheduled job
+    fn set_job_run(&mut self, run: &JobRun) -> Result<(), Error>;
+    /// Forgets the last run of a job, so the scheduler treats it like it never ran
+    fn clear_job_run(&mut self, job: &str) -> Result<(), Error>;
+
+    /// Checks if a migration file with this batch id was already applied
+    fn is_migration_applied(&self, batch_id: &str) -> Result<bool, Error>;
+    /// Checks if any migration was recorded, databases migrated before batches existed have none
+    fn has_migration_batches(&self) -> Result<bool, Error>;
+    fn add_migration_batch(
+        &mut self,
+        batch_id: &str,
+        entries: u64,
+        time: &DateTime<Utc>,
+    ) -> Result<(), Error>;
+}
+
+pub trait QuizAttemptRepository {
+    /// Records a counted kotoba quiz attempt, whether it passed or not.
+    fn add_quiz_attempt(&mut self, attempt: &QuizAttempt) -> Result<(), Error>;
+
+    /// Returns the attempts of a user on a quiz made at or after `since`, sorted by time ascendingly.
+    fn get_quiz_attempts_since(
+        &self,
+        user_id: u64,
+        quiz_role: QuizRoles,
+        since: &DateTime<Utc>,
+    ) -> Result<Vec<QuizAttempt>, Error>;
+
+    fn get_quiz_statistics(&self, quiz_role: QuizRoles) -> Result<QuizStatistics, Error>;
+
+    /// Returns a list of members who passed a quiz according to the (QUIZ_PASS_PAGE_SIZE constant), sorted by the time they first passed.
+    /// Members who hid themselves from the leaderboard aren't listed.
+    fn get_paginated_quiz_passes(
+        &self,
+        quiz_role: QuizRoles,
+        page_number: u64,
+    ) -> Result<Vec<QuizPass>, Error>;
+
+    /// Returns how many members are listed by get_paginated_quiz_passes
+    fn get_total_quiz_passes(&self, quiz_role: QuizRoles) -> Result<u64, Error>;
+}
+
+pub trait ImportRepository {
+    /// Returns the characters already imported for a record of an external source, None if it was never imported
+    fn get_imported_characters(
+        &self,
+        user_id: u64,
+        source: &str,
+        key: &str,
+    ) -> Result<Option<i64>, Error>;
+
+    fn set_imported_characters(
+        &mut self,
+        user_id: u64,
+        source: &str,
+        key: &str,
+        characters: i64,
+        time: &DateTime<Utc>,
+    ) -> Result<(), Error>;
+
+    /// Moves the import records of a user to another user, records the other user already has are kept
+    fn move_imported_entries(&mut self, from_user_id: u64, to_user_id: u64) -> Result<(), Error>;
+
+    fn delete_imported_entries(&mut self, user_id: u64) -> Result<(), Error>;
+}
+
+pub trait ModerationRepository {
+    /// Stores a log that waits for approval and returns it with its id
+    fn add_pending_log_entry(
+        &mut self,
+        user_id: u64,
+        name: &str,
+        characters: i64,
+        time: &DateTime<Utc>,
+        notes: Option<String>,
+        media_type: Option<MediaType>,
+    ) -> Result<PendingLogEntry, Error>;
+
+    /// Removes a pending log and returns it, None if it was already approved or rejected
+    fn take_pending_log_entry(&mut self, id: u64) -> Result<Option<PendingLogEntry>, Error>;
+
+    /// Moves the pending logs of a user to another user under their name, returns how many were moved
+    fn move_pending_log_entries(
+        &mut self,
+        from_user_id: u64,
+        to_user_id: u64,
+        to_name: &str,
+    ) -> Result<u64, Error>;
+
+    /// Deletes the pending logs of a user, returns how many were deleted
+    fn delete_pending_log_entries(&mut self, user_id: u64) -> Result<u64, Error>;
+}
+
+pub trait AuditRepository {
+    fn add_audit_entry(&mut self, entry: &AuditEntry) -> Result<(), Error>;
+
+    /// Returns the audit log according to the (AUDIT_PAGE_SIZE constant), newest first. With a target, only the changes to that user.
+    fn get_paginated_audit_entries(
+        &self,
+        target_id: Option<u64>,
+        page_number: u64,
+    ) -> Result<Vec<AuditEntry>, Error>;
+
+    fn get_total_audit_entries(&self, target_id: Option<u64>) -> Result<u64, Error>;
+}
+
+pub trait NameHistoryRepository {
+    fn add_name_change(&mut self, change: &NameChange) -> Result<(), Error>;
+
+    /// Returns the names a user had most recently, None if none were recorded
+    fn get_latest_names(&self, user_id: u64) -> Result<Option<MemberNames>, Error>;
+
+    /// Returns the name history according to the (NAME_HISTORY_PAGE_SIZE constant), newest first.
+    /// With a user, only their changes. With a name, only the changes where either name contains it.
+    fn get_paginated_name_changes(
+        &self,
+        user_id: Option<u64>,
+        name: Option<&str>,
+        page_number: u64,
+    ) -> Result<Vec<NameChange>, Error>;
+
+    fn get_total_name_changes(
+        &self,
+        user_id: Option<u64>,
+        name: Option<&str>,
+    ) -> Result<u64, Error>;
+}
+
+pub trait ReminderRepository {
+    fn get_reminder(&self, user_id: u64) -> Result<Option<Reminder>, Error>;
+    fn get_reminders(&self) -> Result<Vec<Reminder>, Error>;
+    /// Replaces the reminder of the user
+    fn set_reminder(&mut self, reminder: &Reminder) -> Result<(), Error>;
+    /// Returns false if the user had no reminder
+    fn delete_reminder(&mut self, user_id: u64) -> Result<bool, Error>;
+    fn set_reminder_sent(&mut self, user_id: u64, time: &DateTime<Utc>) -> Result<(), Error>;
+}
+
+pub trait UserSettingsRepository {
+    /// Returns the settings of a user, the default ones if they never changed them
+    fn get_settings(&self, user_id: u64) -> Result<UserSettings, Error>;
+    /// Replaces the settings of a user
+    fn set_settings(&mut self, settings: &UserSettings) -> Result<(), Error>;
 }
 
 pub struct SQLiteMetadataRepository<'conn> {
@@ -82,40 +294,86 @@ impl<'conn> SQLiteMetadataRepository<'conn> {
 }
 
 impl MetadataRepository for SQLiteMetadataRepository<'_> {
-    fn get_last_active_status_refresh(&self) -> Result<Option<DateTime<Utc>>, Error> {
-        let mut stmt = self.transaction.prepare(
+    fn get_job_run(&self, job: &str) -> Result<Option<JobRun>, Error> {
+        let run = self
+            .transaction
+            .query_row(
+                "
+            SELECT job, time, error
+            FROM JobRun
+            WHERE job = ?1
+            ",
+                params![job],
+                |row| {
+                    let time: i64 = row.get(1)?;
+                    Ok(JobRun {
+                        job: row.get(0)?,
+                        time: Utc.timestamp_opt(time, 0).unwrap(),
+                        error: row.get(2)?,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(run)
+    }
+
+    fn set_job_run(&mut self, run: &JobRun) -> Result<(), Error> {
+        self.transaction.execute(
             "
-            SELECT last_active_status_refresh
-            FROM Metadata
+            INSERT INTO JobRun (job, time, error)
+            VALUES (?1, ?2, ?3)
+            ON CONFLICT (job) DO UPDATE SET time = excluded.time, error = excluded.error
             ",
+            params![run.job, run.time.timestamp(), run.error],
         )?;
+        Ok(())
+    }
 
-        let rows = stmt.query_map([], |row| {
-            // Since we're selecting one column, use index 0
-            let time: i64 = row.get(0)?;
-            Ok(Utc.timestamp_opt(time, 0).unwrap())
-        })?;
-
-        for row in rows {
-            return Ok(Some(row?));
-        }
-
-        Ok(None)
+    fn clear_job_run(&mut self, job: &str) -> Result<(), Error> {
+        self.transaction
+            .execute("DELETE FROM JobRun WHERE job = ?1", params![job])?;
+        Ok(())
     }
 
-    fn set_last_active_status_refresh(&mut self, time: DateTime<Utc>) -> Result<(), Error> {
-        let sql_update = "UPDATE Metadata SET last_active_status_refresh = ?1";
-        let affected = self
+    fn is_migration_applied(&self, batch_id: &str) -> Result<bool, Error> {
+        let batch = self
             .transaction
-            .execute(sql_update, params![time.timestamp()])?;
+            .query_row(
+                "
+    SELECT id FROM MigrationBatch
+    WHERE id = ?1
+    ",
+                [batch_id],
+                |row| {
+                    let id: String = row.get(0)?;
+                    Ok(id)
+                },
+            )
+            .optional()?;
+
+        Ok(batch.is_some())
+    }
 
-        // If no row was updated, insert a new row
-        if affected == 0 {
-            let sql_insert = "INSERT INTO Metadata (last_active_status_refresh) VALUES (?1)";
+    fn has_migration_batches(&self) -> Result<bool, Error> {
+        let batches: i64 =
             self.transaction
-                .execute(sql_insert, params![time.timestamp()])?;
-        }
+                .query_row("SELECT COUNT(*) FROM MigrationBatch", [], |row| row.get(0))?;
+        Ok(batches > 0)
+    }
 
+    fn add_migration_batch(
+        &mut self,
+        batch_id: &str,
+        entries: u64,
+        time: &DateTime<Utc>,
+    ) -> Result<(), Error> {
+        self.transaction.execute(
+            "
+        INSERT INTO MigrationBatch (id, entries, time)
+        VALUES (?1, ?2, ?3)
+        ",
+            params![batch_id, entries, time.timestamp()],
+        )?;
         Ok(())
     }
 }
@@ -146,14 +404,14 @@ impl<'conn> SQLiteCharacterStatisticsRepository<'conn> {
 }
 
 impl CharacterStatisticsRepository for SQLiteCharacterStatisticsRepository<'_> {
-    fn add_log_entry(
+    fn add_log_entry_with_id(
         &mut self,
         user_id: u64,
         name: &str,
-        characters: i32,
+        characters: i64,
         time: &DateTime<Utc>,
         notes: Option<String>,
-    ) -> Result<CharacterStatistics, Error> {
+    ) -> Result<(CharacterStatistics, Option<u64>), Error> {
         let old_statistics = self.get_or_initialize_statistics(user_id, name)?;
 
         let characters = if characters >= 0 {
@@ -165,6 +423,7 @@ impl CharacterStatisticsRepository for SQLiteCharacterStatisticsRepository<'_> {
         };
 
         // don't insert empty logs (no characters or no notes)
+        let mut entry_id = None;
         if characters != 0 || notes.clone().is_some_and(|n| !n.trim().is_empty()) {
             self.transaction.execute(
                 "
@@ -173,13 +432,14 @@ impl CharacterStatisticsRepository for SQLiteCharacterStatisticsRepository<'_> {
                 ",
                 (user_id, characters, time.timestamp(), notes),
             )?;
+            entry_id = Some(self.transaction.last_insert_rowid() as u64);
         }
 
-        let new_statistics = CharacterStatistics::new(
-            user_id,
-            old_statistics.total_characters + characters,
-            name.to_owned(),
-        );
+        let total_characters = old_statistics
+            .total_characters
+            .checked_add(characters)
+            .ok_or("The total characters would overflow.")?;
+        let new_statistics = CharacterStatistics::new(user_id, total_characters, name.to_owned());
 
         self.transaction.execute(
             "
@@ -190,7 +450,7 @@ WHERE user_id = ?3;
             (new_statistics.total_characters, name, user_id),
         )?;
 
-        Ok(new_statistics)
+        Ok((new_statistics, entry_id))
     }
 
     fn set_active_status(
@@ -212,6 +472,22 @@ WHERE user_id = ?3;
         Ok(())
     }
 
+    fn set_last_seen(&mut self, user_id: u64, time: &DateTime<Utc>) -> Result<(), Error> {
+        self.transaction.execute(
+            "UPDATE CharacterStatistics SET last_seen = ?1 WHERE user_id = ?2",
+            params![time.timestamp(), user_id],
+        )?;
+        Ok(())
+    }
+
+    fn set_names(&mut self, user_id: u64, names: &MemberNames) -> Result<(), Error> {
+        self.transaction.execute(
+            "UPDATE CharacterStatistics SET name = ?1, nickname = ?2 WHERE user_id = ?3",
+            params![names.global_name, names.nickname, user_id],
+        )?;
+        Ok(())
+    }
+
     fn get_paginated_active_users_by_characters(
         &mut self,
         page_number: u64,
@@ -220,9 +496,10 @@ WHERE user_id = ?3;
 
         let mut stmt = self.transaction.prepare(
             "
-                SELECT user_id, total_characters, name
+                SELECT user_id, total_characters, COALESCE(nickname, name)
                 FROM CharacterStatistics
                 WHERE is_active == 1 AND total_characters > 0
+                    AND user_id NOT IN (SELECT user_id FROM UserSettings WHERE show_on_leaderboard = 0)
                 ORDER BY total_characters DESC, user_id ASC
                 LIMIT ?1 OFFSET ?2;
                 ",
@@ -230,7 +507,7 @@ WHERE user_id = ?3;
 
         let rows = stmt.query_map([LEADERBOARD_PAGE_SIZE, offset], |row| {
             let user_id: u64 = row.get(0)?;
-            let total_characters: i32 = row.get(1)?;
+            let total_characters: i64 = row.get(1)?;
             let name: String = row.get(2)?;
             Ok(CharacterStatistics::new(user_id, total_characters, name))
         })?;
@@ -261,7 +538,7 @@ WHERE user_id = ?3;
 
         let rows = stmt.query_map([LEADERBOARD_PAGE_SIZE, offset], |row| {
             let user_id: u64 = row.get(0)?;
-            let total_characters: i32 = row.get(1)?;
+            let total_characters: i64 = row.get(1)?;
             let name: String = row.get(2)?;
             Ok(CharacterStatistics::new(user_id, total_characters, name))
         })?;
@@ -283,25 +560,43 @@ WHERE user_id = ?3;
 
         let mut stmt = self.transaction.prepare(
             "
-                SELECT id, user_id, characters, time, notes
-                FROM CharacterLogEntry
-                WHERE user_id = ?1
-                ORDER BY time DESC
+                SELECT e.id, e.user_id, e.characters, e.time, e.notes, (
+                    -- approved logs are linked to the audit log too, only admin changes count as edits
+                    SELECT a.actor_id FROM AuditLog a
+                    WHERE a.log_entry_id = e.id AND a.action IN (?4, ?5, ?6)
+                    ORDER BY a.time DESC, a.id DESC
+                    LIMIT 1
+                ), e.media_type
+                FROM CharacterLogEntry e
+                WHERE e.user_id = ?1
+                ORDER BY e.time DESC
                 LIMIT ?2 OFFSET ?3;
             ",
         )?;
 
-        let rows = stmt.query_map([user_id, LOG_ENTRY_PAGE_SIZE, offset], |row| {
+        let params = params![
+            user_id,
+            LOG_ENTRY_PAGE_SIZE,
+            offset,
+            AuditAction::EditCharacters.to_string(),
+            AuditAction::SetTotal.to_string(),
+            AuditAction::RepairTotal.to_string()
+        ];
+        let rows = stmt.query_map(params, |row| {
             let user_id: u64 = row.get(1)?;
-            let characters: i32 = row.get(2)?;
+            let characters: i64 = row.get(2)?;
             let time: i64 = row.get(3)?;
             let notes: Option<String> = row.get(4)?;
+            let edited_by: Option<u64> = row.get(5)?;
+            let media_type: Option<String> = row.get(6)?;
 
             Ok(CharacterLogEntry::new(
                 user_id,
                 characters,
                 &Timestamp::from_unix_timestamp(time).expect("Date conversion error!"),
                 notes,
+                edited_by,
+                media_type.as_deref().and_then(MediaType::from_name),
             ))
         })?;
 
@@ -323,17 +618,33 @@ WHERE user_id = ?3;
     ",
                 [user_id],
                 |row| {
-                    let c: i32 = row.get(0)?;
+                    let c: i64 = row.get(0)?;
                     Ok(c)
                 },
             )
             .optional()?;
 
-        let exists = match characters {
-            Some(_) => true,
-            None => false,
-        };
-        Ok(exists)
+        Ok(characters.is_some())
+    }
+
+    fn get_statistics(&self, user_id: u64) -> Result<Option<CharacterStatistics>, Error> {
+        let statistics = self
+            .transaction
+            .query_row(
+                "
+    SELECT total_characters, name FROM CharacterStatistics
+    WHERE user_id = ?1
+    ",
+                [user_id],
+                |row| {
+                    let characters: i64 = row.get(0)?;
+                    let name: String = row.get(1)?;
+                    Ok(CharacterStatistics::new(user_id, characters, name))
+                },
+            )
+            .optional()?;
+
+        Ok(statistics)
     }
 
     fn get_or_initialize_statistics(
@@ -350,7 +661,7 @@ WHERE user_id = ?3;
         ",
                 [user_id],
                 |row| {
-                    let c: i32 = row.get(0)?;
+                    let c: i64 = row.get(0)?;
                     Ok(c)
                 },
             )
@@ -368,7 +679,7 @@ WHERE user_id = ?3;
         ))
     }
 
-    fn get_rank(&mut self, statistics: &CharacterStatistics) -> Result<i32, Error> {
+    fn get_rank(&mut self, statistics: &CharacterStatistics) -> Result<Option<i32>, Error> {
         let mut stmt = self.transaction.prepare(
             "
             WITH RankedUsers AS (
@@ -377,6 +688,7 @@ WHERE user_id = ?3;
                     RANK() OVER (ORDER BY total_characters DESC, user_id ASC) AS rank
                 FROM CharacterStatistics
                 WHERE is_active = 1 AND total_characters > 0
+                    AND user_id NOT IN (SELECT user_id FROM UserSettings WHERE show_on_leaderboard = 0)
             )
             SELECT rank
             FROM RankedUsers
@@ -384,16 +696,19 @@ WHERE user_id = ?3;
             ",
         )?;
 
-        let rank_count: i32 = stmt.query_row([statistics.get_user_id()], |row| row.get(0))?;
+        let rank_count: Option<i32> = stmt
+            .query_row([statistics.get_user_id()], |row| row.get(0))
+            .optional()?;
         Ok(rank_count)
     }
 
     fn get_total_active_users(&mut self) -> Result<u64, Error> {
         let mut stmt = self.transaction.prepare(
             "
-            SELECT COUNT(*) 
-            FROM CharacterStatistics 
+            SELECT COUNT(*)
+            FROM CharacterStatistics
             WHERE is_active == 1 AND total_characters > 0
+                AND user_id NOT IN (SELECT user_id FROM UserSettings WHERE show_on_leaderboard = 0)
             ",
         )?;
 
@@ -404,14 +719,1673 @@ WHERE user_id = ?3;
     fn get_total_log_entries(&mut self, user_id: u64) -> Result<u64, Error> {
         let mut stmt = self.transaction.prepare(
             "
-            SELECT COUNT(*) 
+            SELECT COUNT(*)
             FROM CharacterLogEntry
-            WHERE user_id = ?1
-            GROUP BY user_id;
+            WHERE user_id = ?1;
             ",
         )?;
 
         let count: u64 = stmt.query_row([user_id], |row| row.get(0))?;
         Ok(count)
     }
+
+    fn get_log_average_since(
+        &mut self,
+        user_id: u64,
+        since: &DateTime<Utc>,
+    ) -> Result<(u64, f64), Error> {
+        let mut stmt = self.transaction.prepare(
+            "
+            SELECT COUNT(*), COALESCE(AVG(characters), 0)
+            FROM CharacterLogEntry
+            WHERE user_id = ?1 AND time >= ?2 AND characters > 0
+            ",
+        )?;
+
+        let average = stmt.query_row((user_id, since.timestamp()), |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?;
+        Ok(average)
+    }
+
+    fn get_log_totals_between(
+        &mut self,
+        since: Option<&DateTime<Utc>>,
+        until: &DateTime<Utc>,
+    ) -> Result<Vec<CharacterStatistics>, Error> {
+        let mut stmt = self.transaction.prepare(
+            "
+                SELECT s.user_id, SUM(e.characters) AS characters, COALESCE(s.nickname, s.name)
+                FROM CharacterLogEntry e
+                JOIN CharacterStatistics s ON s.user_id = e.user_id
+                WHERE s.is_active == 1 AND (?1 IS NULL OR e.time >= ?1) AND e.time < ?2
+                    AND s.user_id NOT IN (SELECT user_id FROM UserSettings WHERE show_on_leaderboard = 0)
+                GROUP BY s.user_id
+                ORDER BY characters DESC, s.user_id ASC;
+            ",
+        )?;
+
+        let rows = stmt.query_map(
+            params![since.map(|since| since.timestamp()), until.timestamp()],
+            |row| {
+                let user_id: u64 = row.get(0)?;
+                let characters: i64 = row.get(1)?;
+                let name: String = row.get(2)?;
+                Ok(CharacterStatistics::new(user_id, characters, name))
+            },
+        )?;
+
+        let mut result = Vec::new();
+        for row in rows {
+            result.push(row?);
+        }
+
+        Ok(result)
+    }
+
+    fn get_all_log_entries(&mut self, user_id: u64) -> Result<Vec<CharacterLogEntry>, Error> {
+        let mut stmt = self.transaction.prepare(
+            "
+                SELECT user_id, characters, time, notes, media_type
+                FROM CharacterLogEntry
+                WHERE user_id = ?1
+                ORDER BY time ASC, id ASC;
+            ",
+        )?;
+
+        let rows = stmt.query_map([user_id], |row| {
+            let user_id: u64 = row.get(0)?;
+            let characters: i64 = row.get(1)?;
+            let time: i64 = row.get(2)?;
+            let notes: Option<String> = row.get(3)?;
+            let media_type: Option<String> = row.get(4)?;
+
+            Ok(CharacterLogEntry::new(
+                user_id,
+                characters,
+                &Timestamp::from_unix_timestamp(time).expect("Date conversion error!"),
+                notes,
+                None,
+                media_type.as_deref().and_then(MediaType::from_name),
+            ))
+        })?;
+
+        let mut result = Vec::new();
+        for row in rows {
+            result.push(row?);
+        }
+
+        Ok(result)
+    }
+
+    fn get_total_mismatches(&mut self) -> Result<Vec<TotalMismatch>, Error> {
+        let mut stmt = self.transaction.prepare(
+            "
+                SELECT s.user_id, s.name, s.total_characters, COALESCE(SUM(e.characters), 0) AS computed
+                FROM CharacterStatistics s
+                LEFT JOIN CharacterLogEntry e ON e.user_id = s.user_id
+                GROUP BY s.user_id
+                HAVING s.total_characters != computed
+                ORDER BY s.user_id ASC;
+            ",
+        )?;
+
+        let rows = stmt.query_map([], |row| {
+            Ok(TotalMismatch {
+                user_id: row.get(0)?,
+                name: row.get(1)?,
+                stored_total: row.get(2)?,
+                computed_total: row.get(3)?,
+            })
+        })?;
+
+        let mut result = Vec::new();
+        for row in rows {
+            result.push(row?);
+        }
+
+        Ok(result)
+    }
+
+    fn set_total_characters(&mut self, user_id: u64, total_characters: i64) -> Result<(), Error> {
+        self.transaction.execute(
+            "
+UPDATE CharacterStatistics
+SET total_characters = ?1
+WHERE user_id = ?2;
+        ",
+            (total_characters, user_id),
+        )?;
+        Ok(())
+    }
+
+    fn move_log_entries(&mut self, from_user_id: u64, to_user_id: u64) -> Result<u64, Error> {
+        let moved = self.transaction.execute(
+            "UPDATE CharacterLogEntry SET user_id = ?1 WHERE user_id = ?2",
+            (to_user_id, from_user_id),
+        )?;
+        Ok(moved as u64)
+    }
+
+    fn delete_log_entries(&mut self, user_id: u64) -> Result<u64, Error> {
+        let deleted = self.transaction.execute(
+            "DELETE FROM CharacterLogEntry WHERE user_id = ?1",
+            [user_id],
+        )?;
+        Ok(deleted as u64)
+    }
+
+    fn set_media_type(&mut self, log_entry_id: u64, media_type: MediaType) -> Result<(), Error> {
+        self.transaction.execute(
+            "UPDATE CharacterLogEntry SET media_type = ?1 WHERE id = ?2",
+            params![media_type.name(), log_entry_id],
+        )?;
+        Ok(())
+    }
+}
+
+pub struct SQLiteQuizAttemptRepository<'conn> {
+    transaction: &'conn Transaction<'conn>,
+}
+
+impl<'conn> SQLiteQuizAttemptRepository<'conn> {
+    pub fn new(transaction: &'conn Transaction<'conn>) -> Self {
+        SQLiteQuizAttemptRepository { transaction }
+    }
+}
+
+impl QuizAttemptRepository for SQLiteQuizAttemptRepository<'_> {
+    fn add_quiz_attempt(&mut self, attempt: &QuizAttempt) -> Result<(), Error> {
+        self.transaction.execute(
+            "
+            INSERT INTO QuizAttempt (user_id, quiz, score, passed, time)
+            VALUES (?1, ?2, ?3, ?4, ?5);
+            ",
+            params![
+                attempt.user_id(),
+                attempt.quiz_role().to_string(),
+                attempt.score(),
+                attempt.passed(),
+                attempt.time().unix_timestamp()
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn get_quiz_attempts_since(
+        &self,
+        user_id: u64,
+        quiz_role: QuizRoles,
+        since: &DateTime<Utc>,
+    ) -> Result<Vec<QuizAttempt>, Error> {
+        let mut stmt = self.transaction.prepare(
+            "
+                SELECT user_id, score, passed, time
+                FROM QuizAttempt
+                WHERE user_id = ?1 AND quiz = ?2 AND time >= ?3
+                ORDER BY time ASC;
+            ",
+        )?;
+
+        let rows = stmt.query_map(
+            params![user_id, quiz_role.to_string(), since.timestamp()],
+            |row| {
+                let user_id: u64 = row.get(0)?;
+                let score: i32 = row.get(1)?;
+                let passed: bool = row.get(2)?;
+                let time: i64 = row.get(3)?;
+
+                Ok(QuizAttempt::new(
+                    user_id,
+                    quiz_role,
+                    score,
+                    passed,
+                    &Timestamp::from_unix_timestamp(time).expect("Date conversion error!"),
+                ))
+            },
+        )?;
+
+        let mut result = Vec::new();
+        for row in rows {
+            result.push(row?);
+        }
+
+        Ok(result)
+    }
+
+    fn get_quiz_statistics(&self, quiz_role: QuizRoles) -> Result<QuizStatistics, Error> {
+        let mut stmt = self.transaction.prepare(
+            "
+            SELECT COUNT(*), COALESCE(SUM(passed), 0), COALESCE(AVG(score), 0), COALESCE(MAX(score), 0)
+            FROM QuizAttempt
+            WHERE quiz = ?1
+            ",
+        )?;
+
+        let statistics = stmt.query_row([quiz_role.to_string()], |row| {
+            Ok(QuizStatistics {
+                attempts: row.get(0)?,
+                passes: row.get(1)?,
+                average_score: row.get(2)?,
+                best_score: row.get(3)?,
+            })
+        })?;
+        Ok(statistics)
+    }
+
+    fn get_paginated_quiz_passes(
+        &self,
+        quiz_role: QuizRoles,
+        page_number: u64,
+    ) -> Result<Vec<QuizPass>, Error> {
+        let offset = page_number * QUIZ_PASS_PAGE_SIZE;
+
+        let mut stmt = self.transaction.prepare(
+            "
+                SELECT q.user_id, c.name, MIN(q.time) AS first_pass
+                FROM QuizAttempt q
+                LEFT JOIN CharacterStatistics c ON c.user_id = q.user_id
+                WHERE q.quiz = ?1 AND q.passed = 1
+                    AND q.user_id NOT IN (SELECT user_id FROM UserSettings WHERE show_on_leaderboard = 0)
+                GROUP BY q.user_id
+                ORDER BY first_pass ASC, q.user_id ASC
+                LIMIT ?2 OFFSET ?3;
+            ",
+        )?;
+
+        let rows = stmt.query_map(
+            params![quiz_role.to_string(), QUIZ_PASS_PAGE_SIZE, offset],
+            |row| {
+                let user_id: u64 = row.get(0)?;
+                let name: Option<String> = row.get(1)?;
+                let time: i64 = row.get(2)?;
+
+                Ok(QuizPass {
+                    user_id,
+                    name,
+                    time: Timestamp::from_unix_timestamp(time).expect("Date conversion error!"),
+                })
+            },
+        )?;
+
+        let mut result = Vec::new();
+        for row in rows {
+            result.push(row?);
+        }
+
+        Ok(result)
+    }
+
+    fn get_total_quiz_passes(&self, quiz_role: QuizRoles) -> Result<u64, Error> {
+        let mut stmt = self.transaction.prepare(
+            "
+            SELECT COUNT(DISTINCT user_id)
+            FROM QuizAttempt
+            WHERE quiz = ?1 AND passed = 1
+                AND user_id NOT IN (SELECT user_id FROM UserSettings WHERE show_on_leaderboard = 0)
+            ",
+        )?;
+
+        let count: u64 = stmt.query_row([quiz_role.to_string()], |row| row.get(0))?;
+        Ok(count)
+    }
+}
+
+pub struct SQLiteImportRepository<'conn> {
+    transaction: &'conn Transaction<'conn>,
+}
+
+impl<'conn> SQLiteImportRepository<'conn> {
+    pub fn new(transaction: &'conn Transaction<'conn>) -> Self {
+        SQLiteImportRepository { transaction }
+    }
+}
+
+impl ImportRepository for SQLiteImportRepository<'_> {
+    fn get_imported_characters(
+        &self,
+        user_id: u64,
+        source: &str,
+        key: &str,
+    ) -> Result<Option<i64>, Error> {
+        let characters = self
+            .transaction
+            .query_row(
+                "
+    SELECT characters FROM ImportedEntry
+    WHERE user_id = ?1 AND source = ?2 AND key = ?3
+    ",
+                params![user_id, source, key],
+                |row| {
+                    let c: i64 = row.get(0)?;
+                    Ok(c)
+                },
+            )
+            .optional()?;
+        Ok(characters)
+    }
+
+    fn set_imported_characters(
+        &mut self,
+        user_id: u64,
+        source: &str,
+        key: &str,
+        characters: i64,
+        time: &DateTime<Utc>,
+    ) -> Result<(), Error> {
+        self.transaction.execute(
+            "
+            INSERT INTO ImportedEntry (user_id, source, key, characters, time)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+            ON CONFLICT (user_id, source, key)
+            DO UPDATE SET characters = excluded.characters, time = excluded.time;
+            ",
+            params![user_id, source, key, characters, time.timestamp()],
+        )?;
+        Ok(())
+    }
+
+    fn move_imported_entries(&mut self, from_user_id: u64, to_user_id: u64) -> Result<(), Error> {
+        self.transaction.execute(
+            "UPDATE OR IGNORE ImportedEntry SET user_id = ?1 WHERE user_id = ?2",
+            (to_user_id, from_user_id),
+        )?;
+        self.delete_imported_entries(from_user_id)
+    }
+
+    fn delete_imported_entries(&mut self, user_id: u64) -> Result<(), Error> {
+        self.transaction
+            .execute("DELETE FROM ImportedEntry WHERE user_id = ?1", [user_id])?;
+        Ok(())
+    }
+}
+
+pub struct SQLiteModerationRepository<'conn> {
+    transaction: &'conn Transaction<'conn>,
+}
+
+impl<'conn> SQLiteModerationRepository<'conn> {
+    pub fn new(transaction: &'conn Transaction<'conn>) -> Self {
+        SQLiteModerationRepository { transaction }
+    }
+}
+
+impl ModerationRepository for SQLiteModerationRepository<'_> {
+    fn add_pending_log_entry(
+        &mut self,
+        user_id: u64,
+        name: &str,
+        characters: i64,
+        time: &DateTime<Utc>,
+        notes: Option<String>,
+        media_type: Option<MediaType>,
+    ) -> Result<PendingLogEntry, Error> {
+        self.transaction.execute(
+            "
+            INSERT INTO PendingLogEntry (user_id, name, characters, time, notes, media_type)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6);
+            ",
+            params![
+                user_id,
+                name,
+                characters,
+                time.timestamp(),
+                notes,
+                media_type.map(|media_type| media_type.name())
+            ],
+        )?;
+
+        Ok(PendingLogEntry {
+            id: self.transaction.last_insert_rowid() as u64,
+            user_id,
+            name: name.to_owned(),
+            characters,
+            time: Timestamp::from_unix_timestamp(time.timestamp()).expect("Date conversion error!"),
+            notes,
+            media_type,
+        })
+    }
+
+    fn take_pending_log_entry(&mut self, id: u64) -> Result<Option<PendingLogEntry>, Error> {
+        let entry = self
+            .transaction
+            .query_row(
+                "
+            SELECT user_id, name, characters, time, notes, media_type
+            FROM PendingLogEntry
+            WHERE id = ?1
+            ",
+                [id],
+                |row| {
+                    let time: i64 = row.get(3)?;
+                    let media_type: Option<String> = row.get(5)?;
+                    Ok(PendingLogEntry {
+                        id,
+                        user_id: row.get(0)?,
+                        name: row.get(1)?,
+                        characters: row.get(2)?,
+                        time: Timestamp::from_unix_timestamp(time).expect("Date conversion error!"),
+                        notes: row.get(4)?,
+                        media_type: media_type.as_deref().and_then(MediaType::from_name),
+                    })
+                },
+            )
+            .optional()?;
+
+        if entry.is_some() {
+            self.transaction
+                .execute("DELETE FROM PendingLogEntry WHERE id = ?1", [id])?;
+        }
+        Ok(entry)
+    }
+
+    fn move_pending_log_entries(
+        &mut self,
+        from_user_id: u64,
+        to_user_id: u64,
+        to_name: &str,
+    ) -> Result<u64, Error> {
+        let moved = self.transaction.execute(
+            "UPDATE PendingLogEntry SET user_id = ?1, name = ?2 WHERE user_id = ?3",
+            params![to_user_id, to_name, from_user_id],
+        )?;
+        Ok(moved as u64)
+    }
+
+    fn delete_pending_log_entries(&mut self, user_id: u64) -> Result<u64, Error> {
+        let deleted = self
+            .transaction
+            .execute("DELETE FROM PendingLogEntry WHERE user_id = ?1", [user_id])?;
+        Ok(deleted as u64)
+    }
+}
+
+pub struct SQLiteAuditRepository<'conn> {
+    transaction: &'conn Transaction<'conn>,
+}
+
+impl<'conn> SQLiteAuditRepository<'conn> {
+    pub fn new(transaction: &'conn Transaction<'conn>) -> Self {
+        SQLiteAuditRepository { transaction }
+    }
+}
+
+impl AuditRepository for SQLiteAuditRepository<'_> {
+    fn add_audit_entry(&mut self, entry: &AuditEntry) -> Result<(), Error> {
+        self.transaction.execute(
+            "
+            INSERT INTO AuditLog (actor_id, target_id, action, delta, previous_total, reason, log_entry_id, time)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8);
+            ",
+            params![
+                entry.actor_id,
+                entry.target_id,
+                entry.action.to_string(),
+                entry.delta,
+                entry.previous_total,
+                entry.reason,
+                entry.log_entry_id,
+                entry.time.unix_timestamp()
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn get_paginated_audit_entries(
+        &self,
+        target_id: Option<u64>,
+        page_number: u64,
+    ) -> Result<Vec<AuditEntry>, Error> {
+        let offset = page_number * AUDIT_PAGE_SIZE;
+
+        let mut stmt = self.transaction.prepare(
+            "
+                SELECT actor_id, target_id, action, delta, previous_total, reason, log_entry_id, time
+                FROM AuditLog
+                WHERE ?1 IS NULL OR target_id = ?1
+                ORDER BY time DESC, id DESC
+                LIMIT ?2 OFFSET ?3;
+            ",
+        )?;
+
+        let rows = stmt.query_map(params![target_id, AUDIT_PAGE_SIZE, offset], |row| {
+            let action: String = row.get(2)?;
+            let time: i64 = row.get(7)?;
+            Ok(AuditEntry {
+                actor_id: row.get(0)?,
+                target_id: row.get(1)?,
+                action: AuditAction::from_string(&action).expect("Unknown audit action!"),
+                delta: row.get(3)?,
+                previous_total: row.get(4)?,
+                reason: row.get(5)?,
+                log_entry_id: row.get(6)?,
+                time: Timestamp::from_unix_timestamp(time).expect("Date conversion error!"),
+            })
+        })?;
+
+        let mut result = Vec::new();
+        for row in rows {
+            result.push(row?);
+        }
+
+        Ok(result)
+    }
+
+    fn get_total_audit_entries(&self, target_id: Option<u64>) -> Result<u64, Error> {
+        let count: u64 = self.transaction.query_row(
+            "
+            SELECT COUNT(*)
+            FROM AuditLog
+            WHERE ?1 IS NULL OR target_id = ?1
+            ",
+            params![target_id],
+            |row| row.get(0),
+        )?;
+        Ok(count)
+    }
+}
+
+pub struct SQLiteNameHistoryRepository<'conn> {
+    transaction: &'conn Transaction<'conn>,
+}
+
+impl<'conn> SQLiteNameHistoryRepository<'conn> {
+    pub fn new(transaction: &'conn Transaction<'conn>) -> Self {
+        SQLiteNameHistoryRepository { transaction }
+    }
+}
+
+impl NameHistoryRepository for SQLiteNameHistoryRepository<'_> {
+    fn add_name_change(&mut self, change: &NameChange) -> Result<(), Error> {
+        self.transaction.execute(
+            "
+            INSERT INTO NameHistory (user_id, global_name, nickname, time)
+            VALUES (?1, ?2, ?3, ?4);
+            ",
+            params![
+                change.user_id,
+                change.names.global_name,
+                change.names.nickname,
+                change.time.unix_timestamp()
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn get_latest_names(&self, user_id: u64) -> Result<Option<MemberNames>, Error> {
+        let names = self
+            .transaction
+            .query_row(
+                "
+                SELECT global_name, nickname
+                FROM NameHistory
+                WHERE user_id = ?1
+                ORDER BY time DESC, id DESC
+                LIMIT 1
+                ",
+                params![user_id],
+                |row| {
+                    Ok(MemberNames {
+                        global_name: row.get(0)?,
+                        nickname: row.get(1)?,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(names)
+    }
+
+    fn get_paginated_name_changes(
+        &self,
+        user_id: Option<u64>,
+        name: Option<&str>,
+        page_number: u64,
+    ) -> Result<Vec<NameChange>, Error> {
+        let offset = page_number * NAME_HISTORY_PAGE_SIZE;
+
+        let mut stmt = self.transaction.prepare(
+            "
+                SELECT user_id, global_name, nickname, time
+                FROM NameHistory
+                WHERE (?1 IS NULL OR user_id = ?1)
+                    AND (?2 IS NULL OR instr(lower(global_name), lower(?2)) > 0 OR instr(lower(nickname), lower(?2)) > 0)
+                ORDER BY time DESC, id DESC
+                LIMIT ?3 OFFSET ?4;
+            ",
+        )?;
+
+        let rows = stmt.query_map(
+            params![user_id, name, NAME_HISTORY_PAGE_SIZE, offset],
+            |row| {
+                let time: i64 = row.get(3)?;
+                Ok(NameChange {
+                    user_id: row.get(0)?,
+                    names: MemberNames {
+                        global_name: row.get(1)?,
+                        nickname: row.get(2)?,
+                    },
+                    time: Timestamp::from_unix_timestamp(time).expect("Date conversion error!"),
+                })
+            },
+        )?;
+
+        let mut result = Vec::new();
+        for row in rows {
+            result.push(row?);
+        }
+
+        Ok(result)
+    }
+
+    fn get_total_name_changes(
+        &self,
+        user_id: Option<u64>,
+        name: Option<&str>,
+    ) -> Result<u64, Error> {
+        let count: u64 = self.transaction.query_row(
+            "
+            SELECT COUNT(*)
+            FROM NameHistory
+            WHERE (?1 IS NULL OR user_id = ?1)
+                AND (?2 IS NULL OR instr(lower(global_name), lower(?2)) > 0 OR instr(lower(nickname), lower(?2)) > 0)
+            ",
+            params![user_id, name],
+            |row| row.get(0),
+        )?;
+        Ok(count)
+    }
+}
+
+pub struct SQLiteReminderRepository<'conn> {
+    transaction: &'conn Transaction<'conn>,
+}
+
+impl<'conn> SQLiteReminderRepository<'conn> {
+    pub fn new(transaction: &'conn Transaction<'conn>) -> Self {
+        SQLiteReminderRepository { transaction }
+    }
+
+    fn to_reminder(row: &rusqlite::Row) -> rusqlite::Result<Reminder> {
+        let time: String = row.get(1)?;
+        let frequency: String = row.get(2)?;
+        let last_sent: Option<i64> = row.get(4)?;
+        Ok(Reminder {
+            user_id: row.get(0)?,
+            time: NaiveTime::parse_from_str(&time, "%H:%M").expect("Time conversion error!"),
+            frequency: ReminderFrequency::from_name(&frequency)
+                .expect("Unknown reminder frequency!"),
+            only_if_not_logged: row.get(3)?,
+            last_sent: last_sent.map(|time| Utc.timestamp_opt(time, 0).unwrap()),
+        })
+    }
+}
+
+impl ReminderRepository for SQLiteReminderRepository<'_> {
+    fn get_reminder(&self, user_id: u64) -> Result<Option<Reminder>, Error> {
+        let reminder = self
+            .transaction
+            .query_row(
+                "
+                SELECT user_id, time, frequency, only_if_not_logged, last_sent
+                FROM Reminder
+                WHERE user_id = ?1
+                ",
+                params![user_id],
+                Self::to_reminder,
+            )
+            .optional()?;
+        Ok(reminder)
+    }
+
+    fn get_reminders(&self) -> Result<Vec<Reminder>, Error> {
+        let mut stmt = self.transaction.prepare(
+            "
+            SELECT user_id, time, frequency, only_if_not_logged, last_sent
+            FROM Reminder
+            ORDER BY user_id
+            ",
+        )?;
+
+        let rows = stmt.query_map([], Self::to_reminder)?;
+
+        let mut result = Vec::new();
+        for row in rows {
+            result.push(row?);
+        }
+
+        Ok(result)
+    }
+
+    fn set_reminder(&mut self, reminder: &Reminder) -> Result<(), Error> {
+        self.transaction.execute(
+            "
+            INSERT OR REPLACE INTO Reminder (user_id, time, frequency, only_if_not_logged, last_sent)
+            VALUES (?1, ?2, ?3, ?4, ?5);
+            ",
+            params![
+                reminder.user_id,
+                reminder.time.format("%H:%M").to_string(),
+                reminder.frequency.name(),
+                reminder.only_if_not_logged,
+                reminder.last_sent.map(|time| time.timestamp())
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn delete_reminder(&mut self, user_id: u64) -> Result<bool, Error> {
+        let deleted = self
+            .transaction
+            .execute("DELETE FROM Reminder WHERE user_id = ?1", [user_id])?;
+        Ok(deleted > 0)
+    }
+
+    fn set_reminder_sent(&mut self, user_id: u64, time: &DateTime<Utc>) -> Result<(), Error> {
+        self.transaction.execute(
+            "UPDATE Reminder SET last_sent = ?2 WHERE user_id = ?1",
+            params![user_id, time.timestamp()],
+        )?;
+        Ok(())
+    }
+}
+
+pub struct SQLiteUserSettingsRepository<'conn> {
+    transaction: &'conn Transaction<'conn>,
+}
+
+impl<'conn> SQLiteUserSettingsRepository<'conn> {
+    pub fn new(transaction: &'conn Transaction<'conn>) -> Self {
+        SQLiteUserSettingsRepository { transaction }
+    }
+}
+
+impl UserSettingsRepository for SQLiteUserSettingsRepository<'_> {
+    fn get_settings(&self, user_id: u64) -> Result<UserSettings, Error> {
+        let settings = self
+            .transaction
+            .query_row(
+                "
+                SELECT timezone, default_media_type, history_visible, show_on_leaderboard, reminder_dms, review_dms
+                FROM UserSettings
+                WHERE user_id = ?1
+                ",
+                params![user_id],
+                |row| {
+                    let default_media_type: Option<String> = row.get(1)?;
+                    Ok(UserSettings {
+                        user_id,
+                        timezone: row.get(0)?,
+                        default_media_type: default_media_type
+                            .as_deref()
+                            .and_then(MediaType::from_name),
+                        history_visible: row.get(2)?,
+                        show_on_leaderboard: row.get(3)?,
+                        reminder_dms: row.get(4)?,
+                        review_dms: row.get(5)?,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(settings.unwrap_or_else(|| UserSettings::new(user_id)))
+    }
+
+    fn set_settings(&mut self, settings: &UserSettings) -> Result<(), Error> {
+        self.transaction.execute(
+            "
+            INSERT OR REPLACE INTO UserSettings (user_id, timezone, default_media_type, history_visible, show_on_leaderboard, reminder_dms, review_dms)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7);
+            ",
+            params![
+                settings.user_id,
+                settings.timezone,
+                settings.default_media_type.map(|media_type| media_type.name()),
+                settings.history_visible,
+                settings.show_on_leaderboard,
+                settings.reminder_dms,
+                settings.review_dms
+            ],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use rusqlite::Connection;
+
+    use super::*;
+    use crate::database::create_schema;
+
+    fn connection() -> Connection {
+        let connection = Connection::open_in_memory().unwrap();
+        create_schema(&connection).unwrap();
+        connection
+    }
+
+    fn time(seconds: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(1_700_000_000 + seconds, 0).unwrap()
+    }
+
+    fn log(repo: &mut SQLiteCharacterStatisticsRepository, user_id: u64, characters: i64) {
+        repo.add_log_entry(user_id, "user", characters, &time(0), None)
+            .unwrap();
+    }
+
+    fn entry_characters(entries: &[CharacterLogEntry]) -> Vec<i64> {
+        entries.iter().map(|entry| entry.characters()).collect()
+    }
+
+    #[test]
+    fn add_log_entry_creates_the_user_and_updates_the_name() {
+        let mut connection = connection();
+        let tx = connection.transaction().unwrap();
+        let mut repo = SQLiteCharacterStatisticsRepository::new(&tx);
+
+        let statistics = repo
+            .add_log_entry(1, "old name", 1_000, &time(0), None)
+            .unwrap();
+        assert_eq!(statistics.total_characters, 1_000);
+
+        let statistics = repo
+            .add_log_entry(1, "new name", 500, &time(1), Some("book".to_owned()))
+            .unwrap();
+        assert_eq!(statistics.total_characters, 1_500);
+
+        let stored = repo.get_statistics(1).unwrap().unwrap();
+        assert_eq!(stored.total_characters, 1_500);
+        assert_eq!(stored.name, "new name");
+    }
+
+    #[test]
+    fn add_log_entry_clamps_negative_logs() {
+        let mut connection = connection();
+        let tx = connection.transaction().unwrap();
+        let mut repo = SQLiteCharacterStatisticsRepository::new(&tx);
+
+        log(&mut repo, 1, 1_000);
+        let statistics = repo
+            .add_log_entry(1, "user", -5_000, &time(1), None)
+            .unwrap();
+        assert_eq!(statistics.total_characters, 0);
+        // the entry stores the clamped amount, so the entries still add up to the total
+        assert_eq!(
+            entry_characters(&repo.get_all_log_entries(1).unwrap()),
+            vec![1_000, -1_000]
+        );
+
+        // nothing left to remove, so the log is empty
+        let (statistics, entry_id) = repo
+            .add_log_entry_with_id(1, "user", -10, &time(2), None)
+            .unwrap();
+        assert_eq!(statistics.total_characters, 0);
+        assert_eq!(entry_id, None);
+    }
+
+    #[test]
+    fn add_log_entry_skips_empty_logs() {
+        let mut connection = connection();
+        let tx = connection.transaction().unwrap();
+        let mut repo = SQLiteCharacterStatisticsRepository::new(&tx);
+
+        let (_, entry_id) = repo
+            .add_log_entry_with_id(1, "user", 0, &time(0), None)
+            .unwrap();
+        assert_eq!(entry_id, None);
+        let (_, entry_id) = repo
+            .add_log_entry_with_id(1, "user", 0, &time(0), Some("  ".to_owned()))
+            .unwrap();
+        assert_eq!(entry_id, None);
+        assert_eq!(repo.get_total_log_entries(1).unwrap(), 0);
+
+        // a note is worth keeping even without characters
+        let (_, entry_id) = repo
+            .add_log_entry_with_id(1, "user", 0, &time(0), Some("note".to_owned()))
+            .unwrap();
+        assert!(entry_id.is_some());
+        assert_eq!(repo.get_total_log_entries(1).unwrap(), 1);
+    }
+
+    #[test]
+    fn add_log_entry_rejects_overflowing_totals() {
+        let mut connection = connection();
+        let tx = connection.transaction().unwrap();
+        let mut repo = SQLiteCharacterStatisticsRepository::new(&tx);
+
+        log(&mut repo, 1, i64::MAX);
+        assert!(repo.add_log_entry(1, "user", 1, &time(0), None).is_err());
+        assert_eq!(
+            repo.get_statistics(1).unwrap().unwrap().total_characters,
+            i64::MAX
+        );
+    }
+
+    #[test]
+    fn exists_and_get_statistics_dont_insert() {
+        let mut connection = connection();
+        let tx = connection.transaction().unwrap();
+        let mut repo = SQLiteCharacterStatisticsRepository::new(&tx);
+
+        assert!(!repo.exists(1).unwrap());
+        assert!(repo.get_statistics(1).unwrap().is_none());
+        assert!(!repo.exists(1).unwrap());
+
+        let statistics = repo.get_or_initialize_statistics(1, "user").unwrap();
+        assert_eq!(statistics.total_characters, 0);
+        assert!(repo.exists(1).unwrap());
+        assert_eq!(repo.get_statistics(1).unwrap().unwrap().name, "user");
+
+        // initializing again keeps the total
+        log(&mut repo, 1, 100);
+        let statistics = repo.get_or_initialize_statistics(1, "user").unwrap();
+        assert_eq!(statistics.total_characters, 100);
+    }
+
+    #[test]
+    fn get_rank_orders_by_total_then_user_id() {
+        let mut connection = connection();
+        let tx = connection.transaction().unwrap();
+        let mut repo = SQLiteCharacterStatisticsRepository::new(&tx);
+
+        log(&mut repo, 3, 100);
+        log(&mut repo, 2, 500);
+        log(&mut repo, 1, 100);
+
+        let rank = |repo: &mut SQLiteCharacterStatisticsRepository, user_id| {
+            let statistics = repo.get_statistics(user_id).unwrap().unwrap();
+            repo.get_rank(&statistics).unwrap()
+        };
+        // equal totals don't share a rank, the lower user id goes first
+        assert_eq!(rank(&mut repo, 2), Some(1));
+        assert_eq!(rank(&mut repo, 1), Some(2));
+        assert_eq!(rank(&mut repo, 3), Some(3));
+    }
+
+    #[test]
+    fn get_rank_skips_inactive_and_empty_users() {
+        let mut connection = connection();
+        let tx = connection.transaction().unwrap();
+        let mut repo = SQLiteCharacterStatisticsRepository::new(&tx);
+
+        log(&mut repo, 1, 500);
+        log(&mut repo, 2, 100);
+        let empty = repo.get_or_initialize_statistics(3, "user").unwrap();
+        repo.set_active_status(1, false, None).unwrap();
+
+        let inactive = repo.get_statistics(1).unwrap().unwrap();
+        let active = repo.get_statistics(2).unwrap().unwrap();
+        assert_eq!(repo.get_rank(&inactive).unwrap(), None);
+        assert_eq!(repo.get_rank(&active).unwrap(), Some(1));
+        assert_eq!(repo.get_rank(&empty).unwrap(), None);
+    }
+
+    #[test]
+    fn set_active_status_keeps_the_name_without_a_latest_name() {
+        let mut connection = connection();
+        let tx = connection.transaction().unwrap();
+        let mut repo = SQLiteCharacterStatisticsRepository::new(&tx);
+
+        log(&mut repo, 1, 100);
+        repo.set_active_status(1, false, None).unwrap();
+        assert_eq!(repo.get_statistics(1).unwrap().unwrap().name, "user");
+        assert_eq!(repo.get_total_active_users().unwrap(), 0);
+
+        repo.set_active_status(1, true, Some("renamed")).unwrap();
+        assert_eq!(repo.get_statistics(1).unwrap().unwrap().name, "renamed");
+        assert_eq!(repo.get_total_active_users().unwrap(), 1);
+
+        // unknown users are ignored
+        repo.set_active_status(2, true, Some("nobody")).unwrap();
+        assert!(!repo.exists(2).unwrap());
+    }
+
+    #[test]
+    fn leaderboard_shows_the_nickname() {
+        let mut connection = connection();
+        let tx = connection.transaction().unwrap();
+        let mut repo = SQLiteCharacterStatisticsRepository::new(&tx);
+
+        log(&mut repo, 1, 100);
+        repo.set_names(1, &MemberNames::new("global", Some("nickname")))
+            .unwrap();
+        assert_eq!(repo.get_statistics(1).unwrap().unwrap().name, "global");
+        let leaderboard = repo.get_paginated_active_users_by_characters(0).unwrap();
+        assert_eq!(leaderboard[0].name, "nickname");
+
+        repo.set_names(1, &MemberNames::new("global", None))
+            .unwrap();
+        let leaderboard = repo.get_paginated_active_users_by_characters(0).unwrap();
+        assert_eq!(leaderboard[0].name, "global");
+    }
+
+    #[test]
+    fn name_history_filters_by_user_and_name() {
+        let mut connection = connection();
+        let tx = connection.transaction().unwrap();
+        let mut repo = SQLiteNameHistoryRepository::new(&tx);
+
+        let changes = [
+            (1, MemberNames::new("Alice", None), 0),
+            (1, MemberNames::new("Alice", Some("Wonderland")), 1),
+            (2, MemberNames::new("bob", Some("not alice")), 2),
+        ];
+        for (user_id, names, seconds) in changes {
+            repo.add_name_change(&NameChange {
+                user_id,
+                names,
+                time: Timestamp::from(time(seconds)),
+            })
+            .unwrap();
+        }
+
+        assert_eq!(
+            repo.get_latest_names(1).unwrap(),
+            Some(MemberNames::new("Alice", Some("Wonderland")))
+        );
+        assert_eq!(repo.get_latest_names(3).unwrap(), None);
+
+        assert_eq!(repo.get_total_name_changes(None, None).unwrap(), 3);
+        assert_eq!(repo.get_total_name_changes(Some(1), None).unwrap(), 2);
+        // the name matches either name, ignoring the case
+        let matches = repo
+            .get_paginated_name_changes(None, Some("ALICE"), 0)
+            .unwrap();
+        let users: Vec<u64> = matches.iter().map(|change| change.user_id).collect();
+        assert_eq!(users, vec![2, 1, 1]);
+        assert_eq!(
+            repo.get_total_name_changes(Some(2), Some("wonder"))
+                .unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn log_totals_between_skip_inactive_users() {
+        let mut connection = connection();
+        let tx = connection.transaction().unwrap();
+        let mut repo = SQLiteCharacterStatisticsRepository::new(&tx);
+
+        repo.add_log_entry(1, "user", 100, &time(0), None).unwrap();
+        repo.add_log_entry(1, "user", 50, &time(10), None).unwrap();
+        repo.add_log_entry(2, "user", 300, &time(10), None).unwrap();
+        repo.add_log_entry(2, "user", -100, &time(20), None)
+            .unwrap();
+        repo.add_log_entry(3, "user", 1_000, &time(10), None)
+            .unwrap();
+        repo.set_active_status(3, false, None).unwrap();
+
+        let totals = |repo: &mut SQLiteCharacterStatisticsRepository,
+                      since: Option<DateTime<Utc>>,
+                      until: DateTime<Utc>| {
+            repo.get_log_totals_between(since.as_ref(), &until)
+                .unwrap()
+                .iter()
+                .map(|user| (user.get_user_id(), user.total_characters))
+                .collect::<Vec<(u64, i64)>>()
+        };
+        assert_eq!(totals(&mut repo, None, time(30)), vec![(2, 200), (1, 150)]);
+        // the end is exclusive
+        assert_eq!(
+            totals(&mut repo, Some(time(10)), time(20)),
+            vec![(2, 300), (1, 50)]
+        );
+        assert_eq!(totals(&mut repo, None, time(0)), vec![]);
+    }
+
+    #[test]
+    fn set_last_seen_stores_the_time() {
+        let mut connection = connection();
+        let tx = connection.transaction().unwrap();
+        let mut repo = SQLiteCharacterStatisticsRepository::new(&tx);
+
+        log(&mut repo, 1, 100);
+        let last_seen = |tx: &rusqlite::Transaction| -> Option<i64> {
+            tx.query_row(
+                "SELECT last_seen FROM CharacterStatistics WHERE user_id = 1",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+        assert_eq!(last_seen(&tx), None);
+
+        repo.set_last_seen(1, &time(10)).unwrap();
+        assert_eq!(last_seen(&tx), Some(time(10).timestamp()));
+
+        // unknown users are ignored
+        repo.set_last_seen(2, &time(10)).unwrap();
+        assert!(!repo.exists(2).unwrap());
+    }
+
+    #[test]
+    fn paginated_active_users_by_characters() {
+        let mut connection = connection();
+        let tx = connection.transaction().unwrap();
+        let mut repo = SQLiteCharacterStatisticsRepository::new(&tx);
+
+        for user_id in 1..=LEADERBOARD_PAGE_SIZE + 1 {
+            log(&mut repo, user_id, user_id as i64 * 10);
+        }
+        repo.get_or_initialize_statistics(100, "empty").unwrap();
+        log(&mut repo, 101, 1_000_000);
+        repo.set_active_status(101, false, None).unwrap();
+
+        assert_eq!(
+            repo.get_total_active_users().unwrap(),
+            LEADERBOARD_PAGE_SIZE + 1
+        );
+        let first_page = repo.get_paginated_active_users_by_characters(0).unwrap();
+        assert_eq!(first_page.len() as u64, LEADERBOARD_PAGE_SIZE);
+        assert_eq!(first_page[0].get_user_id(), LEADERBOARD_PAGE_SIZE + 1);
+
+        let second_page = repo.get_paginated_active_users_by_characters(1).unwrap();
+        assert_eq!(second_page.len(), 1);
+        assert_eq!(second_page[0].get_user_id(), 1);
+        assert!(repo
+            .get_paginated_active_users_by_characters(2)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn paginated_users_by_id_include_everyone() {
+        let mut connection = connection();
+        let tx = connection.transaction().unwrap();
+        let mut repo = SQLiteCharacterStatisticsRepository::new(&tx);
+
+        for user_id in (1..=LEADERBOARD_PAGE_SIZE + 1).rev() {
+            repo.get_or_initialize_statistics(user_id, "user").unwrap();
+        }
+        repo.set_active_status(1, false, None).unwrap();
+
+        let first_page = repo.get_paginated_users_by_id(0).unwrap();
+        assert_eq!(first_page.len() as u64, LEADERBOARD_PAGE_SIZE);
+        assert_eq!(first_page[0].get_user_id(), 1);
+        let second_page = repo.get_paginated_users_by_id(1).unwrap();
+        assert_eq!(second_page.len(), 1);
+        assert_eq!(second_page[0].get_user_id(), LEADERBOARD_PAGE_SIZE + 1);
+    }
+
+    #[test]
+    fn paginated_log_entries_are_newest_first() {
+        let mut connection = connection();
+        let tx = connection.transaction().unwrap();
+        let mut repo = SQLiteCharacterStatisticsRepository::new(&tx);
+
+        let entries = LOG_ENTRY_PAGE_SIZE as i64 + 2;
+        for i in 1..=entries {
+            repo.add_log_entry(1, "user", i, &time(i), None).unwrap();
+        }
+        log(&mut repo, 2, 1);
+
+        assert_eq!(repo.get_total_log_entries(1).unwrap(), entries as u64);
+        let first_page = repo.get_paginated_log_entries_by_time(1, 0).unwrap();
+        assert_eq!(first_page.len() as u64, LOG_ENTRY_PAGE_SIZE);
+        assert_eq!(first_page[0].characters(), entries);
+        assert_eq!(
+            first_page[0].time().unix_timestamp(),
+            time(entries).timestamp()
+        );
+
+        let second_page = repo.get_paginated_log_entries_by_time(1, 1).unwrap();
+        assert_eq!(entry_characters(&second_page), vec![2, 1]);
+    }
+
+    #[test]
+    fn paginated_log_entries_show_admin_edits() {
+        let mut connection = connection();
+        let tx = connection.transaction().unwrap();
+        let mut repo = SQLiteCharacterStatisticsRepository::new(&tx);
+
+        log(&mut repo, 1, 100);
+        let (_, entry_id) = repo
+            .add_log_entry_with_id(1, "user", 50, &time(1), None)
+            .unwrap();
+        SQLiteAuditRepository::new(&tx)
+            .add_audit_entry(&AuditEntry {
+                actor_id: 9,
+                target_id: 1,
+                action: AuditAction::EditCharacters,
+                delta: 50,
+                previous_total: 100,
+                reason: "typo".to_owned(),
+                log_entry_id: entry_id,
+                time: Timestamp::from(time(1)),
+            })
+            .unwrap();
+
+        let entries = repo.get_paginated_log_entries_by_time(1, 0).unwrap();
+        let edited_by: Vec<Option<u64>> = entries.iter().map(|entry| entry.edited_by()).collect();
+        assert_eq!(edited_by, vec![Some(9), None]);
+    }
+
+    #[test]
+    fn paginated_log_entries_dont_show_approvals_as_edits() {
+        let mut connection = connection();
+        let tx = connection.transaction().unwrap();
+        let mut repo = SQLiteCharacterStatisticsRepository::new(&tx);
+
+        let (_, entry_id) = repo
+            .add_log_entry_with_id(1, "user", 50_000, &time(0), None)
+            .unwrap();
+        SQLiteAuditRepository::new(&tx)
+            .add_audit_entry(&AuditEntry {
+                actor_id: 9,
+                target_id: 1,
+                action: AuditAction::ApproveLog,
+                delta: 50_000,
+                previous_total: 0,
+                reason: "Reviewed a pending log of 50,000 characters".to_owned(),
+                log_entry_id: entry_id,
+                time: Timestamp::from(time(1)),
+            })
+            .unwrap();
+
+        let entries = repo.get_paginated_log_entries_by_time(1, 0).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].edited_by(), None);
+    }
+
+    #[test]
+    fn get_total_log_entries_without_entries() {
+        let mut connection = connection();
+        let tx = connection.transaction().unwrap();
+        let mut repo = SQLiteCharacterStatisticsRepository::new(&tx);
+
+        // used to fail because a GROUP BY returned no rows
+        assert_eq!(repo.get_total_log_entries(1).unwrap(), 0);
+        repo.get_or_initialize_statistics(1, "user").unwrap();
+        assert_eq!(repo.get_total_log_entries(1).unwrap(), 0);
+    }
+
+    #[test]
+    fn get_log_average_since_counts_recent_positive_logs() {
+        let mut connection = connection();
+        let tx = connection.transaction().unwrap();
+        let mut repo = SQLiteCharacterStatisticsRepository::new(&tx);
+
+        assert_eq!(repo.get_log_average_since(1, &time(0)).unwrap(), (0, 0.0));
+
+        repo.add_log_entry(1, "user", 10_000, &time(-1), None)
+            .unwrap();
+        repo.add_log_entry(1, "user", 100, &time(0), None).unwrap();
+        repo.add_log_entry(1, "user", 300, &time(1), None).unwrap();
+        repo.add_log_entry(1, "user", -50, &time(2), None).unwrap();
+        assert_eq!(repo.get_log_average_since(1, &time(0)).unwrap(), (2, 200.0));
+    }
+
+    #[test]
+    fn get_all_log_entries_are_oldest_first() {
+        let mut connection = connection();
+        let tx = connection.transaction().unwrap();
+        let mut repo = SQLiteCharacterStatisticsRepository::new(&tx);
+
+        repo.add_log_entry(1, "user", 3, &time(5), None).unwrap();
+        repo.add_log_entry(1, "user", 1, &time(1), None).unwrap();
+        // same time, so the insertion order decides
+        repo.add_log_entry(1, "user", 2, &time(1), None).unwrap();
+        assert_eq!(
+            entry_characters(&repo.get_all_log_entries(1).unwrap()),
+            vec![1, 2, 3]
+        );
+        assert!(repo.get_all_log_entries(2).unwrap().is_empty());
+    }
+
+    #[test]
+    fn total_mismatches_and_set_total_characters() {
+        let mut connection = connection();
+        let tx = connection.transaction().unwrap();
+        let mut repo = SQLiteCharacterStatisticsRepository::new(&tx);
+
+        log(&mut repo, 1, 100);
+        log(&mut repo, 2, 200);
+        repo.get_or_initialize_statistics(3, "user").unwrap();
+        assert!(repo.get_total_mismatches().unwrap().is_empty());
+
+        repo.set_total_characters(2, 150).unwrap();
+        repo.set_total_characters(3, 10).unwrap();
+        let mismatches = repo.get_total_mismatches().unwrap();
+        let mismatches: Vec<(u64, i64, i64)> = mismatches
+            .iter()
+            .map(|m| (m.user_id, m.stored_total, m.computed_total))
+            .collect();
+        assert_eq!(mismatches, vec![(2, 150, 200), (3, 10, 0)]);
+    }
+
+    #[test]
+    fn move_and_delete_log_entries() {
+        let mut connection = connection();
+        let tx = connection.transaction().unwrap();
+        let mut repo = SQLiteCharacterStatisticsRepository::new(&tx);
+
+        log(&mut repo, 1, 100);
+        log(&mut repo, 1, 200);
+        log(&mut repo, 2, 300);
+
+        assert_eq!(repo.move_log_entries(1, 2).unwrap(), 2);
+        assert_eq!(repo.get_total_log_entries(1).unwrap(), 0);
+        assert_eq!(repo.get_total_log_entries(2).unwrap(), 3);
+        // the totals are left to the caller
+        assert_eq!(
+            repo.get_statistics(1).unwrap().unwrap().total_characters,
+            300
+        );
+
+        assert_eq!(repo.delete_log_entries(2).unwrap(), 3);
+        assert_eq!(repo.delete_log_entries(2).unwrap(), 0);
+        assert_eq!(
+            repo.get_statistics(2).unwrap().unwrap().total_characters,
+            300
+        );
+    }
+
+    #[test]
+    fn move_and_delete_pending_log_entries() {
+        let mut connection = connection();
+        let tx = connection.transaction().unwrap();
+        let mut repo = SQLiteModerationRepository::new(&tx);
+
+        let first = repo
+            .add_pending_log_entry(1, "alt", 100_000, &time(0), None, None)
+            .unwrap();
+        repo.add_pending_log_entry(1, "alt", 200_000, &time(1), None, None)
+            .unwrap();
+
+        assert_eq!(repo.move_pending_log_entries(1, 2, "main").unwrap(), 2);
+        let moved = repo.take_pending_log_entry(first.id).unwrap().unwrap();
+        assert_eq!((moved.user_id, moved.name.as_str()), (2, "main"));
+
+        assert_eq!(repo.delete_pending_log_entries(1).unwrap(), 0);
+        assert_eq!(repo.delete_pending_log_entries(2).unwrap(), 1);
+    }
+
+    #[test]
+    fn random_logs_keep_the_total_consistent() {
+        let mut connection = connection();
+        let tx = connection.transaction().unwrap();
+        let mut repo = SQLiteCharacterStatisticsRepository::new(&tx);
+        let mut rng = StdRng::seed_from_u64(7);
+
+        for i in 0..500 {
+            let user_id = rng.random_range(1..=5);
+            let characters = rng.random_range(-20_000..=20_000);
+            let before = repo
+                .get_statistics(user_id)
+                .unwrap()
+                .map_or(0, |statistics| statistics.total_characters);
+
+            let statistics = repo
+                .add_log_entry(user_id, "user", characters, &time(i), None)
+                .unwrap();
+            assert_eq!(statistics.total_characters, (before + characters).max(0));
+        }
+
+        // every change to the totals is in the entries
+        assert!(repo.get_total_mismatches().unwrap().is_empty());
+    }
+
+    #[test]
+    fn job_runs() {
+        let mut connection = connection();
+        let tx = connection.transaction().unwrap();
+        let mut repo = SQLiteMetadataRepository::new(&tx);
+
+        assert!(repo.get_job_run("backup").unwrap().is_none());
+        repo.set_job_run(&JobRun {
+            job: "backup".to_owned(),
+            time: time(0),
+            error: Some("disk full".to_owned()),
+        })
+        .unwrap();
+        repo.set_job_run(&JobRun {
+            job: "backup".to_owned(),
+            time: time(10),
+            error: None,
+        })
+        .unwrap();
+
+        // the last run replaces the previous one, including its error
+        let run = repo.get_job_run("backup").unwrap().unwrap();
+        assert_eq!(run.time, time(10));
+        assert_eq!(run.error, None);
+        let rows: u64 = tx
+            .query_row("SELECT COUNT(*) FROM JobRun", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(rows, 1);
+
+        repo.clear_job_run("backup").unwrap();
+        assert!(repo.get_job_run("backup").unwrap().is_none());
+    }
+
+    #[test]
+    fn migration_batches() {
+        let mut connection = connection();
+        let tx = connection.transaction().unwrap();
+        let mut repo = SQLiteMetadataRepository::new(&tx);
+
+        assert!(!repo.is_migration_applied("batch").unwrap());
+        assert!(!repo.has_migration_batches().unwrap());
+        repo.add_migration_batch("batch", 3, &time(0)).unwrap();
+        assert!(repo.is_migration_applied("batch").unwrap());
+        assert!(repo.has_migration_batches().unwrap());
+        assert!(!repo.is_migration_applied("other").unwrap());
+        assert!(repo.add_migration_batch("batch", 3, &time(1)).is_err());
+    }
+
+    #[test]
+    fn reminders() {
+        let mut connection = connection();
+        let tx = connection.transaction().unwrap();
+        let mut repo = SQLiteReminderRepository::new(&tx);
+
+        let reminder = Reminder {
+            user_id: 1,
+            time: NaiveTime::from_hms_opt(21, 30, 0).unwrap(),
+            frequency: ReminderFrequency::Weekdays,
+            only_if_not_logged: true,
+            last_sent: None,
+        };
+        assert!(repo.get_reminder(1).unwrap().is_none());
+        repo.set_reminder(&reminder).unwrap();
+        assert_eq!(repo.get_reminder(1).unwrap(), Some(reminder.clone()));
+
+        repo.set_reminder_sent(1, &time(0)).unwrap();
+        assert_eq!(repo.get_reminders().unwrap()[0].last_sent, Some(time(0)));
+
+        // setting it again replaces it
+        repo.set_reminder(&Reminder {
+            frequency: ReminderFrequency::Daily,
+            ..reminder
+        })
+        .unwrap();
+        let reminders = repo.get_reminders().unwrap();
+        assert_eq!(reminders.len(), 1);
+        assert_eq!(reminders[0].frequency, ReminderFrequency::Daily);
+        assert_eq!(reminders[0].last_sent, None);
+
+        assert!(repo.delete_reminder(1).unwrap());
+        assert!(!repo.delete_reminder(1).unwrap());
+        assert!(repo.get_reminders().unwrap().is_empty());
+    }
+
+    #[test]
+    fn user_settings() {
+        let mut connection = connection();
+        let tx = connection.transaction().unwrap();
+        let mut repo = SQLiteUserSettingsRepository::new(&tx);
+
+        assert_eq!(repo.get_settings(1).unwrap(), UserSettings::new(1));
+
+        let settings = UserSettings {
+            timezone: "America/New_York".to_owned(),
+            default_media_type: Some(MediaType::VisualNovel),
+            history_visible: false,
+            review_dms: false,
+            ..UserSettings::new(1)
+        };
+        repo.set_settings(&settings).unwrap();
+        assert_eq!(repo.get_settings(1).unwrap(), settings);
+
+        // setting them again replaces them
+        repo.set_settings(&UserSettings::new(1)).unwrap();
+        assert_eq!(repo.get_settings(1).unwrap(), UserSettings::new(1));
+    }
+
+    #[test]
+    fn leaderboard_skips_hidden_users() {
+        let mut connection = connection();
+        let tx = connection.transaction().unwrap();
+        let mut repo = SQLiteCharacterStatisticsRepository::new(&tx);
+
+        log(&mut repo, 1, 500);
+        log(&mut repo, 2, 100);
+        SQLiteUserSettingsRepository::new(&tx)
+            .set_settings(&UserSettings {
+                show_on_leaderboard: false,
+                ..UserSettings::new(1)
+            })
+            .unwrap();
+
+        let hidden = repo.get_statistics(1).unwrap().unwrap();
+        let shown = repo.get_statistics(2).unwrap().unwrap();
+        assert_eq!(repo.get_rank(&hidden).unwrap(), None);
+        assert_eq!(repo.get_rank(&shown).unwrap(), Some(1));
+        assert_eq!(repo.get_total_active_users().unwrap(), 1);
+        let page = repo.get_paginated_active_users_by_characters(0).unwrap();
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].get_user_id(), 2);
+        assert_eq!(
+            repo.get_log_totals_between(None, &time(10)).unwrap().len(),
+            1
+        );
+    }
+
+    fn attempt(
+        user_id: u64,
+        quiz_role: QuizRoles,
+        score: i32,
+        passed: bool,
+        seconds: i64,
+    ) -> QuizAttempt {
+        QuizAttempt::new(
+            user_id,
+            quiz_role,
+            score,
+            passed,
+            &Timestamp::from(time(seconds)),
+        )
+    }
+
+    #[test]
+    fn quiz_attempts_since() {
+        let mut connection = connection();
+        let tx = connection.transaction().unwrap();
+        let mut repo = SQLiteQuizAttemptRepository::new(&tx);
+
+        repo.add_quiz_attempt(&attempt(1, QuizRoles::Quiz4, 10, false, 20))
+            .unwrap();
+        repo.add_quiz_attempt(&attempt(1, QuizRoles::Quiz4, 30, true, 10))
+            .unwrap();
+        repo.add_quiz_attempt(&attempt(1, QuizRoles::Quiz4, 5, false, 0))
+            .unwrap();
+        // other users and quizzes aren't returned
+        repo.add_quiz_attempt(&attempt(2, QuizRoles::Quiz4, 30, true, 10))
+            .unwrap();
+        repo.add_quiz_attempt(&attempt(1, QuizRoles::Quiz5, 30, true, 10))
+            .unwrap();
+
+        let attempts = repo
+            .get_quiz_attempts_since(1, QuizRoles::Quiz4, &time(10))
+            .unwrap();
+        let attempts: Vec<(i32, bool, i64)> = attempts
+            .iter()
+            .map(|attempt| {
+                (
+                    attempt.score(),
+                    attempt.passed(),
+                    attempt.time().unix_timestamp(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            attempts,
+            vec![
+                (30, true, time(10).timestamp()),
+                (10, false, time(20).timestamp())
+            ]
+        );
+    }
+
+    #[test]
+    fn quiz_statistics() {
+        let mut connection = connection();
+        let tx = connection.transaction().unwrap();
+        let mut repo = SQLiteQuizAttemptRepository::new(&tx);
+
+        let empty = repo.get_quiz_statistics(QuizRoles::Quiz1).unwrap();
+        assert_eq!((empty.attempts, empty.passes), (0, 0));
+        assert_eq!(empty.average_score, 0.0);
+
+        repo.add_quiz_attempt(&attempt(1, QuizRoles::Quiz1, 10, false, 0))
+            .unwrap();
+        repo.add_quiz_attempt(&attempt(1, QuizRoles::Quiz1, 15, true, 1))
+            .unwrap();
+        repo.add_quiz_attempt(&attempt(2, QuizRoles::Quiz1, 14, false, 2))
+            .unwrap();
+        repo.add_quiz_attempt(&attempt(2, QuizRoles::Quiz2, 20, true, 3))
+            .unwrap();
+
+        let statistics = repo.get_quiz_statistics(QuizRoles::Quiz1).unwrap();
+        assert_eq!((statistics.attempts, statistics.passes), (3, 1));
+        assert_eq!(statistics.average_score, 13.0);
+        assert_eq!(statistics.best_score, 15);
+    }
+
+    #[test]
+    fn quiz_passes_are_sorted_by_the_first_pass_and_skip_hidden_users() {
+        let mut connection = connection();
+        let tx = connection.transaction().unwrap();
+        let mut statistics_repo = SQLiteCharacterStatisticsRepository::new(&tx);
+        log(&mut statistics_repo, 2, 100);
+        let mut repo = SQLiteQuizAttemptRepository::new(&tx);
+
+        repo.add_quiz_attempt(&attempt(1, QuizRoles::Quiz1, 15, true, 5))
+            .unwrap();
+        repo.add_quiz_attempt(&attempt(2, QuizRoles::Quiz1, 15, true, 3))
+            .unwrap();
+        repo.add_quiz_attempt(&attempt(2, QuizRoles::Quiz1, 15, true, 1))
+            .unwrap();
+        repo.add_quiz_attempt(&attempt(3, QuizRoles::Quiz1, 15, true, 2))
+            .unwrap();
+        // failed attempts don't count
+        repo.add_quiz_attempt(&attempt(4, QuizRoles::Quiz1, 0, false, 0))
+            .unwrap();
+        SQLiteUserSettingsRepository::new(&tx)
+            .set_settings(&UserSettings {
+                show_on_leaderboard: false,
+                ..UserSettings::new(3)
+            })
+            .unwrap();
+
+        let passes = repo.get_paginated_quiz_passes(QuizRoles::Quiz1, 0).unwrap();
+        let passes: Vec<(u64, Option<&str>, i64)> = passes
+            .iter()
+            .map(|pass| {
+                (
+                    pass.user_id,
+                    pass.name.as_deref(),
+                    pass.time.unix_timestamp(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            passes,
+            vec![
+                (2, Some("user"), time(1).timestamp()),
+                (1, None, time(5).timestamp())
+            ]
+        );
+        assert_eq!(repo.get_total_quiz_passes(QuizRoles::Quiz1).unwrap(), 2);
+        assert_eq!(repo.get_total_quiz_passes(QuizRoles::Quiz2).unwrap(), 0);
+    }
 }
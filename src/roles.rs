@@ -1,11 +1,21 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt};
 
-use serenity::all::{Guild, Member, Message, Role, RoleId, UserId};
+use chrono::{DateTime, Duration, Utc};
+use serenity::all::{CacheHttp, ChannelId, Guild, GuildId, Member, Message, Role, RoleId, UserId};
 
 use crate::{
-    constants::{self, QUIZ_FONT, QUIZ_REQUIREMENTS, QUIZ_TIME_LIMIT},
+    constants::{
+        self, CONGRATULATE_NEW_ROLE_CHANNEL_IDS, QUIZ_FONT, QUIZ_FONT_OPTION,
+        QUIZ_PASS_REQUIRES_CHARACTERS, QUIZ_REQUIREMENTS, QUIZ_TIME_LIMIT,
+    },
     kotoba::QuizData,
-    model::{CharacterStatistics, Data},
+    model::{CharacterStatistics, Data, QuizAttempt},
+    repository::{
+        CharacterStatisticsRepository, QuizAttemptRepository, SQLiteCharacterStatisticsRepository,
+        SQLiteQuizAttemptRepository,
+    },
+    service::is_promotion,
+    utils::{format_duration, format_with_commas},
 };
 
 // get roles on request, no need to insert to DB
@@ -20,7 +30,7 @@ impl UserRoles {
         let mut roles: Vec<Roles> = Vec::new();
 
         for id in user_roles {
-            if let Some(guild_role) = guild_roles.get(&id) {
+            if let Some(guild_role) = guild_roles.get(id) {
                 // Try to parse as a quiz role
                 if let Some(quiz_role) = QuizRoles::from_string(&guild_role.name) {
                     quizzes.push(quiz_role);
@@ -38,7 +48,7 @@ impl UserRoles {
     /// Updates the user roles based on currently possessed quiz roles and character count, returns their newest role
     pub async fn update_role(
         &self,
-        ctx: crate::Context<'_>,
+        cache_http: impl CacheHttp,
         guild: &Guild,
         user: &Member,
         statistics: &CharacterStatistics,
@@ -50,7 +60,7 @@ impl UserRoles {
         if current_role.is_none() {
             for role in &self.roles {
                 let guild_role = guild.role_by_name(&role.to_string()).unwrap();
-                user.remove_role(ctx, guild_role.id).await?;
+                user.remove_role(cache_http.http(), guild_role.id).await?;
             }
             return Ok(None);
         }
@@ -64,21 +74,62 @@ impl UserRoles {
         // user's role did change, clear the previous ones and give them the correct role
         for role in &self.roles {
             let guild_role = guild.role_by_name(&role.to_string()).unwrap();
-            user.remove_role(ctx, guild_role.id).await?;
+            user.remove_role(cache_http.http(), guild_role.id).await?;
         }
 
         let guild_role = guild.role_by_name(&new_role.to_string()).unwrap();
-        user.add_role(ctx, guild_role.id).await?;
+        user.add_role(cache_http.http(), guild_role.id).await?;
         Ok(Some(new_role))
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+/// Updates the roles of a member after their total changed outside of a command, and congratulates them on a higher role.
+/// Returns the roles they had before and the congratulation if there was one, None if they aren't a member of the guild.
+pub async fn update_member_roles(
+    ctx: &serenity::client::Context,
+    guild_id: GuildId,
+    user_id: UserId,
+    statistics: &CharacterStatistics,
+) -> Result<Option<(UserRoles, Option<String>)>, crate::Error> {
+    let guild = guild_id
+        .to_guild_cached(&ctx.cache)
+        .map(|guild| guild.clone());
+    let member = guild_id.member(ctx, user_id).await;
+    let (Some(guild), Ok(member)) = (guild, member) else {
+        return Ok(None);
+    };
+
+    let roles = UserRoles::new(&member.roles, &guild.roles);
+    let new_role = roles.update_role(ctx, &guild, &member, statistics).await?;
+    let mut congratulation = None;
+    if let Some(new_role) = new_role {
+        // role changed, if it's higher give a congratulations message
+        if is_promotion(new_role, &roles.roles) {
+            let congrats_msg = format!(
+                "Congratulations {} for obtaining role: {}",
+                member.user.display_name(),
+                new_role
+            );
+            for channel_id in CONGRATULATE_NEW_ROLE_CHANNEL_IDS {
+                ChannelId::new(channel_id).say(ctx, &congrats_msg).await?;
+            }
+            congratulation = Some(congrats_msg);
+        }
+    }
+    Ok(Some((roles, congratulation)))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, poise::ChoiceParameter)]
 pub enum QuizRoles {
+    #[name = "Quiz 1"]
     Quiz1,
+    #[name = "Quiz 2"]
     Quiz2,
+    #[name = "Quiz 3"]
     Quiz3,
+    #[name = "Quiz 4"]
     Quiz4,
+    #[name = "Quiz 5"]
     Quiz5,
 }
 
@@ -88,10 +139,76 @@ pub struct QuizRequirement {
     pub score_limit: i32,
     pub max_missed_questions: i32,
     pub unique_ids: Vec<String>,
+    /// The deck names used when starting the quiz with `k!quiz`
+    pub deck_names: Vec<String>,
+    /// Minimum time between two counted attempts, None means no cooldown
+    pub cooldown: Option<Duration>,
+    /// Maximum counted attempts inside a rolling window, None means unlimited
+    pub attempt_limit: Option<AttemptLimit>,
 }
 
-impl QuizRoles {
-    pub fn to_string(&self) -> String {
+#[derive(Debug)]
+pub struct AttemptLimit {
+    pub max_attempts: usize,
+    pub window: Duration,
+}
+
+impl QuizRequirement {
+    /// The exact kotoba command to start this quiz with the settings that are accepted
+    pub fn kotoba_command(&self) -> String {
+        format!(
+            "k!quiz {} {} nd mmq={} font={} atl={}",
+            self.deck_names.join("+"),
+            self.score_limit,
+            self.max_missed_questions,
+            QUIZ_FONT_OPTION,
+            QUIZ_TIME_LIMIT / 1000
+        )
+    }
+
+    /// How far back attempts need to be fetched to evaluate the cooldown and attempt limit
+    pub fn attempt_history_span(&self) -> Duration {
+        let cooldown = self.cooldown.unwrap_or_else(Duration::zero);
+        let window = self
+            .attempt_limit
+            .as_ref()
+            .map_or_else(Duration::zero, |limit| limit.window);
+        cooldown.max(window)
+    }
+
+    /// Returns the earliest time a new attempt will be counted, given the previous attempts sorted by time ascendingly.
+    /// None means that an attempt right now would be counted.
+    pub fn next_counted_attempt_time(
+        &self,
+        attempts: &[QuizAttempt],
+        now: &DateTime<Utc>,
+    ) -> Option<DateTime<Utc>> {
+        let mut next_time: Option<DateTime<Utc>> = None;
+
+        if let (Some(cooldown), Some(last)) = (self.cooldown, attempts.last()) {
+            next_time = Some(**last.time() + cooldown);
+        }
+
+        if let Some(limit) = &self.attempt_limit {
+            let window_start = *now - limit.window;
+            let in_window: Vec<&QuizAttempt> = attempts
+                .iter()
+                .filter(|attempt| **attempt.time() > window_start)
+                .collect();
+            if limit.max_attempts > 0 && in_window.len() >= limit.max_attempts {
+                // the window frees up once the oldest attempt that keeps us at the limit expires
+                let freeing_attempt = in_window[in_window.len() - limit.max_attempts];
+                let time = **freeing_attempt.time() + limit.window;
+                next_time = Some(next_time.map_or(time, |t| t.max(time)));
+            }
+        }
+
+        next_time.filter(|time| time > now)
+    }
+}
+
+impl fmt::Display for QuizRoles {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let string = match self {
             Self::Quiz1 => "Quiz 1",
             Self::Quiz2 => "Quiz 2",
@@ -99,7 +216,24 @@ impl QuizRoles {
             Self::Quiz4 => "Quiz 4",
             Self::Quiz5 => "Quiz 5",
         };
-        string.to_string()
+        f.write_str(string)
+    }
+}
+
+impl QuizRoles {
+    pub fn requirement(&self) -> &'static QuizRequirement {
+        QUIZ_REQUIREMENTS
+            .iter()
+            .find(|requirement| &requirement.quiz_role == self)
+            .expect("Every quiz role must have a quiz requirement!")
+    }
+
+    /// Returns the first quiz, in role order, that hasn't been passed yet
+    pub fn next_quiz(quiz_roles: &[QuizRoles]) -> Option<QuizRoles> {
+        ROLE_REQUIREMENTS
+            .iter()
+            .filter_map(|requirement| requirement.quiz_role)
+            .find(|quiz_role| !quiz_roles.contains(quiz_role))
     }
 
     pub fn from_string(input: &str) -> Option<QuizRoles> {
@@ -179,8 +313,7 @@ impl QuizRoles {
 
                     println!(
                         "{} tried to do quiz {}",
-                        quiz_data.participants[0].discord_user.id,
-                        current_quiz.quiz_role.to_string()
+                        quiz_data.participants[0].discord_user.id, current_quiz.quiz_role
                     );
 
                     let quiz_score_limit = &quiz_data.settings.score_limit;
@@ -190,11 +323,7 @@ impl QuizRoles {
                     let quiz_score = &quiz_data.scores[0].score;
                     let quiz_user = &quiz_data.participants[0].discord_user.id;
 
-                    // Since the player didn't reach the score needed, we just ignore it
-                    if quiz_score < &current_quiz.score_limit {
-                        continue;
-                    }
-
+                    // attempts with the wrong settings aren't real attempts, so check them before counting anything
                     if &current_quiz.max_missed_questions != quiz_max_missed_questions
                         || &current_quiz.score_limit != quiz_score_limit
                         || quiz_font != QUIZ_FONT
@@ -204,8 +333,92 @@ impl QuizRoles {
                         continue;
                     }
 
-                    // Actually give the role to the member
                     let user_id = UserId::new(quiz_user.parse::<u64>()?);
+                    let reached_score = quiz_score >= &current_quiz.score_limit;
+                    let gated = Roles::gated_by_quiz(current_quiz.quiz_role);
+                    let gated_characters = gated.as_ref().map(|gated| gated.characters);
+
+                    // enforce the cooldown and attempt limit, only counted attempts are recorded
+                    // an attempt only counts as passed if it grants the role, so the character gate is checked first
+                    let now = data.clock.timestamp();
+                    let quiz_score = *quiz_score;
+                    let (next_attempt_time, total_characters) = data
+                        .database
+                        .write(move |connection| {
+                            let tx = connection.transaction()?;
+                            let statistics_repository =
+                                SQLiteCharacterStatisticsRepository::new(&tx);
+                            let total_characters = statistics_repository
+                                .get_statistics(user_id.get())?
+                                .map_or(0, |statistics| statistics.total_characters);
+                            let has_characters = !QUIZ_PASS_REQUIRES_CHARACTERS
+                                || gated_characters
+                                    .is_none_or(|characters| total_characters >= characters);
+
+                            let mut repository = SQLiteQuizAttemptRepository::new(&tx);
+                            let since = *now - current_quiz.attempt_history_span();
+                            let attempts = repository.get_quiz_attempts_since(
+                                user_id.get(),
+                                current_quiz.quiz_role,
+                                &since,
+                            )?;
+
+                            let next_attempt_time =
+                                current_quiz.next_counted_attempt_time(&attempts, &now);
+                            if next_attempt_time.is_none() {
+                                repository.add_quiz_attempt(&QuizAttempt::new(
+                                    user_id.get(),
+                                    current_quiz.quiz_role,
+                                    quiz_score,
+                                    reached_score && has_characters,
+                                    &now,
+                                ))?;
+                            }
+                            tx.commit()?;
+                            Ok((next_attempt_time, total_characters))
+                        })
+                        .await?;
+
+                    if let Some(next_attempt_time) = next_attempt_time {
+                        let wait = next_attempt_time - data.clock.now();
+                        message
+                            .reply(
+                                ctx,
+                                format!(
+                                    "This attempt was not counted. Your next attempt at {} will be counted in {}.",
+                                    current_quiz.quiz_role,
+                                    format_duration(&wait)
+                                ),
+                            )
+                            .await?;
+                        continue;
+                    }
+
+                    // Since the player didn't reach the score needed, we just ignore it
+                    if !reached_score {
+                        continue;
+                    }
+
+                    if let Some(gated) = gated.filter(|_| QUIZ_PASS_REQUIRES_CHARACTERS) {
+                        if total_characters < gated.characters {
+                            message
+                                .reply(
+                                    ctx,
+                                    format!(
+                                        "<@{}> passed {}, but {} needs {} characters and you have {}. Log the rest of your characters and take the quiz again.",
+                                        user_id.get(),
+                                        current_quiz.quiz_role,
+                                        gated.role,
+                                        format_with_commas(gated.characters),
+                                        format_with_commas(total_characters)
+                                    ),
+                                )
+                                .await?;
+                            continue;
+                        }
+                    }
+
+                    // Actually give the role to the member
                     let guild_id = message.guild_id.unwrap();
                     let guild = message.guild(&ctx.cache).unwrap().clone();
                     let role = guild
@@ -219,7 +432,7 @@ impl QuizRoles {
                             format!(
                                 "Congratulations <@{}> on passing {}!",
                                 user_id.get(),
-                                current_quiz.quiz_role.to_string()
+                                current_quiz.quiz_role
                             ),
                         )
                         .await?;
@@ -270,7 +483,7 @@ impl Ord for Roles {
 #[derive(Debug, Clone)]
 pub struct RoleRequirement {
     pub role: Roles,
-    pub characters: i32,
+    pub characters: i64,
     pub quiz_role: Option<QuizRoles>,
 }
 
@@ -337,8 +550,8 @@ static ROLE_REQUIREMENTS: [RoleRequirement; 12] = [
     },
 ];
 
-impl Roles {
-    pub fn to_string(&self) -> String {
+impl fmt::Display for Roles {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let string = match self {
             Self::Heimin => "平民",
             Self::Danshaku => "男爵",
@@ -353,12 +566,14 @@ impl Roles {
             Self::Texnsen => "天仙",
             Self::Jouzu => "上手",
         };
-        string.to_string()
+        f.write_str(string)
     }
+}
 
+impl Roles {
     pub fn from_characters_and_quiz_roles(
-        quiz_roles: &Vec<QuizRoles>,
-        characters: i32,
+        quiz_roles: &[QuizRoles],
+        characters: i64,
     ) -> Option<Roles> {
         // Check for the highest eligible role
         let mut highest_role: Option<Roles> = None;
@@ -371,15 +586,15 @@ impl Roles {
                     return highest_role;
                 }
 
-                highest_role = Some(requirement.role.clone());
+                highest_role = Some(requirement.role);
             }
         }
         highest_role
     }
 
     pub fn next_role_requirement(
-        quiz_roles: &Vec<QuizRoles>,
-        characters: i32,
+        quiz_roles: &[QuizRoles],
+        characters: i64,
     ) -> Option<RoleRequirement> {
         for requirement in ROLE_REQUIREMENTS.iter() {
             if characters < requirement.characters {
@@ -394,6 +609,14 @@ impl Roles {
         None
     }
 
+    /// Returns the requirement of the role that is gated behind a quiz
+    pub fn gated_by_quiz(quiz_role: QuizRoles) -> Option<RoleRequirement> {
+        ROLE_REQUIREMENTS
+            .iter()
+            .find(|requirement| requirement.quiz_role == Some(quiz_role))
+            .cloned()
+    }
+
     pub fn from_string(input: &str) -> Option<Roles> {
         match input {
             "平民" => Some(Self::Heimin),
@@ -411,3 +634,108 @@ impl Roles {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use serenity::all::Timestamp;
+
+    use super::*;
+
+    fn time(hours: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(1_700_000_000, 0).unwrap() + Duration::hours(hours)
+    }
+
+    fn attempts(hours: &[i64]) -> Vec<QuizAttempt> {
+        hours
+            .iter()
+            .map(|hours| {
+                QuizAttempt::new(
+                    1,
+                    QuizRoles::Quiz4,
+                    0,
+                    false,
+                    &Timestamp::from(time(*hours)),
+                )
+            })
+            .collect()
+    }
+
+    fn requirement(
+        cooldown: Option<Duration>,
+        attempt_limit: Option<AttemptLimit>,
+    ) -> QuizRequirement {
+        QuizRequirement {
+            quiz_role: QuizRoles::Quiz4,
+            score_limit: 1,
+            max_missed_questions: 4,
+            unique_ids: Vec::new(),
+            deck_names: Vec::new(),
+            cooldown,
+            attempt_limit,
+        }
+    }
+
+    fn three_a_day() -> Option<AttemptLimit> {
+        Some(AttemptLimit {
+            max_attempts: 3,
+            window: Duration::hours(24),
+        })
+    }
+
+    #[test]
+    fn attempts_inside_the_cooldown_wait_for_it() {
+        let requirement = requirement(Some(Duration::hours(1)), None);
+        let attempts = attempts(&[0]);
+        assert_eq!(
+            requirement.next_counted_attempt_time(&attempts, &(time(0) + Duration::minutes(30))),
+            Some(time(1))
+        );
+        assert_eq!(
+            requirement.next_counted_attempt_time(&attempts, &time(1)),
+            None
+        );
+    }
+
+    #[test]
+    fn attempts_at_the_cap_wait_for_the_window() {
+        let requirement = requirement(Some(Duration::hours(1)), three_a_day());
+        let attempts = attempts(&[0, 2, 4]);
+        // past the cooldown but the oldest of the three attempts is still inside the window
+        assert_eq!(
+            requirement.next_counted_attempt_time(&attempts, &time(10)),
+            Some(time(24))
+        );
+        // two attempts don't reach the cap
+        assert_eq!(
+            requirement.next_counted_attempt_time(&attempts[..2], &time(10)),
+            None
+        );
+    }
+
+    #[test]
+    fn the_window_slides_open() {
+        let requirement = requirement(None, three_a_day());
+        let attempts = attempts(&[0, 2, 4, 25]);
+        // the attempt at 0 left the window, the ones at 2, 4 and 25 are inside it until 26
+        assert_eq!(
+            requirement.next_counted_attempt_time(&attempts, &time(25)),
+            Some(time(26))
+        );
+        assert_eq!(
+            requirement.next_counted_attempt_time(&attempts, &time(26)),
+            None
+        );
+    }
+
+    #[test]
+    fn attempts_without_limits_are_always_counted() {
+        let requirement = requirement(None, None);
+        let attempts = attempts(&[0, 0, 0, 0, 0]);
+        assert_eq!(
+            requirement.next_counted_attempt_time(&attempts, &time(0)),
+            None
+        );
+        assert_eq!(requirement.attempt_history_span(), Duration::zero());
+    }
+}
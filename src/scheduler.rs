@@ -0,0 +1,634 @@
+use std::{
+    collections::{HashMap, HashSet},
+    env::var,
+    fmt, fs,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Datelike, Duration, Months, NaiveTime, Utc, Weekday};
+use poise::{serenity_prelude as serenity, ChoiceParameter};
+use serenity::all::{GuildId, UserId};
+
+use crate::{
+    clock::Clock,
+    constants::{
+        ACTIVE_USERS_SCHEDULE, BACKUPS_KEPT, BACKUP_DIRECTORY, BACKUP_SCHEDULE, REMINDERS_SCHEDULE,
+        SCHEDULER_TICK_INTERVAL,
+    },
+    database::Database,
+    digest::{digest_channel, digest_period, post_digest},
+    model::{JobRun, MemberNames},
+    reminders::send_reminders,
+    repository::{
+        MetadataRepository, SQLiteCharacterStatisticsRepository, SQLiteMetadataRepository,
+        SQLiteNameHistoryRepository,
+    },
+    service, Error,
+};
+
+/// The recurring work of the bot, the names are stored with the last runs
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, poise::ChoiceParameter)]
+pub enum Job {
+    #[name = "active_users"]
+    ActiveUsers,
+    #[name = "backup"]
+    Backup,
+    #[name = "digest"]
+    Digest,
+    #[name = "reminders"]
+    Reminders,
+}
+
+impl Job {
+    pub const ALL: [Job; 4] = [Job::ActiveUsers, Job::Backup, Job::Digest, Job::Reminders];
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::ActiveUsers => "Reconciles the active status with the member list",
+            Self::Backup => "Copies the database into the backup directory",
+            Self::Digest => "Posts the leaderboard digest of the last period",
+            Self::Reminders => "DMs the reminders to log that are due",
+        }
+    }
+
+    /// The schedule from the SCHEDULE_<JOB> env var, i.e. SCHEDULE_BACKUP, or the default one
+    fn schedule(&self) -> Result<Schedule, Error> {
+        let default = match self {
+            Self::ActiveUsers => ACTIVE_USERS_SCHEDULE,
+            Self::Backup => BACKUP_SCHEDULE,
+            // without a channel there's nowhere to post it
+            Self::Digest => match digest_channel() {
+                Some(_) => digest_period()?.schedule(),
+                None => "off",
+            },
+            Self::Reminders => REMINDERS_SCHEDULE,
+        };
+        let variable = format!("SCHEDULE_{}", self.name().to_uppercase());
+        let schedule = var(&variable).unwrap_or_else(|_| default.to_owned());
+        Schedule::parse(&schedule)
+            .ok_or_else(|| format!("{} is not a valid schedule: {}", variable, schedule).into())
+    }
+}
+
+/// When a job runs on its own, the times are in UTC
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Schedule {
+    /// this long after the last run
+    Every(Duration),
+    Daily(NaiveTime),
+    Weekly(Weekday, NaiveTime),
+    /// on the first day of every month
+    Monthly(NaiveTime),
+    /// only when triggered with /admin jobs
+    Off,
+}
+
+impl Schedule {
+    /// Parses `every 30m`, `every 6h`, `every 2d`, `daily 04:00`, `weekly sun 04:00`, `monthly 04:00` or `off`
+    pub fn parse(string: &str) -> Option<Schedule> {
+        let parts: Vec<&str> = string.split_whitespace().collect();
+        match parts.as_slice() {
+            ["off"] => Some(Self::Off),
+            ["every", interval] => parse_interval(interval).map(Self::Every),
+            ["daily", time] => Some(Self::Daily(parse_time(time)?)),
+            ["weekly", day, time] => Some(Self::Weekly(day.parse().ok()?, parse_time(time)?)),
+            ["monthly", time] => Some(Self::Monthly(parse_time(time)?)),
+            _ => None,
+        }
+    }
+
+    /// The first time the job is due after `time`, None if it doesn't run on its own
+    pub fn next_after(&self, time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let (first, period) = match self {
+            Self::Off => return None,
+            Self::Every(interval) => return Some(time + *interval),
+            Self::Daily(at) => (time.date_naive().and_time(*at), Duration::days(1)),
+            Self::Weekly(day, at) => {
+                let days_ahead =
+                    (day.num_days_from_monday() + 7 - time.weekday().num_days_from_monday()) % 7;
+                let date = time.date_naive() + Duration::days(days_ahead.into());
+                (date.and_time(*at), Duration::weeks(1))
+            }
+            Self::Monthly(at) => {
+                let first_day = time.date_naive().with_day(1)?;
+                let first = first_day.and_time(*at).and_utc();
+                if first > time {
+                    return Some(first);
+                }
+                let next_month = first_day.checked_add_months(Months::new(1))?;
+                return Some(next_month.and_time(*at).and_utc());
+            }
+        };
+        let first = first.and_utc();
+        Some(if first > time { first } else { first + period })
+    }
+
+    /// When the job is due next. Jobs that never ran are due right away with an interval,
+    /// otherwise they wait for their first time after the bot started.
+    pub fn next_run(
+        &self,
+        last_run: Option<&JobRun>,
+        started: DateTime<Utc>,
+    ) -> Option<DateTime<Utc>> {
+        match (last_run, self) {
+            (Some(last_run), _) => self.next_after(last_run.time),
+            (None, Self::Every(_)) => Some(started),
+            (None, _) => self.next_after(started),
+        }
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Off => write!(f, "off"),
+            Self::Every(interval) => {
+                let minutes = interval.num_minutes();
+                if minutes % (24 * 60) == 0 {
+                    write!(f, "every {}d", minutes / (24 * 60))
+                } else if minutes % 60 == 0 {
+                    write!(f, "every {}h", minutes / 60)
+                } else {
+                    write!(f, "every {}m", minutes)
+                }
+            }
+            Self::Daily(at) => write!(f, "daily {}", at.format("%H:%M")),
+            Self::Monthly(at) => write!(f, "monthly {}", at.format("%H:%M")),
+            Self::Weekly(day, at) => write!(
+                f,
+                "weekly {} {}",
+                day.to_string().to_lowercase(),
+                at.format("%H:%M")
+            ),
+        }
+    }
+}
+
+fn parse_interval(string: &str) -> Option<Duration> {
+    let unit = string.chars().last()?;
+    let number: i64 = string[..string.len() - unit.len_utf8()].parse().ok()?;
+    if number <= 0 {
+        return None;
+    }
+    match unit {
+        'm' => Duration::try_minutes(number),
+        'h' => Duration::try_hours(number),
+        'd' => Duration::try_days(number),
+        _ => None,
+    }
+}
+
+pub fn parse_time(string: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(string, "%H:%M").ok()
+}
+
+/// What the jobs need to run, outside of a command
+#[derive(Clone)]
+pub struct JobContext {
+    pub serenity: serenity::Context,
+    pub database: Database,
+    pub clock: Arc<dyn Clock>,
+}
+
+/// A job with its schedule and last run, for /admin jobs
+pub struct JobStatus {
+    pub job: Job,
+    pub schedule: Schedule,
+    pub last_run: Option<JobRun>,
+    pub next_run: Option<DateTime<Utc>>,
+    pub running: bool,
+}
+
+/// Runs the jobs on their schedules. The last runs are stored, so the schedules carry over restarts.
+#[derive(Clone)]
+pub struct Scheduler {
+    schedules: Arc<HashMap<Job, Schedule>>,
+    /// jobs at a time of day that never ran wait for their first time after this
+    started: DateTime<Utc>,
+    /// a job doesn't run twice at once, i.e. when it's triggered while it's running on its schedule
+    running: Arc<Mutex<HashSet<Job>>>,
+}
+
+impl Scheduler {
+    pub fn new(started: DateTime<Utc>) -> Result<Scheduler, Error> {
+        let mut schedules = HashMap::new();
+        for job in Job::ALL {
+            schedules.insert(job, job.schedule()?);
+        }
+        Ok(Scheduler {
+            schedules: Arc::new(schedules),
+            started,
+            running: Arc::new(Mutex::new(HashSet::new())),
+        })
+    }
+
+    pub fn schedule(&self, job: Job) -> Schedule {
+        self.schedules[&job]
+    }
+
+    /// Starts the timer that runs the due jobs, once the bot is logged in
+    pub fn start(&self, context: JobContext) {
+        let scheduler = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SCHEDULER_TICK_INTERVAL);
+            loop {
+                interval.tick().await;
+                let jobs = match scheduler.due_jobs(&context).await {
+                    Ok(jobs) => jobs,
+                    Err(error) => {
+                        println!("Failed to check the scheduled jobs: {}", error);
+                        continue;
+                    }
+                };
+                for job in jobs {
+                    if let Err(error) = scheduler.run(job, &context).await {
+                        println!("Job {} failed: {}", job.name(), error);
+                    }
+                }
+            }
+        });
+    }
+
+    async fn due_jobs(&self, context: &JobContext) -> Result<Vec<Job>, Error> {
+        let scheduler = self.clone();
+        let now = context.clock.now();
+        let running = self.running.lock().unwrap().clone();
+        let jobs = context
+            .database
+            .read(move |connection| {
+                let tx = connection.transaction()?;
+                let repository = SQLiteMetadataRepository::new(&tx);
+                scheduler.due_jobs_at(&repository, now)
+            })
+            .await?;
+        Ok(jobs
+            .into_iter()
+            .filter(|job| !running.contains(job))
+            .collect())
+    }
+
+    fn due_jobs_at(
+        &self,
+        repository: &impl MetadataRepository,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<Job>, Error> {
+        let mut jobs = Vec::new();
+        for job in Job::ALL {
+            let last_run = repository.get_job_run(job.name())?;
+            let next_run = self.schedule(job).next_run(last_run.as_ref(), self.started);
+            if next_run.is_some_and(|next_run| next_run <= now) {
+                jobs.push(job);
+            }
+        }
+        Ok(jobs)
+    }
+
+    pub async fn statuses(&self, database: &Database) -> Result<Vec<JobStatus>, Error> {
+        let last_runs = database
+            .read(|connection| {
+                let tx = connection.transaction()?;
+                let repository = SQLiteMetadataRepository::new(&tx);
+                let mut last_runs = Vec::new();
+                for job in Job::ALL {
+                    last_runs.push((job, repository.get_job_run(job.name())?));
+                }
+                Ok(last_runs)
+            })
+            .await?;
+
+        let running = self.running.lock().unwrap().clone();
+        Ok(last_runs
+            .into_iter()
+            .map(|(job, last_run)| {
+                let schedule = self.schedule(job);
+                JobStatus {
+                    job,
+                    schedule,
+                    next_run: schedule.next_run(last_run.as_ref(), self.started),
+                    last_run,
+                    running: running.contains(&job),
+                }
+            })
+            .collect())
+    }
+
+    /// Runs a job right away and records the run, with the error if it failed
+    pub async fn run(&self, job: Job, context: &JobContext) -> Result<(), Error> {
+        if !self.running.lock().unwrap().insert(job) {
+            return Err(format!("{} is already running", job.name()).into());
+        }
+        let time = context.clock.now();
+        println!("Running job {}...", job.name());
+        let result = run_job(job, context).await;
+        self.running.lock().unwrap().remove(&job);
+
+        let run = JobRun {
+            job: job.name().to_owned(),
+            time,
+            error: result.as_ref().err().map(|error| error.to_string()),
+        };
+        context
+            .database
+            .write(move |connection| {
+                let tx = connection.transaction()?;
+                SQLiteMetadataRepository::new(&tx).set_job_run(&run)?;
+                tx.commit()?;
+                Ok(())
+            })
+            .await?;
+        result
+    }
+}
+
+/// The server whose members are the active users, the bot can be in other servers as well
+pub fn configured_guild() -> Option<GuildId> {
+    var("GUILD_ID")
+        .ok()
+        .and_then(|id| id.parse().ok())
+        .map(GuildId::new)
+}
+
+async fn run_job(job: Job, context: &JobContext) -> Result<(), Error> {
+    match job {
+        Job::ActiveUsers => {
+            let guild_id = configured_guild().ok_or("GUILD_ID is not set.")?;
+            refresh_active_users(context, guild_id).await
+        }
+        Job::Backup => {
+            backup(&context.database, BACKUP_DIRECTORY, context.clock.now()).await?;
+            let deleted = prune_backups(BACKUP_DIRECTORY, BACKUPS_KEPT)?;
+            if deleted > 0 {
+                println!("Deleted {} old backups", deleted);
+            }
+            Ok(())
+        }
+        Job::Digest => post_digest(context).await,
+        Job::Reminders => send_reminders(context).await,
+    }
+}
+
+/// Reconciles the active status of every stored user with the guild's member list.
+/// The member events keep it up to date, this catches the joins and leaves missed while offline.
+async fn refresh_active_users(context: &JobContext, guild_id: GuildId) -> Result<(), Error> {
+    println!("Reloading active users...");
+    let members = match cached_members(&context.serenity, guild_id) {
+        Some(members) => members,
+        None => fetch_members(&context.serenity, guild_id).await?,
+    };
+
+    let now = context.clock.now();
+    context
+        .database
+        .write(move |connection| {
+            let tx = connection.transaction()?;
+            let mut repository = SQLiteCharacterStatisticsRepository::new(&tx);
+            let mut name_history_repository = SQLiteNameHistoryRepository::new(&tx);
+            service::refresh_active_users(
+                &mut repository,
+                &mut name_history_repository,
+                &members,
+                &now,
+            )?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await?;
+    println!("Done reloading active users");
+    Ok(())
+}
+
+/// The guild's members from the gateway cache, None if the cache doesn't hold all of them yet
+fn cached_members(ctx: &serenity::Context, guild_id: GuildId) -> Option<HashMap<u64, MemberNames>> {
+    let guild = guild_id.to_guild_cached(&ctx.cache)?;
+    if (guild.members.len() as u64) < guild.member_count {
+        return None;
+    }
+    Some(
+        guild
+            .members
+            .values()
+            .map(|member| (member.user.id.get(), MemberNames::of(member)))
+            .collect(),
+    )
+}
+
+async fn fetch_members(
+    ctx: &serenity::Context,
+    guild_id: GuildId,
+) -> Result<HashMap<u64, MemberNames>, Error> {
+    let mut after: Option<UserId> = None;
+    let mut members: HashMap<u64, MemberNames> = HashMap::with_capacity(2500);
+    loop {
+        let temp_members = guild_id.members(ctx, None, after).await?;
+        if temp_members.is_empty() {
+            break;
+        }
+        after = Some(temp_members.last().unwrap().user.id);
+        for m in temp_members.into_iter() {
+            members.insert(m.user.id.get(), MemberNames::of(&m));
+        }
+    }
+    Ok(members)
+}
+
+/// Writes a consistent copy of the database into `directory`, reads and writes continue meanwhile
+async fn backup(database: &Database, directory: &str, now: DateTime<Utc>) -> Result<(), Error> {
+    fs::create_dir_all(directory)?;
+    let file_name = format!("perdition-{}.db", now.format("%Y%m%d-%H%M%S"));
+    let path = Path::new(directory)
+        .join(file_name)
+        .to_str()
+        .ok_or("The backup directory isn't valid unicode.")?
+        .to_owned();
+    database
+        .read(move |connection| {
+            connection.execute("VACUUM INTO ?1", [&path])?;
+            println!("Backed up the database to {}", path);
+            Ok(())
+        })
+        .await
+}
+
+/// Deletes all but the newest `kept` backups in `directory`, returns how many were deleted
+fn prune_backups(directory: &str, kept: usize) -> Result<usize, Error> {
+    let mut backups = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        let is_backup = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with("perdition-") && name.ends_with(".db"));
+        if is_backup {
+            backups.push(path);
+        }
+    }
+    // the names sort by the time of the backup
+    backups.sort();
+    let deleted = backups.len().saturating_sub(kept);
+    for path in backups.iter().take(deleted) {
+        fs::remove_file(path)?;
+    }
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::{clock::FakeClock, memory_repository::MemoryMetadataRepository};
+
+    /// Sunday 2023-11-12 12:00:00 UTC
+    fn time(seconds: i64) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2023, 11, 12, 12, 0, 0).unwrap() + Duration::seconds(seconds)
+    }
+
+    /// A scheduler where the jobs that aren't given are off
+    fn scheduler(schedules: &[(Job, &str)]) -> Scheduler {
+        let mut all: HashMap<Job, Schedule> =
+            Job::ALL.iter().map(|job| (*job, Schedule::Off)).collect();
+        for (job, schedule) in schedules {
+            all.insert(*job, Schedule::parse(schedule).unwrap());
+        }
+        Scheduler {
+            schedules: Arc::new(all),
+            started: time(0),
+            running: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    #[test]
+    fn schedules_are_parsed_and_displayed() {
+        for schedule in [
+            "every 30m",
+            "every 6h",
+            "every 2d",
+            "daily 04:00",
+            "weekly sun 18:30",
+            "monthly 00:00",
+            "off",
+        ] {
+            assert_eq!(Schedule::parse(schedule).unwrap().to_string(), schedule);
+        }
+        assert_eq!(
+            Schedule::parse("every 24h").unwrap().to_string(),
+            "every 1d"
+        );
+        for invalid in [
+            "",
+            "every",
+            "every 0h",
+            "every 5s",
+            "daily 25:00",
+            "weekly 04:00",
+        ] {
+            assert_eq!(Schedule::parse(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn next_times() {
+        let daily = Schedule::parse("daily 04:00").unwrap();
+        assert_eq!(daily.next_after(time(0)), Some(time(16 * 3600)));
+        // exactly at the time means the next day
+        assert_eq!(daily.next_after(time(16 * 3600)), Some(time(40 * 3600)));
+
+        let weekly = Schedule::parse("weekly mon 00:00").unwrap();
+        assert_eq!(weekly.next_after(time(0)), Some(time(12 * 3600)));
+        let weekly = Schedule::parse("weekly sun 18:00").unwrap();
+        assert_eq!(weekly.next_after(time(0)), Some(time(6 * 3600)));
+        let weekly = Schedule::parse("weekly sun 06:00").unwrap();
+        assert_eq!(
+            weekly.next_after(time(0)),
+            Some(time(7 * 24 * 3600 - 6 * 3600))
+        );
+
+        let monthly = Schedule::parse("monthly 00:00").unwrap();
+        let december = Utc.with_ymd_and_hms(2023, 12, 1, 0, 0, 0).unwrap();
+        assert_eq!(monthly.next_after(time(0)), Some(december));
+        assert_eq!(
+            monthly.next_after(december),
+            Some(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap())
+        );
+
+        let every = Schedule::parse("every 2h").unwrap();
+        assert_eq!(every.next_after(time(0)), Some(time(2 * 3600)));
+        assert_eq!(Schedule::Off.next_after(time(0)), None);
+    }
+
+    #[test]
+    fn due_jobs() {
+        let scheduler = scheduler(&[(Job::ActiveUsers, "every 1d"), (Job::Backup, "daily 04:00")]);
+        let mut repository = MemoryMetadataRepository::new();
+        let clock = FakeClock::new(time(0));
+
+        // interval jobs that never ran are due right away, the others wait for their time
+        let due = scheduler.due_jobs_at(&repository, clock.now()).unwrap();
+        assert_eq!(due, vec![Job::ActiveUsers]);
+
+        repository
+            .set_job_run(&JobRun {
+                job: Job::ActiveUsers.name().to_owned(),
+                time: clock.now(),
+                error: Some("failed".to_owned()),
+            })
+            .unwrap();
+        // failed runs wait for the next time too
+        clock.advance(Duration::hours(16));
+        let due = scheduler.due_jobs_at(&repository, clock.now()).unwrap();
+        assert_eq!(due, vec![Job::Backup]);
+        clock.advance(Duration::hours(8));
+        let due = scheduler.due_jobs_at(&repository, clock.now()).unwrap();
+        assert_eq!(due, vec![Job::ActiveUsers, Job::Backup]);
+
+        let scheduler = self::scheduler(&[]);
+        clock.advance(Duration::days(365));
+        let due = scheduler.due_jobs_at(&repository, clock.now()).unwrap();
+        assert!(due.is_empty());
+    }
+
+    #[tokio::test]
+    async fn backups_are_copied_and_pruned() {
+        let directory =
+            std::env::temp_dir().join(format!("immersion-bot-backups-{}", std::process::id()));
+        let directory = directory.to_str().unwrap().to_owned();
+        let database = Database::open(&format!("{}.db", directory)).unwrap();
+
+        for hours in 0..3 {
+            backup(&database, &directory, time(hours * 3600))
+                .await
+                .unwrap();
+        }
+        assert_eq!(prune_backups(&directory, 2).unwrap(), 1);
+
+        let mut names: Vec<String> = fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            vec![
+                "perdition-20231112-130000.db",
+                "perdition-20231112-140000.db"
+            ]
+        );
+        // the copies are complete databases
+        let copy = rusqlite::Connection::open(Path::new(&directory).join(&names[0])).unwrap();
+        let tables: u64 = copy
+            .query_row(
+                "SELECT COUNT(*) FROM sqlite_master WHERE name = 'CharacterStatistics'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(tables, 1);
+
+        drop(database);
+        fs::remove_dir_all(&directory).unwrap();
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = fs::remove_file(format!("{}.db{}", directory, suffix));
+        }
+    }
+}
@@ -0,0 +1,584 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serenity::all::Timestamp;
+
+use crate::{
+    constants::{LEADERBOARD_PAGE_SIZE, LOG_ENTRY_PAGE_SIZE},
+    model::{CharacterLogEntry, CharacterStatistics, MemberNames, NameChange},
+    repository::{CharacterStatisticsRepository, NameHistoryRepository},
+    roles::{QuizRoles, RoleRequirement, Roles},
+    Error,
+};
+
+/// The outcome of adding a log entry
+#[derive(Debug)]
+pub struct LogResult {
+    pub statistics: CharacterStatistics,
+    pub previous_total: i64,
+    /// None if the log was empty and nothing was inserted
+    pub log_entry_id: Option<u64>,
+    /// None if the user isn't on the leaderboard, i.e. after logging their total back to 0
+    pub rank: Option<i32>,
+}
+
+impl LogResult {
+    /// How much the total changed, can be less than the logged characters when a negative log is clamped
+    pub fn delta(&self) -> i64 {
+        self.statistics.total_characters - self.previous_total
+    }
+}
+
+/// Where a member stands on the role ladder
+#[derive(Debug)]
+pub struct RoleProgress {
+    pub current_role: Option<Roles>,
+    pub next_requirement: Option<RoleRequirement>,
+    pub total_characters: i64,
+}
+
+impl RoleProgress {
+    pub fn new(quiz_roles: &[QuizRoles], total_characters: i64) -> RoleProgress {
+        RoleProgress {
+            current_role: Roles::from_characters_and_quiz_roles(quiz_roles, total_characters),
+            next_requirement: Roles::next_role_requirement(quiz_roles, total_characters),
+            total_characters,
+        }
+    }
+
+    /// The characters still needed for the next role, None if only a quiz is missing or there is no next role
+    pub fn missing_characters(&self) -> Option<i64> {
+        self.next_requirement
+            .as_ref()
+            .map(|requirement| requirement.characters - self.total_characters)
+            .filter(|missing| *missing > 0)
+    }
+
+    /// The quiz needed for the next role once the characters are there
+    pub fn missing_quiz(&self) -> Option<QuizRoles> {
+        match self.missing_characters() {
+            Some(_) => None,
+            None => self.next_requirement.as_ref()?.quiz_role,
+        }
+    }
+}
+
+/// Whether a member got a role higher than all roles they had before, which is worth congratulating
+pub fn is_promotion(new_role: Roles, previous_roles: &[Roles]) -> bool {
+    previous_roles.iter().all(|role| &new_role > role)
+}
+
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub page: u64,
+    /// at least 1, so an empty list still has a page to show
+    pub total_pages: u64,
+}
+
+pub struct LeaderboardPage {
+    pub users: Vec<CharacterStatistics>,
+    pub page: u64,
+    pub total_pages: u64,
+    pub users_count: u64,
+    /// the statistics of the member who looks at the leaderboard
+    pub statistics: CharacterStatistics,
+    pub rank: Option<i32>,
+}
+
+/// Adds a log entry and returns the new total and rank of the user
+pub fn log_characters(
+    repository: &mut impl CharacterStatisticsRepository,
+    user_id: u64,
+    name: &str,
+    characters: i64,
+    time: &DateTime<Utc>,
+    notes: Option<String>,
+) -> Result<LogResult, Error> {
+    let previous_total = repository
+        .get_statistics(user_id)?
+        .map_or(0, |statistics| statistics.total_characters);
+    let (statistics, log_entry_id) =
+        repository.add_log_entry_with_id(user_id, name, characters, time, notes)?;
+    let rank = repository.get_rank(&statistics)?;
+
+    Ok(LogResult {
+        statistics,
+        previous_total,
+        log_entry_id,
+        rank,
+    })
+}
+
+/// Returns the statistics and rank of a user, None if they never logged
+pub fn rank(
+    repository: &mut impl CharacterStatisticsRepository,
+    user_id: u64,
+) -> Result<Option<(CharacterStatistics, Option<i32>)>, Error> {
+    let Some(statistics) = repository.get_statistics(user_id)? else {
+        return Ok(None);
+    };
+    let rank = repository.get_rank(&statistics)?;
+    Ok(Some((statistics, rank)))
+}
+
+/// The leaderboard page a rank is on, counting pages from 0
+pub fn leaderboard_page_of_rank(rank: i32) -> u64 {
+    (rank.max(1) as u64 - 1) / LEADERBOARD_PAGE_SIZE
+}
+
+pub fn total_leaderboard_pages(
+    repository: &mut impl CharacterStatisticsRepository,
+) -> Result<u64, Error> {
+    let users_count = repository.get_total_active_users()?;
+    Ok(users_count.div_ceil(LEADERBOARD_PAGE_SIZE).max(1))
+}
+
+pub fn leaderboard_page(
+    repository: &mut impl CharacterStatisticsRepository,
+    user_id: u64,
+    name: &str,
+    page: u64,
+) -> Result<LeaderboardPage, Error> {
+    let users = repository.get_paginated_active_users_by_characters(page)?;
+    let statistics = repository.get_or_initialize_statistics(user_id, name)?;
+    let rank = repository.get_rank(&statistics)?;
+    let users_count = repository.get_total_active_users()?;
+
+    Ok(LeaderboardPage {
+        users,
+        page,
+        total_pages: users_count.div_ceil(LEADERBOARD_PAGE_SIZE).max(1),
+        users_count,
+        statistics,
+        rank,
+    })
+}
+
+pub fn total_history_pages(
+    repository: &mut impl CharacterStatisticsRepository,
+    user_id: u64,
+) -> Result<u64, Error> {
+    let entries = repository.get_total_log_entries(user_id)?;
+    Ok(entries.div_ceil(LOG_ENTRY_PAGE_SIZE).max(1))
+}
+
+/// Returns a page of a user's log entries, newest first
+pub fn history_page(
+    repository: &mut impl CharacterStatisticsRepository,
+    user_id: u64,
+    page: u64,
+) -> Result<Page<CharacterLogEntry>, Error> {
+    let items = repository.get_paginated_log_entries_by_time(user_id, page)?;
+    let total_pages = total_history_pages(repository, user_id)?;
+    Ok(Page {
+        items,
+        page,
+        total_pages,
+    })
+}
+
+/// Marks a member who is on the server as active with their latest names, i.e. after they joined or used a command.
+/// Returns false if they never logged, nothing is stored for them then.
+pub fn member_seen(
+    repository: &mut impl CharacterStatisticsRepository,
+    name_history_repository: &mut impl NameHistoryRepository,
+    user_id: u64,
+    names: &MemberNames,
+    now: &DateTime<Utc>,
+) -> Result<bool, Error> {
+    if !repository.exists(user_id)? {
+        return Ok(false);
+    }
+    repository.set_active_status(user_id, true, None)?;
+    update_names(repository, name_history_repository, user_id, names, now)?;
+    repository.set_last_seen(user_id, now)?;
+    Ok(true)
+}
+
+/// Marks a member who left the server as inactive, which hides them from the leaderboard
+pub fn member_left(
+    repository: &mut impl CharacterStatisticsRepository,
+    user_id: u64,
+    name: &str,
+    now: &DateTime<Utc>,
+) -> Result<(), Error> {
+    repository.set_active_status(user_id, false, Some(name))?;
+    repository.set_last_seen(user_id, now)?;
+    Ok(())
+}
+
+/// Stores the latest names of a user and adds them to the name history if they differ from the last recorded ones.
+/// Returns whether they were added, users who never logged are ignored.
+pub fn update_names(
+    repository: &mut impl CharacterStatisticsRepository,
+    name_history_repository: &mut impl NameHistoryRepository,
+    user_id: u64,
+    names: &MemberNames,
+    now: &DateTime<Utc>,
+) -> Result<bool, Error> {
+    if !repository.exists(user_id)? {
+        return Ok(false);
+    }
+    repository.set_names(user_id, names)?;
+    if name_history_repository.get_latest_names(user_id)?.as_ref() == Some(names) {
+        return Ok(false);
+    }
+    name_history_repository.add_name_change(&NameChange {
+        user_id,
+        names: names.clone(),
+        time: Timestamp::from(*now),
+    })?;
+    Ok(true)
+}
+
+/// Marks the users who are in `members` as active with their latest names and everyone else as inactive
+pub fn refresh_active_users(
+    repository: &mut impl CharacterStatisticsRepository,
+    name_history_repository: &mut impl NameHistoryRepository,
+    members: &HashMap<u64, MemberNames>,
+    now: &DateTime<Utc>,
+) -> Result<(), Error> {
+    let mut page_number = 0;
+    loop {
+        let users = repository.get_paginated_users_by_id(page_number)?;
+        if users.is_empty() {
+            break;
+        }
+        for u in users.iter() {
+            let user_id = u.get_user_id();
+            match members.get(&user_id) {
+                Some(names) => {
+                    repository.set_active_status(user_id, true, None)?;
+                    update_names(repository, name_history_repository, user_id, names, now)?;
+                    repository.set_last_seen(user_id, now)?;
+                }
+                None => repository.set_active_status(user_id, false, None)?,
+            }
+        }
+        page_number += 1;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::memory_repository::{
+        MemoryCharacterStatisticsRepository, MemoryNameHistoryRepository,
+    };
+
+    fn time(seconds: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(1_700_000_000 + seconds, 0).unwrap()
+    }
+
+    fn log(repository: &mut MemoryCharacterStatisticsRepository, user_id: u64, characters: i64) {
+        log_characters(repository, user_id, "user", characters, &time(0), None).unwrap();
+    }
+
+    #[test]
+    fn logging_adds_to_the_total() {
+        let mut repository = MemoryCharacterStatisticsRepository::new();
+        log(&mut repository, 1, 1_000);
+        let result = log_characters(&mut repository, 1, "user", 500, &time(1), None).unwrap();
+
+        assert_eq!(result.previous_total, 1_000);
+        assert_eq!(result.statistics.total_characters, 1_500);
+        assert_eq!(result.delta(), 500);
+        assert_eq!(result.rank, Some(1));
+        assert!(result.log_entry_id.is_some());
+    }
+
+    #[test]
+    fn negative_logs_are_clamped_to_the_total() {
+        let mut repository = MemoryCharacterStatisticsRepository::new();
+        log(&mut repository, 1, 1_000);
+        let result = log_characters(&mut repository, 1, "user", -5_000, &time(1), None).unwrap();
+
+        assert_eq!(result.statistics.total_characters, 0);
+        assert_eq!(result.delta(), -1_000);
+        // nobody with 0 characters is on the leaderboard
+        assert_eq!(result.rank, None);
+    }
+
+    #[test]
+    fn empty_logs_are_not_inserted() {
+        let mut repository = MemoryCharacterStatisticsRepository::new();
+        let result = log_characters(&mut repository, 1, "user", 0, &time(0), None).unwrap();
+        assert_eq!(result.log_entry_id, None);
+
+        let result = log_characters(
+            &mut repository,
+            1,
+            "user",
+            0,
+            &time(0),
+            Some(" ".to_owned()),
+        )
+        .unwrap();
+        assert_eq!(result.log_entry_id, None);
+
+        let result = log_characters(
+            &mut repository,
+            1,
+            "user",
+            0,
+            &time(0),
+            Some("note".to_owned()),
+        )
+        .unwrap();
+        assert!(result.log_entry_id.is_some());
+        assert_eq!(repository.get_total_log_entries(1).unwrap(), 1);
+    }
+
+    #[test]
+    fn overflowing_totals_are_rejected() {
+        let mut repository = MemoryCharacterStatisticsRepository::new();
+        log(&mut repository, 1, i64::MAX);
+        assert!(log_characters(&mut repository, 1, "user", 1, &time(0), None).is_err());
+    }
+
+    #[test]
+    fn ranks_by_total_then_user_id() {
+        let mut repository = MemoryCharacterStatisticsRepository::new();
+        log(&mut repository, 3, 100);
+        log(&mut repository, 2, 500);
+        log(&mut repository, 1, 100);
+
+        let ranks: Vec<Option<i32>> = [2, 1, 3]
+            .iter()
+            .map(|user_id| rank(&mut repository, *user_id).unwrap().unwrap().1)
+            .collect();
+        assert_eq!(ranks, vec![Some(1), Some(2), Some(3)]);
+        assert!(rank(&mut repository, 4).unwrap().is_none());
+    }
+
+    #[test]
+    fn inactive_users_are_not_ranked() {
+        let mut repository = MemoryCharacterStatisticsRepository::new();
+        log(&mut repository, 1, 500);
+        log(&mut repository, 2, 100);
+        repository.set_active_status(1, false, None).unwrap();
+
+        assert_eq!(rank(&mut repository, 1).unwrap().unwrap().1, None);
+        assert_eq!(rank(&mut repository, 2).unwrap().unwrap().1, Some(1));
+        assert_eq!(total_leaderboard_pages(&mut repository).unwrap(), 1);
+    }
+
+    #[test]
+    fn leaderboard_pages() {
+        let mut repository = MemoryCharacterStatisticsRepository::new();
+        let users = LEADERBOARD_PAGE_SIZE + 1;
+        for user_id in 1..=users {
+            log(&mut repository, user_id, user_id as i64 * 10);
+        }
+
+        assert_eq!(total_leaderboard_pages(&mut repository).unwrap(), 2);
+        let second_page = leaderboard_page(&mut repository, 1, "user", 1).unwrap();
+        assert_eq!(second_page.users.len(), 1);
+        assert_eq!(second_page.users[0].get_user_id(), 1);
+        assert_eq!(second_page.users_count, users);
+        assert_eq!(second_page.rank, Some(users as i32));
+        assert_eq!(leaderboard_page_of_rank(users as i32), 1);
+        assert_eq!(leaderboard_page_of_rank(1), 0);
+    }
+
+    #[test]
+    fn leaderboard_for_an_unranked_user() {
+        let mut repository = MemoryCharacterStatisticsRepository::new();
+        let page = leaderboard_page(&mut repository, 1, "user", 0).unwrap();
+
+        assert!(page.users.is_empty());
+        assert_eq!(page.total_pages, 1);
+        assert_eq!(page.statistics.total_characters, 0);
+        assert_eq!(page.rank, None);
+    }
+
+    #[test]
+    fn history_pages_are_newest_first() {
+        let mut repository = MemoryCharacterStatisticsRepository::new();
+        assert_eq!(total_history_pages(&mut repository, 1).unwrap(), 1);
+
+        let entries = LOG_ENTRY_PAGE_SIZE as i64 + 2;
+        for i in 1..=entries {
+            log_characters(&mut repository, 1, "user", i, &time(i), None).unwrap();
+        }
+
+        let first_page = history_page(&mut repository, 1, 0).unwrap();
+        assert_eq!(first_page.total_pages, 2);
+        assert_eq!(first_page.items.len(), LOG_ENTRY_PAGE_SIZE as usize);
+        assert_eq!(first_page.items[0].characters(), entries);
+
+        let second_page = history_page(&mut repository, 1, 1).unwrap();
+        let characters: Vec<i64> = second_page.items.iter().map(|e| e.characters()).collect();
+        assert_eq!(characters, vec![2, 1]);
+    }
+
+    #[test]
+    fn role_progress_needs_characters() {
+        let progress = RoleProgress::new(&[], 50_000);
+        assert_eq!(progress.current_role, None);
+        assert_eq!(progress.missing_characters(), Some(50_000));
+        assert_eq!(progress.missing_quiz(), None);
+
+        let progress = RoleProgress::new(&[], 100_000);
+        assert_eq!(progress.current_role, Some(Roles::Heimin));
+        assert_eq!(progress.missing_characters(), Some(400_000));
+    }
+
+    #[test]
+    fn role_progress_is_gated_by_quizzes() {
+        let progress = RoleProgress::new(&[], 600_000);
+        assert_eq!(progress.current_role, Some(Roles::Heimin));
+        assert_eq!(progress.missing_characters(), None);
+        assert_eq!(progress.missing_quiz(), Some(QuizRoles::Quiz1));
+
+        let progress = RoleProgress::new(&[QuizRoles::Quiz1], 600_000);
+        assert_eq!(progress.current_role, Some(Roles::Danshaku));
+        assert_eq!(progress.missing_characters(), Some(400_000));
+        assert_eq!(progress.missing_quiz(), None);
+    }
+
+    #[test]
+    fn promotions() {
+        assert!(is_promotion(Roles::Heimin, &[]));
+        assert!(is_promotion(Roles::Danshaku, &[Roles::Heimin]));
+        assert!(!is_promotion(Roles::Heimin, &[Roles::Danshaku]));
+        assert!(!is_promotion(Roles::Heimin, &[Roles::Heimin]));
+    }
+
+    #[test]
+    fn refreshing_active_users() {
+        let mut repository = MemoryCharacterStatisticsRepository::new();
+        let mut name_history_repository = MemoryNameHistoryRepository::new();
+        let users = LEADERBOARD_PAGE_SIZE + 1;
+        for user_id in 1..=users {
+            log(&mut repository, user_id, 100);
+        }
+
+        // only the first user is still on the server, with a new name
+        let members = HashMap::from([(1, MemberNames::new("renamed", None))]);
+        refresh_active_users(
+            &mut repository,
+            &mut name_history_repository,
+            &members,
+            &time(0),
+        )
+        .unwrap();
+
+        assert_eq!(repository.get_total_active_users().unwrap(), 1);
+        let statistics = repository.get_statistics(1).unwrap().unwrap();
+        assert_eq!(statistics.name, "renamed");
+        // users on the last page are refreshed too
+        assert_eq!(rank(&mut repository, users).unwrap().unwrap().1, None);
+    }
+
+    #[test]
+    fn members_seen_and_leaving() {
+        let mut repository = MemoryCharacterStatisticsRepository::new();
+        let mut name_history_repository = MemoryNameHistoryRepository::new();
+        let names = MemberNames::new("renamed", None);
+        // members who never logged aren't stored
+        assert!(!member_seen(
+            &mut repository,
+            &mut name_history_repository,
+            1,
+            &names,
+            &time(0)
+        )
+        .unwrap());
+        assert!(!repository.exists(1).unwrap());
+
+        log(&mut repository, 1, 100);
+        member_left(&mut repository, 1, "user", &time(1)).unwrap();
+        assert_eq!(repository.get_total_active_users().unwrap(), 0);
+        assert_eq!(repository.last_seen(1), Some(time(1)));
+
+        assert!(member_seen(
+            &mut repository,
+            &mut name_history_repository,
+            1,
+            &names,
+            &time(2)
+        )
+        .unwrap());
+        assert_eq!(repository.get_total_active_users().unwrap(), 1);
+        assert_eq!(
+            repository.get_statistics(1).unwrap().unwrap().name,
+            "renamed"
+        );
+        assert_eq!(repository.last_seen(1), Some(time(2)));
+    }
+
+    #[test]
+    fn refreshing_only_marks_members_as_seen() {
+        let mut repository = MemoryCharacterStatisticsRepository::new();
+        let mut name_history_repository = MemoryNameHistoryRepository::new();
+        log(&mut repository, 1, 100);
+        log(&mut repository, 2, 100);
+
+        let members = HashMap::from([(1, MemberNames::new("user", None))]);
+        refresh_active_users(
+            &mut repository,
+            &mut name_history_repository,
+            &members,
+            &time(5),
+        )
+        .unwrap();
+
+        assert_eq!(repository.last_seen(1), Some(time(5)));
+        assert_eq!(repository.last_seen(2), None);
+    }
+
+    #[test]
+    fn name_changes_are_recorded_once() {
+        let mut repository = MemoryCharacterStatisticsRepository::new();
+        let mut name_history_repository = MemoryNameHistoryRepository::new();
+        // unknown users have no history
+        let names = MemberNames::new("user", None);
+        assert!(!update_names(
+            &mut repository,
+            &mut name_history_repository,
+            1,
+            &names,
+            &time(0)
+        )
+        .unwrap());
+
+        log(&mut repository, 1, 100);
+        let mut update = |names: &MemberNames, seconds: i64| {
+            update_names(
+                &mut repository,
+                &mut name_history_repository,
+                1,
+                names,
+                &time(seconds),
+            )
+            .unwrap()
+        };
+        let nicknamed = MemberNames::new("user", Some("nickname"));
+        assert!(update(&names, 1));
+        assert!(update(&nicknamed, 2));
+        assert!(!update(&nicknamed, 3));
+
+        // the leaderboard shows the nickname, the stored name stays the global one
+        assert_eq!(repository.get_statistics(1).unwrap().unwrap().name, "user");
+        let leaderboard = repository
+            .get_paginated_active_users_by_characters(0)
+            .unwrap();
+        assert_eq!(leaderboard[0].name, "nickname");
+
+        let history = name_history_repository
+            .get_paginated_name_changes(Some(1), None, 0)
+            .unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].names, nicknamed);
+        assert_eq!(
+            name_history_repository
+                .get_total_name_changes(None, Some("NICK"))
+                .unwrap(),
+            1
+        );
+    }
+}
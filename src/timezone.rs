@@ -0,0 +1,114 @@
+use chrono::{DateTime, Duration, NaiveDateTime, Offset, TimeZone, Utc};
+use chrono_tz::{Tz, TZ_VARIANTS};
+
+/// An IANA timezone from the tz database that chrono-tz is built with, i.e. Asia/Tokyo
+#[derive(Clone, Debug, PartialEq)]
+pub struct Timezone(Tz);
+
+impl Timezone {
+    pub fn utc() -> Timezone {
+        Timezone(Tz::UTC)
+    }
+
+    /// Loads a timezone by its IANA name, matched case-insensitively. None if it doesn't exist.
+    pub fn load(name: &str) -> Option<Timezone> {
+        let name = name.trim();
+        let timezone = match name.parse::<Tz>() {
+            Ok(timezone) => timezone,
+            Err(_) => *TZ_VARIANTS
+                .iter()
+                .find(|known| known.name().eq_ignore_ascii_case(name))?,
+        };
+        Some(Timezone(timezone))
+    }
+
+    pub fn name(&self) -> &str {
+        self.0.name()
+    }
+
+    /// The local time of a moment in this timezone
+    pub fn to_local(&self, time: DateTime<Utc>) -> DateTime<Tz> {
+        time.with_timezone(&self.0)
+    }
+
+    /// The moment a local time refers to. When the clocks are turned back, the earlier of the two moments.
+    /// When they skip ahead, a skipped time is moved forward by the skipped amount.
+    pub fn local_to_utc(&self, local: NaiveDateTime) -> DateTime<Utc> {
+        match self.0.from_local_datetime(&local).earliest() {
+            Some(time) => time.to_utc(),
+            None => {
+                // the offset from before the clocks skipped ahead
+                let before = self
+                    .0
+                    .offset_from_utc_datetime(&(local - Duration::days(1)))
+                    .fix();
+                local.and_utc() - Duration::seconds(before.local_minus_utc().into())
+            }
+        }
+    }
+
+    /// Midnight of the local day a moment falls on
+    pub fn start_of_day(&self, time: DateTime<Utc>) -> DateTime<Utc> {
+        let date = self.to_local(time).date_naive();
+        self.local_to_utc(date.and_time(chrono::NaiveTime::MIN))
+    }
+}
+
+/// The names of the timezones in the tz database, sorted, for autocompletion
+pub fn timezone_names() -> Vec<String> {
+    let mut names: Vec<String> = TZ_VARIANTS
+        .iter()
+        .map(|timezone| timezone.name().to_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, NaiveTime};
+
+    use super::*;
+
+    fn utc(month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2025, month, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    fn local(month: u32, day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        utc(month, day, hour, minute).naive_utc()
+    }
+
+    #[test]
+    fn names_are_matched_case_insensitively() {
+        assert_eq!(Timezone::load("utc"), Some(Timezone::utc()));
+        assert_eq!(Timezone::load(" asia/tokyo ").unwrap().name(), "Asia/Tokyo");
+        assert!(Timezone::load("Asia/Atlantis").is_none());
+        assert!(Timezone::load("../etc/passwd").is_none());
+        assert!(timezone_names().contains(&"America/New_York".to_owned()));
+    }
+
+    #[test]
+    fn local_times_around_transitions() {
+        // DST from March 9th to November 2nd in 2025
+        let new_york = Timezone::load("America/New_York").unwrap();
+        assert_eq!(new_york.local_to_utc(local(6, 1, 12, 0)), utc(6, 1, 16, 0));
+        // 02:30 was skipped, it's treated like 03:30
+        assert_eq!(new_york.local_to_utc(local(3, 9, 2, 30)), utc(3, 9, 7, 30));
+        // 01:30 happened twice, the first one counts
+        assert_eq!(
+            new_york.local_to_utc(local(11, 2, 1, 30)),
+            utc(11, 2, 5, 30)
+        );
+
+        assert_eq!(new_york.start_of_day(utc(6, 2, 3, 0)), utc(6, 1, 4, 0));
+        assert_eq!(
+            new_york.to_local(utc(6, 2, 3, 0)).date_naive(),
+            NaiveDate::from_ymd_opt(2025, 6, 1).unwrap()
+        );
+        assert_eq!(
+            new_york.to_local(utc(6, 2, 3, 0)).time(),
+            NaiveTime::from_hms_opt(23, 0, 0).unwrap()
+        );
+    }
+}
@@ -1,20 +1,23 @@
-pub fn format_with_commas(num: i32) -> String {
+use chrono::Duration;
+
+use crate::{constants::MAX_CHARACTERS_PER_ENTRY, Error};
+
+pub fn format_with_commas(num: i64) -> String {
     // if negative, remove the negative mark and process it as a positive number
     let (mut str, is_negative) = match num < 0 {
-        true => (num.abs().to_string(), true),
+        true => (num.unsigned_abs().to_string(), true),
         false => (num.to_string(), false),
     };
     let len = str.len();
 
     if len > 3 {
-        if num < 0 {}
         let mut result = String::new();
         let mut count = 0;
 
         for c in str.chars().rev() {
             if count == 3 {
                 result.push(',');
-                count = 0;  
+                count = 0;
             }
             result.push(c);
             count += 1;
@@ -32,3 +35,54 @@ pub fn format_with_commas(num: i32) -> String {
         str
     }
 }
+
+/// Shortens a text to at most max_chars characters, ending it with an ellipsis when something was cut
+pub fn truncate_chars(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_owned();
+    }
+    let mut truncated: String = text.chars().take(max_chars.saturating_sub(1)).collect();
+    truncated.push('…');
+    truncated
+}
+
+/// Formats a duration as hours and minutes, i.e. "2 hours 5 minutes", rounding seconds up to the next minute
+pub fn format_duration(duration: &Duration) -> String {
+    let total_minutes = (duration.num_seconds() + 59).div_euclid(60).max(0);
+    let hours = total_minutes / 60;
+    let minutes = total_minutes % 60;
+
+    let plural = |n: i64, unit: &str| match n {
+        1 => format!("1 {unit}"),
+        n => format!("{n} {unit}s"),
+    };
+
+    match (hours, minutes) {
+        (0, minutes) => plural(minutes, "minute"),
+        (hours, 0) => plural(hours, "hour"),
+        (hours, minutes) => format!("{} {}", plural(hours, "hour"), plural(minutes, "minute")),
+    }
+}
+
+/// 64-bit FNV-1a, used to recognize files and sessions that were already imported.
+/// std's hasher isn't stable between Rust versions, so it can't be stored in the db.
+pub fn fnv1a(text: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in text.as_bytes() {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Rejects log entries above MAX_CHARACTERS_PER_ENTRY, in both directions
+pub fn check_entry_characters(characters: i64) -> Result<(), Error> {
+    if characters.unsigned_abs() > MAX_CHARACTERS_PER_ENTRY as u64 {
+        return Err(format!(
+            "A single log can't be more than {} characters.",
+            format_with_commas(MAX_CHARACTERS_PER_ENTRY)
+        )
+        .into());
+    }
+    Ok(())
+}